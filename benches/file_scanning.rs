@@ -1,5 +1,4 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use std::path::Path;
 use tempfile::tempdir;
 use std::fs::{self, File};
 use std::io::Write;
@@ -55,7 +54,7 @@ fn bench_scan_directory(c: &mut Criterion) {
         let dir = create_test_directory(*depth, *files_per_dir, *file_size);
         
         group.bench_function(
-            &format!("scan_directory - {} (depth: {}, files/dir: {}, size: {}B)", 
+            format!("scan_directory - {} (depth: {}, files/dir: {}, size: {}B)", 
                     name, depth, files_per_dir, file_size),
             |b| {
                 b.iter(|| {
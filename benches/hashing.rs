@@ -7,7 +7,7 @@ use dedcore::hashing::{hash_bytes, HashKind};
 
 fn generate_random_data(size: usize) -> Vec<u8> {
     let mut data = vec![0u8; size];
-    let mut rng = rand::thread_rng();
+    let mut rng = rand::rng();
     rng.fill_bytes(&mut data);
     data
 }
@@ -30,7 +30,7 @@ fn bench_hashing(c: &mut Criterion) {
             let id = format!("{} - {}B", hash_kind, size);
             group.bench_function(&id, |b| {
                 b.iter(|| {
-                    let _ = hash_bytes(&data, hash_kind.clone());
+                    let _ = hash_bytes(&data, hash_kind);
                 })
             });
         }
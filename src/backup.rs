@@ -0,0 +1,220 @@
+//! Checking that a backup holds a copy of every source file.
+//!
+//! [`verify_backup`] hashes both trees, going through the incremental hash
+//! cache so a second check of an unchanged backup reads nothing. A source file
+//! is safe when any backup file has its content, wherever it sits; otherwise
+//! it is modified when the backup has a different file at the same relative
+//! path, and missing when it has none.
+
+use crate::cache::{mtime_secs, HashCache};
+use crate::hashing::{hash_file, HashKind};
+use crate::scanner::FileError;
+use crate::walker::{collect_roots, resolve_targets, FileFilter, ScanRoot};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A file with the digest of its content.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupEntry {
+    #[serde(with = "crate::paths")]
+    pub file: PathBuf,
+    pub hash: String,
+    pub size: u64,
+}
+
+/// A source file whose backup copy at the same relative path has other content.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModifiedFile {
+    pub source: BackupEntry,
+    pub backup: BackupEntry,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BackupCheck {
+    pub source: Option<ScanRoot>,
+    pub backup: Option<ScanRoot>,
+    /// Source files with a content-identical copy somewhere in the backup.
+    pub verified: usize,
+    /// Source files with no copy of their content, and nothing at their path.
+    pub missing: Vec<BackupEntry>,
+    /// Source files with no copy of their content, but another version at their path.
+    pub modified: Vec<ModifiedFile>,
+    /// Backup files whose content is nowhere in the source.
+    pub extra: Vec<BackupEntry>,
+    /// Files that could not be read; a source file in here was not verified.
+    pub errors: Vec<FileError>,
+    /// Digest algorithm used on both sides.
+    pub algorithm: String,
+    pub files_hashed: usize,
+    pub cache_hits: usize,
+}
+
+impl BackupCheck {
+    /// Whether every source file was read and found in the backup.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty() && self.modified.is_empty() && self.errors.is_empty()
+    }
+}
+
+/// `file` relative to `root`, or its name when the root is the file itself.
+fn relative_to(root: &ScanRoot, file: &Path) -> PathBuf {
+    match file.strip_prefix(&root.path) {
+        Ok(rel) if !rel.as_os_str().is_empty() => rel.to_path_buf(),
+        _ => file.file_name().map(PathBuf::from).unwrap_or_default(),
+    }
+}
+
+/// Verify that `backup` holds the content of every file under `source`.
+///
+/// With a `cache_path`, digests of unchanged files are taken from the cache and
+/// new ones are added to it. Fails when either side doesn't exist.
+pub fn verify_backup(
+    source: PathBuf,
+    backup: PathBuf,
+    filter: &FileFilter,
+    algo: HashKind,
+    cache_path: Option<&Path>,
+) -> Result<BackupCheck, String> {
+    let (mut roots, missing) = resolve_targets(&[source.clone(), backup.clone()]);
+    if let Some(target) = missing.first() {
+        return Err(format!("{}: No such file or directory", target.display()));
+    }
+    if roots.len() == 1 {
+        return Err(format!("{} and {} are the same", source.display(), backup.display()));
+    }
+    let backup_root = roots.pop().unwrap();
+    let source_root = roots.pop().unwrap();
+
+    let mut cache = cache_path.map(|p| {
+        let mut cache = HashCache::load(p);
+        cache.clean();
+        cache
+    });
+    let mut check = BackupCheck { algorithm: algo.to_string(), ..Default::default() };
+    let source_files = hash_with_cache(collect_roots(std::slice::from_ref(&source_root), filter), algo, &mut cache, &mut check);
+    let backup_files = hash_with_cache(collect_roots(std::slice::from_ref(&backup_root), filter), algo, &mut cache, &mut check);
+    if let Some(cache) = cache {
+        // The cache only speeds up the next check, losing it is not an error
+        let _ = cache.save();
+    }
+
+    let backup_hashes: HashSet<&str> = backup_files.iter().map(|e| e.hash.as_str()).collect();
+    let source_hashes: HashSet<&str> = source_files.iter().map(|e| e.hash.as_str()).collect();
+    let backup_by_path: HashMap<PathBuf, &BackupEntry> =
+        backup_files.iter().map(|e| (relative_to(&backup_root, &e.file), e)).collect();
+    let mut superseded: HashSet<&Path> = HashSet::new();
+    for entry in &source_files {
+        if backup_hashes.contains(entry.hash.as_str()) {
+            check.verified += 1;
+            continue;
+        }
+        match backup_by_path.get(&relative_to(&source_root, &entry.file)) {
+            Some(other) => {
+                superseded.insert(other.file.as_path());
+                check.modified.push(ModifiedFile { source: entry.clone(), backup: (*other).clone() });
+            }
+            None => check.missing.push(entry.clone()),
+        }
+    }
+    // The other version of a modified file is already reported with it
+    check.extra = backup_files
+        .iter()
+        .filter(|e| !source_hashes.contains(e.hash.as_str()) && !superseded.contains(e.file.as_path()))
+        .cloned()
+        .collect();
+    check.errors.sort_by(|a, b| a.file.cmp(&b.file));
+    check.source = Some(source_root);
+    check.backup = Some(backup_root);
+    Ok(check)
+}
+
+/// Digest every file, reusing and updating the cache. Failures go to `check.errors`.
+fn hash_with_cache(
+    files: Vec<PathBuf>,
+    algo: HashKind,
+    cache: &mut Option<HashCache>,
+    check: &mut BackupCheck,
+) -> Vec<BackupEntry> {
+    let results: Vec<_> = files
+        .into_par_iter()
+        .map(|file| {
+            let meta = match fs::metadata(&file) {
+                Ok(meta) => meta,
+                Err(e) => return Err(FileError { file, reason: e.to_string() }),
+            };
+            let (size, mtime) = (meta.len(), mtime_secs(&meta));
+            let cached = cache
+                .as_ref()
+                .and_then(|cache| cache.get(&file, size, mtime, algo))
+                .and_then(|e| e.hash.clone());
+            match cached {
+                Some(hash) => Ok((file, size, mtime, hash, true)),
+                None => match hash_file(&file, algo) {
+                    Ok(hash) => Ok((file, size, mtime, hash, false)),
+                    Err(e) => Err(FileError { file, reason: e.to_string() }),
+                },
+            }
+        })
+        .collect();
+    let mut entries = Vec::new();
+    for result in results {
+        match result {
+            Ok((file, size, mtime, hash, cached)) => {
+                if cached {
+                    check.cache_hits += 1;
+                } else {
+                    check.files_hashed += 1;
+                    if let Some(cache) = cache.as_mut() {
+                        cache.update(&file, size, mtime, algo, None, Some(hash.clone()));
+                    }
+                }
+                entries.push(BackupEntry { file, hash: hex::encode(hash), size });
+            }
+            Err(e) => check.errors.push(e),
+        }
+    }
+    entries.sort_by(|a, b| a.file.cmp(&b.file));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_verify_backup_classifies_files() {
+        let dir = tempdir().unwrap();
+        let (source, backup) = (dir.path().join("src"), dir.path().join("bak"));
+        fs::create_dir_all(source.join("docs")).unwrap();
+        fs::create_dir_all(backup.join("moved")).unwrap();
+        fs::write(source.join("docs/a.txt"), "kept").unwrap();
+        fs::write(backup.join("moved/a-copy.txt"), "kept").unwrap();
+        fs::write(source.join("b.txt"), "new version").unwrap();
+        fs::write(backup.join("b.txt"), "old version").unwrap();
+        fs::write(source.join("c.txt"), "never backed up").unwrap();
+        fs::write(backup.join("stale.txt"), "deleted from source").unwrap();
+        let cache = dir.path().join("cache.json");
+
+        let check =
+            verify_backup(source.clone(), backup.clone(), &FileFilter::default(), HashKind::Blake3, Some(&cache))
+                .unwrap();
+        assert_eq!(check.verified, 1);
+        assert_eq!(check.missing.len(), 1);
+        assert!(check.missing[0].file.ends_with("c.txt"));
+        assert_eq!(check.modified.len(), 1);
+        assert!(check.modified[0].backup.file.ends_with("bak/b.txt"));
+        assert_eq!(check.extra.len(), 1);
+        assert!(check.extra[0].file.ends_with("stale.txt"));
+        assert!(!check.is_complete());
+        assert_eq!(check.files_hashed, 6);
+
+        // Nothing changed, so nothing is read again
+        let check = verify_backup(source, backup, &FileFilter::default(), HashKind::Blake3, Some(&cache)).unwrap();
+        assert_eq!(check.files_hashed, 0);
+        assert_eq!(check.cache_hits, 6);
+    }
+}
@@ -0,0 +1,179 @@
+//! Incremental hash cache.
+//!
+//! Files whose size and modification time are unchanged since the last scan
+//! reuse the digests recorded here instead of being read again. Both the
+//! partial (head and tail) and the full digest are kept, since a file may
+//! only ever have needed the former. Each entry also records the algorithm
+//! that produced it, so changing `--security` or `--speed` never mixes digests.
+//!
+//! Entries are stored as a list rather than an object keyed by path, since a
+//! path that isn't UTF-8 can't be a JSON key; see [`crate::paths`]. Caches in
+//! the older keyed form are still read.
+
+use crate::hashing::HashKind;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CacheEntry {
+    pub size: u64,
+    pub mtime: u64,
+    /// Entries written before the algorithm was recorded have none and are never reused.
+    #[serde(default)]
+    pub algorithm: Option<HashKind>,
+    #[serde(default)]
+    pub partial: Option<Vec<u8>>,
+    #[serde(default)]
+    pub hash: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Default)]
+pub struct HashCache {
+    path: PathBuf,
+    entries: BTreeMap<PathBuf, CacheEntry>,
+}
+
+/// An entry as written to disk.
+#[derive(Serialize)]
+struct StoredRef<'a> {
+    #[serde(with = "crate::paths")]
+    file: &'a Path,
+    #[serde(flatten)]
+    entry: &'a CacheEntry,
+}
+
+#[derive(Deserialize)]
+struct Stored {
+    #[serde(with = "crate::paths")]
+    file: PathBuf,
+    #[serde(flatten)]
+    entry: CacheEntry,
+}
+
+fn parse_entries(data: &str) -> Option<BTreeMap<PathBuf, CacheEntry>> {
+    match serde_json::from_str::<Vec<Stored>>(data) {
+        Ok(stored) => Some(stored.into_iter().map(|s| (s.file, s.entry)).collect()),
+        Err(_) => serde_json::from_str::<BTreeMap<String, CacheEntry>>(data)
+            .ok()
+            .map(|keyed| keyed.into_iter().map(|(f, e)| (PathBuf::from(f), e)).collect()),
+    }
+}
+
+/// Modification time in whole seconds, or 0 when the platform can't report it.
+pub fn mtime_secs(meta: &Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl HashCache {
+    /// Load the cache stored at `path`. A missing or unreadable file gives an empty cache.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|data| parse_entries(&data))
+            .unwrap_or_default();
+        Self { path, entries }
+    }
+
+    /// Cached digests for `file`, if they were recorded with the same size, mtime and algorithm.
+    pub fn get(&self, file: &Path, size: u64, mtime: u64, algorithm: HashKind) -> Option<&CacheEntry> {
+        self.entries
+            .get(file)
+            .filter(|e| e.size == size && e.mtime == mtime && e.algorithm == Some(algorithm))
+    }
+
+    pub fn insert(&mut self, file: PathBuf, entry: CacheEntry) {
+        self.entries.insert(file, entry);
+    }
+
+    /// Record a freshly computed digest, discarding anything cached for an older
+    /// version of the file or under another algorithm.
+    pub fn update(
+        &mut self,
+        file: &Path,
+        size: u64,
+        mtime: u64,
+        algorithm: HashKind,
+        partial: Option<Vec<u8>>,
+        hash: Option<Vec<u8>>,
+    ) {
+        let entry = self.entries.entry(file.to_path_buf()).or_default();
+        if entry.size != size || entry.mtime != mtime || entry.algorithm != Some(algorithm) {
+            *entry = CacheEntry { size, mtime, algorithm: Some(algorithm), ..Default::default() };
+        }
+        if partial.is_some() {
+            entry.partial = partial;
+        }
+        if hash.is_some() {
+            entry.hash = hash;
+        }
+    }
+
+    /// Drop entries for files that no longer exist.
+    pub fn clean(&mut self) {
+        self.entries.retain(|f, _| f.exists());
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let stored: Vec<StoredRef> = self.entries.iter().map(|(file, entry)| StoredRef { file, entry }).collect();
+        let json = serde_json::to_string_pretty(&stored)?;
+        fs::write(&self.path, json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("cache.json");
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "hello").unwrap();
+
+        let mut cache = HashCache::load(&cache_path);
+        assert!(cache.is_empty());
+        cache.update(&file, 5, 42, HashKind::Blake3, Some(vec![1]), None);
+        cache.update(&file, 5, 42, HashKind::Blake3, None, Some(vec![1, 2, 3]));
+        cache.update(Path::new("/does/not/exist"), 1, 1, HashKind::Blake3, None, Some(vec![]));
+        cache.clean();
+        cache.save().unwrap();
+
+        let mut cache = HashCache::load(&cache_path);
+        assert_eq!(cache.len(), 1);
+        let entry = cache.get(&file, 5, 42, HashKind::Blake3).unwrap();
+        assert_eq!(entry.partial.as_deref(), Some(&[1u8][..]));
+        assert_eq!(entry.hash.as_deref(), Some(&[1u8, 2, 3][..]));
+        assert!(cache.get(&file, 5, 43, HashKind::Blake3).is_none());
+        assert!(cache.get(&file, 5, 42, HashKind::Sha256).is_none());
+
+        // A changed file drops the stale digests
+        cache.update(&file, 6, 43, HashKind::Blake3, Some(vec![9]), None);
+        let entry = cache.get(&file, 6, 43, HashKind::Blake3).unwrap();
+        assert_eq!(entry.hash, None);
+
+        // So does hashing it with another algorithm
+        cache.update(&file, 6, 43, HashKind::Sha256, None, Some(vec![7]));
+        let entry = cache.get(&file, 6, 43, HashKind::Sha256).unwrap();
+        assert_eq!(entry.partial, None);
+        assert!(cache.get(&file, 6, 43, HashKind::Blake3).is_none());
+    }
+}
@@ -1,149 +1,442 @@
-use crate::hashing::{HashConfig, Security, Speed};
+use crate::backup::{verify_backup, BackupCheck};
+use crate::compare::{compare, Comparison};
+use crate::hashing::{hash_file, HashConfig, Security, Speed};
+use crate::index::DEFAULT_INDEX_MEMORY;
+use crate::report;
 use crate::safety::QuarantineManager;
-use crate::similarity::{
-    compare_images_with_algorithm, group_similar_text_files, text_similarity
+use crate::scanner::{FileError, FileHash, ScanOptions, ScanProgress, ScanReport, Scanner};
+use crate::config::Config;
+use crate::dirtree::DirMatch;
+use crate::filter::parse_size;
+use crate::keep::{KeepPolicy, KeepRule};
+use crate::owner::user_name;
+use crate::paths::LosslessPath;
+use crate::walker::{
+    collect_roots, file_id, is_reference, link_count, read_path_list, resolve_listed, resolve_targets, root_of, walk_roots, FileFilter, LinkStatus,
+    Symlink,
 };
-use clap::{Parser, Subcommand, ValueEnum};
-use hex;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use indicatif::{ProgressBar, ProgressStyle};
 use inquire::Confirm;
-use regex::Regex;
-use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
-use std::env;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::process::ExitCode;
 use rayon::prelude::*;
-use walkdir;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct App {
+    #[command(subcommand)]
+    pub cmd: AppCmd,
+}
 
 #[derive(Subcommand, Debug)]
 pub enum AppCmd {
+    /// Scan files and directories for duplicate and similar files
+    Scan(ScanArgs),
+    /// Print the content hash of every matching file
+    Hash(HashArgs),
+    /// Compare the content of two trees: files only in A, only in B, and in both
+    Compare(CompareArgs),
+    /// Check that every file in SOURCE has a content-identical copy in BACKUP
+    VerifyBackup(VerifyBackupArgs),
+    /// Manage quarantined files
     #[command(subcommand)]
     Quarantine(QuarantineCmd),
+    /// Inspect the recovery log and restore files from it
     #[command(subcommand)]
     Recovery(RecoveryCmd),
+    /// Summarize the scan history appended to dedcore_report.json
+    Report(ReportArgs),
 }
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-pub struct App {
-    #[arg(short, long)]
-    pub filetypes: Option<String>,
+/// Hash algorithm selection shared by the scanning subcommands.
+#[derive(Args, Debug, Clone)]
+pub struct HashOpts {
+    /// Hash security level
+    #[arg(long, value_enum, default_value = "high")]
+    pub security: Security,
+
+    /// Hash speed preference
+    #[arg(long, value_enum, default_value = "balanced")]
+    pub speed: Speed,
+}
 
+impl Default for HashOpts {
+    fn default() -> Self {
+        Self { security: Security::High, speed: Speed::Balanced }
+    }
+}
+
+impl HashOpts {
+    pub fn config(&self) -> HashConfig {
+        HashConfig::new(self.security.clone(), self.speed.clone())
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ScanArgs {
+    /// Files and directories to scan
+    #[arg(value_name = "TARGETS", required_unless_present_any = ["from_file", "from_stdin"])]
+    pub targets: Vec<PathBuf>,
+
+    /// Also scan the files and directories listed in this file, one per line
+    #[arg(long, value_name = "LIST", group = "from")]
+    pub from_file: Option<PathBuf>,
+
+    /// Also scan the files and directories listed on standard input, one per line
+    #[arg(long, group = "from")]
+    pub from_stdin: bool,
+
+    /// Listed paths are separated by NUL bytes (find -print0, fd -0, git ls-files -z)
+    #[arg(short = '0', long, requires = "from")]
+    pub null: bool,
+
+    #[command(flatten)]
+    pub filters: FileFilter,
+
+    #[command(flatten)]
+    pub hash: HashOpts,
+
+    /// Show what would happen without hashing or moving anything
     #[arg(short, long)]
     pub dry: bool,
 
-    #[arg(short = 'p', long)]
-    pub dir: Option<PathBuf>,
+    /// Quarantine all duplicates (all but one per group) after scanning
+    #[arg(long)]
+    pub quarantine_all_dupes: bool,
 
-    #[arg(
-        long,
-        value_name = "SECURITY",
-        default_value = "high",
-        help = "Hash security: low, medium, high, maximum"
-    )]
-    pub security: String,
+    /// Replace duplicates (all but one per group) with hardlinks to the kept file
+    #[arg(long, conflicts_with = "quarantine_all_dupes")]
+    pub link: bool,
 
+    /// Replace duplicates (all but one per group) with symlinks to the kept file
     #[arg(
         long,
-        value_name = "SPEED",
-        default_value = "balanced",
-        help = "Hash speed: fastest, balanced, mostsecure"
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        default_missing_value = "relative",
+        conflicts_with_all = ["quarantine_all_dupes", "link"]
     )]
-    pub speed: String,
+    pub symlink: Option<SymlinkMode>,
 
-    #[arg(
-        long,
-        help = "Quarantine all duplicates (all but one per group) after scanning"
-    )]
-    pub quarantine_all_dupes: bool,
+    /// Also report whole duplicated directories; MATCH is names, or content to ignore file names
+    #[arg(long, value_enum, value_name = "MATCH", num_args = 0..=1, default_missing_value = "names")]
+    pub dirs: Option<DirMatch>,
 
-    #[arg(value_name = "TARGETS", required = true)]
-    pub targets: Vec<String>,
+    /// Quarantine every duplicated directory except the one kept
+    #[arg(long, requires = "dirs")]
+    pub quarantine_dirs: bool,
 
-    #[command(subcommand)]
-    pub cmd: Option<AppCmd>,
+    /// Compare against DIR without ever modifying it; its copies are always the ones kept
+    #[arg(long, value_name = "DIR")]
+    pub reference: Vec<PathBuf>,
 
-    #[arg(long, value_name = "PATH", help = "Path to save JSON report")]
-    pub json_report: Option<String>,
+    /// Move every matched file into DIR instead of hashing it
+    #[arg(long, value_name = "DIR")]
+    pub quarantine_dir: Option<PathBuf>,
 
-    #[arg(long, value_name = "PATH", help = "Path to save HTML report")]
-    pub html_report: Option<String>,
+    /// Quarantine every hashed file through the quarantine manager
+    #[arg(long)]
+    pub safe_delete: bool,
 
-    #[arg(
-        long,
-        value_name = "FLOAT",
-        help = "Minimum similarity threshold for grouping similar text files (0.0-1.0, default: 0.8)"
-    )]
-    /// Similarity threshold for text files (0.0 to 1.0)
-    /// Similarity threshold for text files (0.0 to 1.0)
-    #[arg(long, default_value_t = 0.8, value_parser = clap::value_parser!(f32), help = "Minimum similarity threshold for grouping text files (0.0 to 1.0, default: 0.8)")]
+    /// With --safe-delete, permanently delete the quarantined files
+    #[arg(long, requires = "safe_delete", conflicts_with = "rollback")]
+    pub commit: bool,
+
+    /// With --safe-delete, restore the quarantined files immediately
+    #[arg(long, requires = "safe_delete")]
+    pub rollback: bool,
+
+    /// Compare duplicates byte by byte before reporting them (always on when quarantining)
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Trust digests alone, even before quarantining
+    #[arg(long, conflicts_with = "verify")]
+    pub no_verify: bool,
+
+    /// Rule for picking the file to keep in each group; repeat to add tie-breakers.
+    /// One of oldest, newest, shortest-path, longest-path, root-order, dir:PATH, regex:PATTERN
+    #[arg(long = "keep", value_name = "RULE")]
+    pub keep: Vec<KeepRule>,
+
+    /// Settings file supplying the keep policy when --keep is not given [default: ~/.dedcore/config.json]
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Path to save JSON report
+    #[arg(long, value_name = "PATH")]
+    pub json_report: Option<PathBuf>,
+
+    /// Path to save HTML report
+    #[arg(long, value_name = "PATH")]
+    pub html_report: Option<PathBuf>,
+
+    /// Append this scan to dedcore_report.json and dedcore_report.html
+    #[arg(long)]
+    pub append_report: bool,
+
+    /// Minimum similarity threshold for grouping similar text files (0.0 to 1.0)
+    #[arg(long, value_name = "FLOAT", default_value_t = 0.8, value_parser = parse_threshold)]
     pub similarity_threshold: f32,
 
     /// Image hashing algorithm to use
     #[arg(long, value_enum, default_value_t = ImageHashAlgorithm::Combined)]
     pub image_hash_algorithm: ImageHashAlgorithm,
 
-    #[arg(
-        long,
-        value_name = "FLOAT",
-        default_value_t = 0.9,
-        value_parser = clap::value_parser!(f32),
-        help = "Minimum similarity threshold for grouping similar images (0.0-1.0, default: 0.9)"
-    )]
+    /// Minimum similarity threshold for grouping similar images (0.0 to 1.0)
+    #[arg(long, value_name = "FLOAT", default_value_t = 0.9, value_parser = parse_threshold)]
     pub image_similarity_threshold: f32,
+
+    /// Memory for the index of scanned files; beyond it the index moves to a temporary file
+    #[arg(long, value_name = "SIZE", default_value = "256M", value_parser = parse_size)]
+    pub index_memory: u64,
+
+    /// Don't keep the list of every scanned file and its digest for the JSON and HTML reports
+    #[arg(long, conflicts_with = "safe_delete")]
+    pub no_file_list: bool,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-pub enum ImageHashAlgorithm {
-    /// Average hash (fastest but less accurate)
-    Avg,
-    /// Perceptual hash (more accurate but slower)
-    Phash,
-    /// Difference hash (good balance of speed and accuracy)
-    Dhash,
-    /// Color hash (based on color distribution)
-    Color,
-    /// Combined approach (uses all hashes with weighted average)
-    Combined,
+impl ScanArgs {
+    /// Scan arguments for `targets` with every option at its CLI default.
+    pub fn new(targets: Vec<PathBuf>) -> Self {
+        Self {
+            targets,
+            from_file: None,
+            from_stdin: false,
+            null: false,
+            filters: FileFilter::default(),
+            hash: HashOpts::default(),
+            dry: false,
+            quarantine_all_dupes: false,
+            link: false,
+            symlink: None,
+            dirs: None,
+            quarantine_dirs: false,
+            reference: Vec::new(),
+            quarantine_dir: None,
+            safe_delete: false,
+            commit: false,
+            rollback: false,
+            verify: false,
+            no_verify: false,
+            keep: Vec::new(),
+            config: None,
+            json_report: None,
+            html_report: None,
+            append_report: false,
+            similarity_threshold: 0.8,
+            image_hash_algorithm: ImageHashAlgorithm::Combined,
+            image_similarity_threshold: 0.9,
+            index_memory: DEFAULT_INDEX_MEMORY as u64,
+            no_file_list: false,
+        }
+    }
+
+    /// Whether duplicates get compared byte by byte: on request, and by default
+    /// whenever the scan is going to move files.
+    pub fn verify_enabled(&self) -> bool {
+        !self.no_verify
+            && (self.verify
+                || self.quarantine_all_dupes
+                || self.quarantine_dirs
+                || self.link
+                || self.symlink.is_some())
+    }
+
+    /// Paths from `--from-file` or `--from-stdin`; empty when neither was given.
+    pub fn listed_paths(&self) -> io::Result<Vec<PathBuf>> {
+        if self.from_stdin {
+            read_path_list(io::stdin().lock(), self.null)
+        } else if let Some(list) = &self.from_file {
+            read_path_list(fs::File::open(list)?, self.null)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Keep rules from `--keep`, or else from the config file, with their
+    /// directories resolved.
+    pub fn keep_policy(&self) -> Result<KeepPolicy, String> {
+        if !self.keep.is_empty() {
+            return KeepPolicy::new(self.keep.clone()).resolve();
+        }
+        let policy = match &self.config {
+            // Only the default location is allowed to be missing
+            Some(path) if !path.exists() => Err(format!("{}: No such file", path.display())),
+            Some(path) => Config::load(path).map(|config| config.keep),
+            None => Config::load(Config::default_path()).map(|config| config.keep),
+        }?;
+        policy.resolve()
+    }
+
+    /// Library options equivalent to these arguments.
+    pub fn scan_options(&self) -> ScanOptions {
+        ScanOptions::new(self.targets.clone())
+            .reference(self.reference.clone())
+            .filter(self.filters.clone())
+            .hash_config(self.hash.config())
+            .cache_path(Some(".dedcore_cache.json"))
+            .text_similarity(Some(self.similarity_threshold))
+            .image_similarity(Some(self.image_similarity_threshold))
+            .image_hash_algorithm(self.image_hash_algorithm)
+            .verify(self.verify_enabled())
+            .directories(self.dirs)
+            .index_memory(usize::try_from(self.index_memory).unwrap_or(usize::MAX))
+            .list_files(!self.no_file_list)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct FileHashCacheEntry {
-    size: u64,
-    mtime: u64,
-    hash: Vec<u8>,
+#[derive(Args, Debug, Clone)]
+pub struct HashArgs {
+    /// Files and directories to hash
+    #[arg(value_name = "TARGETS", required = true)]
+    pub targets: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub filters: FileFilter,
+
+    #[command(flatten)]
+    pub hash: HashOpts,
+
+    /// Path to save the hashes as JSON
+    #[arg(long, value_name = "PATH")]
+    pub json_report: Option<PathBuf>,
 }
 
-fn load_cache(path: &str) -> BTreeMap<String, FileHashCacheEntry> {
-    let cache_path = std::path::Path::new(path);
-    if cache_path.exists() {
-        if let Ok(data) = std::fs::read_to_string(cache_path) {
-            if let Ok(map) = serde_json::from_str(&data) {
-                return map;
-            }
+#[derive(Args, Debug, Clone)]
+pub struct CompareArgs {
+    /// First file or directory
+    #[arg(value_name = "A")]
+    pub a: PathBuf,
+
+    /// Second file or directory
+    #[arg(value_name = "B")]
+    pub b: PathBuf,
+
+    #[command(flatten)]
+    pub filters: FileFilter,
+
+    #[command(flatten)]
+    pub hash: HashOpts,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = CompareFormat::Text)]
+    pub format: CompareFormat,
+
+    /// Only output one of the sets
+    #[arg(long, value_enum, value_name = "SET")]
+    pub only: Option<CompareSet>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct VerifyBackupArgs {
+    /// Directory that should be backed up
+    #[arg(value_name = "SOURCE")]
+    pub source: PathBuf,
+
+    /// Directory holding the backup
+    #[arg(value_name = "BACKUP")]
+    pub backup: PathBuf,
+
+    #[command(flatten)]
+    pub filters: FileFilter,
+
+    #[command(flatten)]
+    pub hash: HashOpts,
+
+    /// Print the result as JSON instead of text
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// How `compare` prints its result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompareFormat {
+    /// Sections for humans
+    Text,
+    /// The whole comparison as JSON
+    Json,
+    /// One path per line; prefixed with the set and a tab unless --only is given
+    Paths,
+}
+
+/// One of the sets `compare` sorts files into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompareSet {
+    /// Files whose content only A has
+    #[value(name = "only-a")]
+    OnlyA,
+    /// Files whose content only B has
+    #[value(name = "only-b")]
+    OnlyB,
+    /// Files in A whose content B also has
+    Common,
+}
+
+impl CompareSet {
+    fn label(self) -> &'static str {
+        match self {
+            CompareSet::OnlyA => "only-a",
+            CompareSet::OnlyB => "only-b",
+            CompareSet::Common => "common",
+        }
+    }
+
+    /// The paths in this set. Common content is listed by its paths in A.
+    fn paths(self, comparison: &Comparison) -> Vec<&PathBuf> {
+        match self {
+            CompareSet::OnlyA => comparison.only_a.iter().collect(),
+            CompareSet::OnlyB => comparison.only_b.iter().collect(),
+            CompareSet::Common => comparison.common.iter().flat_map(|c| &c.a).collect(),
         }
     }
-    BTreeMap::new()
 }
 
-fn save_cache(path: &str, cache: &BTreeMap<String, FileHashCacheEntry>) {
-    if let Ok(json) = serde_json::to_string_pretty(cache) {
-        let _ = std::fs::write(path, json);
+#[derive(Args, Debug, Clone)]
+pub struct ReportArgs {
+    /// Scan history file written by `scan --append-report`
+    #[arg(long, value_name = "PATH", default_value = "dedcore_report.json")]
+    pub history: PathBuf,
+
+    /// Only show the most recent N scans
+    #[arg(long, value_name = "N")]
+    pub last: Option<usize>,
+}
+
+fn parse_threshold(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err("must be between 0.0 and 1.0".to_string())
     }
 }
 
-fn clean_cache(cache: &mut BTreeMap<String, FileHashCacheEntry>) {
-    cache.retain(|f, _| std::path::Path::new(f).exists());
+/// How `--symlink` points duplicates at the kept file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SymlinkMode {
+    /// Path relative to the duplicate's directory
+    Relative,
+    /// Absolute path to the kept file
+    Absolute,
 }
 
-#[derive(Serialize)]
-struct FileHashReport {
-    file: String,
-    hash: String,
-    algorithm: String,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ImageHashAlgorithm {
+    /// Average hash (fastest but less accurate)
+    Avg,
+    /// Perceptual hash (more accurate but slower)
+    Phash,
+    /// Difference hash (good balance of speed and accuracy)
+    Dhash,
+    /// Color hash (based on color distribution)
+    Color,
+    /// Combined approach (uses all hashes with weighted average)
+    Combined,
 }
 
 #[derive(Subcommand, Debug)]
@@ -151,7 +444,7 @@ pub enum QuarantineCmd {
     /// Quarantine a file (move it to quarantine)
     File {
         #[arg(value_name = "FILE")]
-        file: String,
+        file: PathBuf,
     },
     /// Commit deletions (permanently delete quarantined files)
     Commit,
@@ -162,7 +455,7 @@ pub enum QuarantineCmd {
     /// Restore a specific quarantined file by its original path
     Restore {
         #[arg(value_name = "ORIGINAL_PATH")]
-        original_path: String,
+        original_path: PathBuf,
     },
 }
 
@@ -173,286 +466,90 @@ pub enum RecoveryCmd {
     /// Restore a file from quarantine by original path
     Restore {
         #[arg(value_name = "ORIGINAL_PATH")]
-        original_path: String,
+        original_path: PathBuf,
     },
 }
 
-fn collect_files_recursively_with_filter<P: AsRef<Path>>(
-    root: P,
-    exts: Option<&Vec<String>>,
-    min_size: Option<u64>,
-    max_size: Option<u64>,
-    min_age: Option<u64>,
-    max_age: Option<u64>,
-    regex_filter: Option<&Regex>,
-) -> Vec<String> {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    walkdir::WalkDir::new(root)
-        .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| {
-            let meta = e.metadata().ok();
-            let size_ok = meta
-                .as_ref()
-                .map(|m| {
-                    let len = m.len();
-                    (min_size.map_or(true, |min| len >= min))
-                        && (max_size.map_or(true, |max| len <= max))
-                })
-                .unwrap_or(false);
-            if !size_ok {
-                return false;
-            }
-            let age_ok = meta
-                .as_ref()
-                .map(|m| {
-                    if let Ok(modified) = m.modified() {
-                        if let Ok(modified_secs) = modified.duration_since(UNIX_EPOCH) {
-                            let age_secs = if now >= modified_secs.as_secs() {
-                                now - modified_secs.as_secs()
-                            } else {
-                                0
-                            };
-                            let age_days = age_secs / 86400;
-                            (min_age.map_or(true, |min| age_days >= min))
-                                && (max_age.map_or(true, |max| age_days <= max))
-                        } else {
-                            true
-                        }
-                    } else {
-                        true
-                    }
-                })
-                .unwrap_or(false);
-            if !age_ok {
-                return false;
-            }
-            let path_str = e.path().to_string_lossy();
-            if let Some(re) = regex_filter {
-                if !re.is_match(&path_str) {
-                    return false;
-                }
-            }
-            if let Some(exts) = exts {
-                if let Some(ext) = e.path().extension().and_then(|s| s.to_str()) {
-                    return exts.iter().any(|x| x.eq_ignore_ascii_case(ext));
-                } else {
-                    return false;
-                }
-            }
-            true
-        })
-        .map(|e| e.path().to_string_lossy().to_string())
-        .collect()
-}
-
-/// Collects files recursively in parallel using Rayon for improved performance.
-/// This function provides the same filtering capabilities as `collect_files_recursively_with_filter`
-/// but processes directories in parallel.
-fn collect_files_recursively_parallel<P: AsRef<Path> + Send + Sync>(
-    root: P,
-    exts: Option<&Vec<String>>,
-    min_size: Option<u64>,
-    max_size: Option<u64>,
-    min_age: Option<u64>,
-    max_age: Option<u64>,
-    regex_filter: Option<&Regex>,
-) -> Vec<String> {
-    use walkdir::WalkDir;
-    
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    
-    // Convert filter options to Arc for thread-safe sharing
-    let exts = exts.map(|v| Arc::new(v.clone()));
-    let regex_filter = regex_filter.map(|r| Arc::new(r.clone()));
-    
-    // Create a channel for collecting results
-    let (tx, rx) = std::sync::mpsc::channel();
-    
-    // Walk the directory in parallel
-    WalkDir::new(root)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| e.file_type().is_dir() || e.file_type().is_file())
-        .par_bridge()
-        .for_each_with(tx, |sender, entry| {
-            if entry.file_type().is_file() {
-                let meta = match entry.metadata() {
-                    Ok(m) => m,
-                    Err(_) => return,
-                };
-                
-                // Check file size
-                let len = meta.len();
-                if let Some(min) = min_size {
-                    if len < min { return; }
-                }
-                if let Some(max) = max_size {
-                    if len > max { return; }
-                }
-                
-                // Check file age
-                if min_age.is_some() || max_age.is_some() {
-                    if let Ok(modified) = meta.modified() {
-                        if let Ok(modified_secs) = modified.duration_since(UNIX_EPOCH) {
-                            let age_secs = now.saturating_sub(modified_secs.as_secs());
-                            let age_days = age_secs / 86400;
-                            
-                            if let Some(min) = min_age {
-                                if age_days < min { return; }
-                            }
-                            if let Some(max) = max_age {
-                                if age_days > max { return; }
-                            }
-                        }
-                    }
-                }
-                
-                // Check regex filter
-                let path_str = entry.path().to_string_lossy();
-                if let Some(ref re) = regex_filter {
-                    if !re.is_match(&path_str) { return; }
-                }
-                
-                // Check file extension
-                if let Some(exts) = &exts {
-                    if let Some(ext) = entry.path().extension().and_then(|s| s.to_str()) {
-                        if !exts.iter().any(|x| x.eq_ignore_ascii_case(ext)) {
-                            return;
-                        }
-                    } else if !exts.is_empty() {
-                        return;
-                    }
-                }
-                
-                // If we get here, the file passes all filters
-                let _ = sender.send(entry.path().to_string_lossy().to_string());
-            }
-        });
-    
-    // Collect all results
-    rx.into_iter().collect()
-}
-
-pub fn run_with_args<I, T>(args: I)
+pub fn run_with_args<I, T>(args: I) -> ExitCode
 where
     I: IntoIterator<Item = T>,
     T: Into<std::ffi::OsString> + Clone,
 {
     let app = App::parse_from(args);
-    // Parse security and speed from CLI
-    let security = match app.security.to_lowercase().as_str() {
-        "low" => Security::Low,
-        "medium" => Security::Medium,
-        "maximum" => Security::Maximum,
-        _ => Security::High,
-    };
-    let speed = match app.speed.to_lowercase().as_str() {
-        "fastest" => Speed::Fastest,
-        "mostsecure" => Speed::MostSecure,
-        _ => Speed::Balanced,
-    };
-    let default_config = HashConfig::new(security, speed);
-    let mut filetypes: Option<Vec<String>> = None;
-    let mut min_size: Option<u64> = None;
-    let mut max_size: Option<u64> = None;
-    let mut min_age: Option<u64> = None;
-    let mut max_age: Option<u64> = None;
-    let mut regex_filter: Option<Regex> = None;
-    let mut dry_run = app.dry;
-    let mut quarantine_dir: Option<String> = None;
-    let mut safe_delete = false;
-    let mut commit = false;
-    let mut rollback = false;
-    let args: Vec<String> = env::args().collect();
-    for arg in &args {
-        if let Some(rest) = arg.strip_prefix("--filetypes=") {
-            filetypes = Some(rest.split(',').map(|s| s.trim().to_string()).collect());
-        }
-        if let Some(rest) = arg.strip_prefix("--min-size=") {
-            min_size = Some(rest.parse::<u64>().unwrap());
-        }
-        if let Some(rest) = arg.strip_prefix("--max-size=") {
-            max_size = Some(rest.parse::<u64>().unwrap());
-        }
-        if let Some(rest) = arg.strip_prefix("--min-age=") {
-            min_age = Some(rest.parse::<u64>().unwrap());
-        }
-        if let Some(rest) = arg.strip_prefix("--max-age=") {
-            max_age = Some(rest.parse::<u64>().unwrap());
-        }
-        if let Some(rest) = arg.strip_prefix("--regex=") {
-            if let Ok(re) = Regex::new(rest) {
-                regex_filter = Some(re);
-            } else {
-                eprintln!("Invalid regex pattern: {}", rest);
-                return;
-            }
-        }
-        if arg == "--dry" {
-            dry_run = true;
-        }
-        if let Some(rest) = arg.strip_prefix("--quarantine-dir=") {
-            quarantine_dir = Some(rest.to_string());
-        }
-        if arg == "--safe-delete" {
-            safe_delete = true;
-        }
-        if arg == "--commit" {
-            commit = true;
+    match app.cmd {
+        AppCmd::Scan(args) => run_scan(&args),
+        AppCmd::Hash(args) => run_hash(&args),
+        AppCmd::Compare(args) => run_compare(&args),
+        AppCmd::VerifyBackup(args) => run_verify_backup(&args),
+        AppCmd::Quarantine(cmd) => run_quarantine(&cmd),
+        AppCmd::Recovery(cmd) => run_recovery(&cmd),
+        AppCmd::Report(args) => run_report(&args),
+    }
+}
+
+pub fn run_scan(args: &ScanArgs) -> ExitCode {
+    let policy = match args.keep_policy() {
+        Ok(policy) => policy,
+        Err(e) => {
+            eprintln!("Invalid keep policy in {}", e);
+            return ExitCode::FAILURE;
         }
-        if arg == "--rollback" {
-            rollback = true;
+    };
+    let listed = match args.listed_paths() {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("Cannot read the list of paths: {}", e);
+            return ExitCode::FAILURE;
         }
+    };
+    let list_source = match &args.from_file {
+        Some(list) => list.display().to_string(),
+        None => "standard input".to_string(),
+    };
+    let listed_count = listed.len();
+    // Listed directories are walked like targets, listed files are taken as they are
+    let (listed_dirs, listed_files, listed_missing) = resolve_listed(listed, &args.filters);
+    let mut options = args.scan_options().keep_policy(policy);
+    options.targets.extend(listed_dirs);
+    let scanner = Scanner::new(options);
+    let FileFilter {
+        filetypes, min_size, max_size, min_age, max_age, regex: regex_filter, include, exclude, expr, owner, group, ..
+    } = &args.filters;
+    let scan_target = match args.targets.as_slice() {
+        [] => format!("{} paths listed in {}", listed_count, list_source),
+        [t] if t.is_dir() => format!("directory: {}", t.display()),
+        [t] => format!("file: {}", t.display()),
+        targets => format!(
+            "files: {}",
+            targets.iter().map(|t| t.display().to_string()).collect::<Vec<_>>().join(", ")
+        ),
+    };
+    let (roots, missing) = scanner.roots();
+    for target in missing.iter().chain(&listed_missing) {
+        eprintln!("No valid file or directory found for: {}", target.display());
     }
-    let mut files: Vec<String> = Vec::new();
-    let scan_target;
-    if app.targets.len() == 1 && Path::new(&app.targets[0]).is_dir() {
-        scan_target = format!("directory: {}", app.targets[0]);
-        files = collect_files_recursively_parallel(
-            &app.targets[0],
-            filetypes.as_ref(),
-            min_size,
-            max_size,
-            min_age,
-            max_age,
-            regex_filter.as_ref(),
-        );
-    } else if app.targets.len() == 1 && Path::new(&app.targets[0]).is_file() {
-        scan_target = format!("file: {}", app.targets[0]);
-        files.push(app.targets[0].clone());
-    } else {
-        scan_target = format!("files: {}", app.targets.join(", "));
-        for f in &app.targets {
-            if Path::new(f).is_file() {
-                files.push(f.clone());
-            } else if Path::new(f).is_dir() {
-                let mut dir_files = collect_files_recursively_parallel(
-                    f,
-                    filetypes.as_ref(),
-                    min_size,
-                    max_size,
-                    min_age,
-                    max_age,
-                    regex_filter.as_ref(),
-                );
-                files.append(&mut dir_files);
-            }
+    // Moving or listing files needs them all up front; a scan hashes them as the walk finds them
+    let walked = if args.dry || args.quarantine_dir.is_some() {
+        let mut files = walk_roots(&roots, &args.filters).files;
+        files.extend(listed_files.iter().cloned());
+        files.sort();
+        files.dedup();
+        if files.is_empty() {
+            eprintln!("No files found to hash.");
+            return ExitCode::FAILURE;
         }
-    }
-    if files.is_empty() {
-        eprintln!("No files found to hash.");
-        return;
-    }
+        files
+    } else {
+        Vec::new()
+    };
     println!("\n=== DEDCORE File Hasher ===");
     println!("Scanning {}", scan_target);
+    if !args.targets.is_empty() && (args.from_stdin || args.from_file.is_some()) {
+        println!("Also scanning {} paths listed in {}", listed_count, list_source);
+    }
+    for dir in &args.reference {
+        println!("Reference (read-only): {}", dir.display());
+    }
     if let Some(ref exts) = filetypes {
         println!("Filtering by file types: {:?}", exts);
     }
@@ -471,214 +568,92 @@ where
     if let Some(ref re) = regex_filter {
         println!("Filtering by regex: {}", re);
     }
-    if let Some(ref qdir) = quarantine_dir {
-        println!("Quarantine directory: {}", qdir);
+    for glob in include {
+        println!("Including: {}", glob);
+    }
+    for glob in exclude {
+        println!("Excluding: {}", glob);
+    }
+    if let Some(ref expr) = expr {
+        println!("Filtering by expression: {}", expr);
+    }
+    if !owner.is_empty() {
+        let names: Vec<String> = owner.iter().map(|&uid| user_name(uid).unwrap_or_else(|| uid.to_string())).collect();
+        println!("Filtering by owner: {}", names.join(", "));
+    }
+    if !group.is_empty() {
+        let gids: Vec<String> = group.iter().map(|gid| gid.to_string()).collect();
+        println!("Filtering by group id: {}", gids.join(", "));
     }
-    if safe_delete {
+    if args.filters.one_file_system {
+        println!("Staying on one filesystem per target");
+    }
+    if args.filters.deletable {
+        println!("Skipping files that can't be deleted");
+    }
+    if let Some(ref qdir) = args.quarantine_dir {
+        println!("Quarantine directory: {}", qdir.display());
+    }
+    if args.safe_delete {
         println!("Safe delete mode: enabled");
     }
-    if commit {
+    if args.commit {
         println!("Commit mode: enabled");
     }
-    if rollback {
+    if args.rollback {
         println!("Rollback mode: enabled");
     }
-    println!("Files to process: {}\n", files.len());
-    // Check for text similarity if threshold is provided
-    let threshold = app.similarity_threshold;
-    if threshold < 0.0 || threshold > 1.0 {
-        eprintln!("Error: Similarity threshold must be between 0.0 and 1.0");
-        return;
-    }
-
-    println!("\n=== Analyzing text similarity (threshold: {:.2}) ===", threshold);
-    
-    // Filter text files
-    let text_files: Vec<String> = files.iter()
-        .filter(|f| {
-            let ext = Path::new(f).extension()
-                .and_then(|s| s.to_str())
-                .unwrap_or("")
-                .to_lowercase();
-            matches!(ext.as_str(), "txt" | "md" | "rs" | "py" | "js" | "ts" | "java" | "c" | "cpp" | "h" | "hpp" | "html" | "css" | "json" | "toml" | "yaml" | "yml" | "xml" | "csv" | "log")
-        })
-        .cloned()
-        .collect();
-
-    if text_files.len() < 2 {
-        println!("Not enough text files for similarity analysis.");
+    if walked.is_empty() {
+        println!();
     } else {
-        match group_similar_text_files(&text_files, threshold as f32) {
-            Ok(groups) => {
-                if groups.is_empty() {
-                    println!("No similar text files found above the threshold.");
-                } else {
-                    println!("\n=== Found {} groups of similar text files ===", groups.len());
-                    for (i, group) in groups.iter().enumerate() {
-                        println!("\nGroup {} ({} files):", i + 1, group.len());
-                        for file in group {
-                            println!("  {}", file);
-                        }
-                        
-                        // Show similarity scores for the first few pairs in each group
-                            if group.len() > 1 {
-                                println!("  Similarity scores:");
-                                let reference = &group[0];
-                                for other in group.iter().skip(1).take(3) { // Limit to first 3 comparisons
-                                    if let Ok(similarity) = text_similarity(Path::new(reference), Path::new(other)) {
-                                        println!("    {} <-> {}: {:.1}%", 
-                                            Path::new(reference).file_name().unwrap_or_default().to_string_lossy(),
-                                            Path::new(other).file_name().unwrap_or_default().to_string_lossy(),
-                                            similarity * 100.0
-                                        );
-                                    }
-                                }
-                                if group.len() > 4 {
-                                    println!("    ... and {} more comparisons", group.len() - 2);
-                                }
-                            }
-                        }
-                        
-                    if dry_run {
-                        println!("\n[DRY RUN] Would have processed {} groups of similar text files.", groups.len());
-                        return;
-                    }
-                }
-            },
-            Err(e) => {
-                eprintln!("Error analyzing text similarity: {}", e);
-            }
-        }
+        println!("Files to process: {}\n", walked.len());
     }
-    
-    if dry_run {
-        println!("\n[DRY RUN] Text similarity analysis complete. No changes made.");
-        return;
-    }
-    if dry_run {
+
+    if args.dry {
         println!("[DRY RUN] The following files would be processed:");
-        for f in &files {
-            println!("{}", f);
+        for f in &walked {
+            println!("{}", f.display());
         }
-        println!("\n[DRY RUN] {} files would be processed.", files.len());
-        return;
+        println!("\n[DRY RUN] {} files would be processed. No changes made.", walked.len());
+        return ExitCode::SUCCESS;
     }
-    if let Some(ref qdir) = quarantine_dir {
+    if let Some(ref qdir) = args.quarantine_dir {
         fs::create_dir_all(qdir).ok();
         let mut moved = 0;
-        for f in &files {
-            if let Some(fname) = Path::new(f).file_name() {
-                let dest = Path::new(qdir).join(fname);
+        for f in walked.iter().filter(|f| !is_reference(&roots, f)) {
+            if let Some(fname) = f.file_name() {
+                let dest = qdir.join(fname);
                 if let Err(e) = fs::rename(f, &dest) {
-                    eprintln!("Failed to move {} to {}: {}", f, dest.display(), e);
+                    eprintln!("Failed to move {} to {}: {}", f.display(), dest.display(), e);
                 } else {
-                    println!("{} -> {}", f, dest.display());
+                    println!("{} -> {}", f.display(), dest.display());
                     moved += 1;
                 }
             }
         }
         println!("\n{} files moved to quarantine.", moved);
-        return;
+        return ExitCode::SUCCESS;
     }
-    let pb = ProgressBar::new(files.len() as u64);
-    pb.set_style(
-        ProgressStyle::with_template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}")
-            .unwrap()
-            .progress_chars("##-"),
-    );
-    let mut cache = load_cache(".dedcore_cache.json");
-    clean_cache(&mut cache);
-    let mut results = Vec::with_capacity(files.len());
-    let mut report: Vec<FileHashReport> = Vec::with_capacity(files.len());
-    let mut algo_summary: std::collections::BTreeMap<String, String> =
-        std::collections::BTreeMap::new();
-    let mut hash_to_files: HashMap<Vec<u8>, Vec<String>> = HashMap::new();
-    let mut skipped = 0;
-    for f in &files {
-        let ext = Path::new(f)
-            .extension()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_string();
-        let algo = default_config.choose_algorithm(&ext);
-        let meta = match std::fs::metadata(f) {
-            Ok(m) => m,
-            Err(_) => continue,
-        };
-        let size = meta.len();
-        let mtime = meta
-            .modified()
-            .ok()
-            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
-            .map(|d| d.as_secs())
-            .unwrap_or(0);
-        if let Some(entry) = cache.get(f) {
-            if entry.size == size && entry.mtime == mtime {
-                skipped += 1;
-                hash_to_files
-                    .entry(entry.hash.clone())
-                    .or_default()
-                    .push(f.clone());
-                report.push(FileHashReport {
-                    file: f.to_string(),
-                    hash: hex::encode(&entry.hash),
-                    algorithm: format!("{:?}", algo),
-                });
-                continue;
-            }
-        }
-        let hash = crate::hashing::hash_file(f, algo.clone()).unwrap_or_default();
-        cache.insert(
-            f.clone(),
-            FileHashCacheEntry {
-                size,
-                mtime,
-                hash: hash.clone(),
-            },
-        );
-        results.push((f.to_string(), hash.clone()));
-        hash_to_files
-            .entry(hash.clone())
-            .or_default()
-            .push(f.clone());
-        report.push(FileHashReport {
-            file: f.to_string(),
-            hash: hex::encode(&hash),
-            algorithm: format!("{:?}", algo),
-        });
-        if !ext.is_empty() {
-            algo_summary
-                .entry(ext.clone())
-                .or_insert_with(|| format!("{:?}", algo));
-        }
-        pb.inc(1);
-    }
-    pb.finish_with_message("done");
-    save_cache(".dedcore_cache.json", &cache);
-    if skipped > 0 {
-        println!(
-            "Skipped {} unchanged files due to incremental scanning.",
-            skipped
-        );
+
+    let mut report = scan_with_progress_bar(&scanner, listed_files);
+    if report.stats.files_scanned == 0 {
+        eprintln!("No files found to hash.");
+        return ExitCode::FAILURE;
     }
+    report.errors.extend(listed_missing.into_iter().map(|target| FileError {
+        file: target,
+        reason: "No such file or directory".to_string(),
+    }));
+    report.stats.files_failed = report.errors.len();
+    print_scan_report(&report, args);
 
-    if safe_delete {
+    if args.safe_delete {
         println!("\n=== Safe Delete Mode ===");
 
-        let mut quarantine = match QuarantineManager::new() {
-            Ok(qm) => qm,
-            Err(e) => {
-                eprintln!("Failed to initialize quarantine system: {}", e);
-                return;
-            }
-        };
-
-        let mut _quarantined_count = 0;
-        for (file_path, _) in &results {
-            if let Err(e) = quarantine.quarantine_file(file_path) {
-                eprintln!("Failed to quarantine {}: {}", file_path, e);
-            } else {
-                _quarantined_count += 1;
+        let Some(mut quarantine) = open_quarantine() else { return ExitCode::FAILURE };
+        for file in report.files.iter().filter(|f| !is_reference(&report.roots, f)) {
+            if let Err(e) = quarantine.quarantine_file(file) {
+                eprintln!("Failed to quarantine {}: {}", file.display(), e);
             }
         }
 
@@ -689,7 +664,7 @@ where
             total_size as f64 / 1024.0 / 1024.0
         );
 
-        if commit {
+        if args.commit {
             println!("\n=== Committing Deletions ===");
             match quarantine.commit_deletions() {
                 Ok(deleted_count) => {
@@ -699,7 +674,7 @@ where
                     eprintln!("Failed to commit deletions: {}", e);
                 }
             }
-        } else if rollback {
+        } else if args.rollback {
             println!("\n=== Rolling Back ===");
             match quarantine.rollback() {
                 Ok(restored_count) => {
@@ -715,659 +690,831 @@ where
         }
     }
 
-    if let Some(ref jpath) = app.json_report {
-        if let Ok(json) = serde_json::to_string_pretty(&report) {
-            if let Err(e) = fs::write(jpath, json) {
-                eprintln!("Failed to write JSON report: {}", e);
-            } else {
-                println!("JSON report written to {}", jpath);
+    if args.quarantine_dirs && !report.directory_groups.is_empty() {
+        println!("\nFound {} groups of duplicate directories.", report.directory_groups.len());
+        let Some(mut qm) = open_quarantine() else { return ExitCode::FAILURE };
+        let (dirs, files) = quarantine_directories(&mut qm, &report);
+        println!("Quarantined {} duplicate directories ({} files).", dirs, files);
+    }
+
+    if args.quarantine_all_dupes && !report.duplicate_groups.is_empty() {
+        println!("\nFound {} groups of duplicates.", report.duplicate_groups.len());
+        let Some(mut qm) = open_quarantine() else { return ExitCode::FAILURE };
+        let quarantined = quarantine_duplicates(&mut qm, &report);
+        println!("Quarantined {} duplicate files.", quarantined);
+    }
+
+    if (args.link || args.symlink.is_some()) && !report.duplicate_groups.is_empty() {
+        println!("\nFound {} groups of duplicates.", report.duplicate_groups.len());
+        let Some(mut qm) = open_quarantine() else { return ExitCode::FAILURE };
+        let (linked, skipped) = link_duplicates(&mut qm, &report, args.symlink);
+        let kind = if args.symlink.is_some() { "symlinks" } else { "hardlinks" };
+        println!("Replaced {} duplicate files with {}.", linked, kind);
+        if !skipped.is_empty() {
+            println!("Skipped {} files:", skipped.len());
+            for (file, reason) in &skipped {
+                println!("  {}: {}", file.display(), reason);
             }
-        } else {
-            eprintln!("Failed to serialize JSON report");
         }
     }
-    if let Some(ref hpath) = app.html_report {
-        let mut html = String::from(
-            "<html><head><title>dedcore Report</title></head><body><h1>dedcore File Hash Report</h1><table border=1><tr><th>File</th><th>Hash</th><th>Algorithm</th></tr>",
-        );
-        for r in &report {
-            html.push_str(&format!(
-                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
-                r.file, r.hash, r.algorithm
-            ));
-        }
-        html.push_str("</table></body></html>");
-        if let Err(_e) = fs::write(hpath, html) {
-            eprintln!("Failed to write HTML report: {}", hpath);
-        } else {
-            println!("HTML report written to {}", hpath);
+
+    if let Some(ref jpath) = args.json_report {
+        match report::write_json_report(&report, jpath) {
+            Ok(()) => println!("JSON report written to {}", jpath.display()),
+            Err(e) => eprintln!("Failed to write JSON report: {}", e),
         }
     }
-    // Ask the user if they want to append the report after every scan
-    if Confirm::new(
-        "Would you like to append this scan to dedcore_report.json and dedcore_report.html?",
-    )
-    .with_default(true)
-    .prompt()
-    .unwrap_or(false)
-    {
-        let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-        // JSON append
-        let json_path = "dedcore_report.json";
-        let mut all_scans = if let Ok(existing) = std::fs::read_to_string(json_path) {
-            serde_json::from_str::<serde_json::Value>(&existing)
-                .unwrap_or_else(|_| serde_json::json!([]))
-        } else {
-            serde_json::json!([])
-        };
-        let mut duplicate_groups: Vec<Vec<String>> = Vec::new();
-        let mut potential_space_savings: u64 = 0;
-        for files in hash_to_files.values() {
-            if files.len() > 1 {
-                // Calculate space savings: sum size of all but one file
-                let mut group_size = 0;
-                for file in &files[1..] {
-                    if let Ok(meta) = fs::metadata(file) {
-                        group_size += meta.len();
-                    }
-                }
-                potential_space_savings += group_size;
-                duplicate_groups.push(files.clone());
-            }
-        }
-        let scan_entry = serde_json::json!({
-            "timestamp": timestamp,
-            "results": report,
-            "summary": {
-                "processed_files": results.len(),
-                "duplicate_groups": duplicate_groups.len(),
-                "potential_space_savings_bytes": potential_space_savings,
-                "potential_space_savings_mb": (potential_space_savings as f64 / 1024.0 / 1024.0),
-            }
-        });
-        if let Some(arr) = all_scans.as_array_mut() {
-            arr.push(scan_entry);
+    if let Some(ref hpath) = args.html_report {
+        match report::write_html_report(&report, hpath) {
+            Ok(()) => println!("HTML report written to {}", hpath.display()),
+            Err(e) => eprintln!("Failed to write HTML report {}: {}", hpath.display(), e),
         }
-        if let Ok(json) = serde_json::to_string_pretty(&all_scans) {
-            if let Err(e) = std::fs::write(json_path, json) {
-                eprintln!("Failed to write dedcore_report.json: {}", e);
-            } else {
-                println!("Appended scan to dedcore_report.json");
-            }
+    }
+    if args.append_report {
+        match report::append_to_history(&report, "dedcore_report.json", "dedcore_report.html") {
+            Ok(()) => println!("Appended scan to dedcore_report.json and dedcore_report.html"),
+            Err(e) => eprintln!("Failed to append scan to the report history: {}", e),
         }
-        // HTML append
-        let html_path = "dedcore_report.html";
-        let mut html = if let Ok(existing) = std::fs::read_to_string(html_path) {
-            existing
-        } else {
-            String::from("<html><head><title>dedcore Report</title></head><body>")
-        };
-        html.push_str(&format!("<h2>Scan at {}</h2>", timestamp));
-        html.push_str("<table border=1><tr><th>File</th><th>Hash</th><th>Algorithm</th></tr>");
-        for r in &report {
-            html.push_str(&format!(
-                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
-                r.file, r.hash, r.algorithm
-            ));
+    }
+    ExitCode::SUCCESS
+}
+
+/// Walk and scan, along with the listed `files`, showing one progress bar
+/// that grows as the walk finds files and restarts for every later stage.
+fn scan_with_progress_bar(scanner: &Scanner, files: Vec<PathBuf>) -> ScanReport {
+    let pb = ProgressBar::new(0);
+    pb.set_style(
+        ProgressStyle::with_template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}")
+            .unwrap()
+            .progress_chars("##-"),
+    );
+    let report = scanner.run_with_files(files, |p| match p {
+        ScanProgress::Stage { stage, total } => {
+            pb.set_length(total as u64);
+            pb.set_position(0);
+            pb.set_message(stage.to_string());
         }
-        html.push_str("</table><br/>");
-        if potential_space_savings > 0 {
-            html.push_str(&format!(
-                "<p>Potential space savings: {:.2} MB</p>",
-                potential_space_savings as f64 / 1024.0 / 1024.0
-            ));
+        ScanProgress::Found => pb.inc_length(1),
+        ScanProgress::Advanced => pb.inc(1),
+    });
+    pb.finish_with_message("done");
+    report
+}
+
+/// Keep the first file of every duplicate group and quarantine the rest,
+/// leaving reference files alone.
+fn quarantine_duplicates(qm: &mut QuarantineManager, report: &ScanReport) -> usize {
+    let mut quarantined = 0;
+    for group in &report.duplicate_groups {
+        if !group.files[0].exists() {
+            println!("Skipping group of {}: the copy to keep is gone", group.files[0].display());
+            continue;
         }
-        if let Err(e) = std::fs::write(html_path, html.clone()) {
-            eprintln!("Failed to write dedcore_report.html: {}", e);
-        } else {
-            println!("Appended scan to dedcore_report.html");
+        // Files in a duplicate directory quarantined earlier are already gone
+        for file in group.removable().filter(|f| f.exists()) {
+            // Moving one name of a hardlinked file away frees nothing
+            let links = fs::metadata(file).map(|m| link_count(&m)).unwrap_or(1);
+            if links > 1 {
+                println!(
+                    "Skipping {}: it has {} hardlinks, quarantining it would free no space",
+                    file.display(),
+                    links
+                );
+                continue;
+            }
+            match qm.quarantine_file(file) {
+                Ok(_) => quarantined += 1,
+                Err(e) => println!("Failed to quarantine {}: {}", file.display(), e),
+            }
         }
     }
-    println!("\nProcessed {} files.", results.len());
+    quarantined
+}
 
-    if !algo_summary.is_empty() {
-        println!("\nAlgorithm selection summary:");
-        println!("{:<12} | {}", "File Type", "Algorithm");
-        println!("{:-<12}-+-{:-<10}", "", "");
-        for (ext, algo) in &algo_summary {
-            println!("{:<12} | {}", ext, algo);
+/// Keep the first directory of every directory group and quarantine the rest.
+///
+/// Returns the number of directories and of files in them that were quarantined.
+fn quarantine_directories(qm: &mut QuarantineManager, report: &ScanReport) -> (usize, usize) {
+    let (mut dirs, mut files) = (0, 0);
+    for group in &report.directory_groups {
+        if !group.dirs[0].exists() {
+            println!("Skipping group of {}: the directory to keep is gone", group.dirs[0].display());
+            continue;
+        }
+        for dir in group.removable() {
+            match qm.quarantine_directory(dir) {
+                Ok(n) => {
+                    dirs += 1;
+                    files += n;
+                }
+                Err(e) => println!("Failed to quarantine {}: {}", dir.display(), e),
+            }
         }
     }
+    (dirs, files)
+}
 
-    // Group duplicates
-    let mut duplicate_groups: Vec<Vec<String>> = Vec::new();
-    let mut potential_space_savings: u64 = 0;
-    for files in hash_to_files.values() {
-        if files.len() > 1 {
-            // Calculate space savings: sum size of all but one file
-            let mut group_size = 0;
-            for file in &files[1..] {
-                if let Ok(meta) = fs::metadata(file) {
-                    group_size += meta.len();
-                }
+/// Replace all but the first file of every group with a link to it: a symlink
+/// in the given mode, or a hardlink when there is none. Reference files are left alone.
+///
+/// Returns how many files were replaced, and the files left alone with the reason why.
+fn link_duplicates(
+    qm: &mut QuarantineManager,
+    report: &ScanReport,
+    symlink: Option<SymlinkMode>,
+) -> (usize, Vec<(PathBuf, String)>) {
+    let mut linked = 0;
+    let mut skipped = Vec::new();
+    for group in &report.duplicate_groups {
+        let keep = &group.files[0];
+        let keep_dev = fs::metadata(keep).ok().and_then(|m| file_id(&m)).map(|(dev, _)| dev);
+        for file in group.removable() {
+            let dev = fs::metadata(file).ok().and_then(|m| file_id(&m)).map(|(dev, _)| dev);
+            if dev.is_some() && keep_dev.is_some() && dev != keep_dev {
+                skipped.push((file.clone(), format!("on a different filesystem than {}", keep.display())));
+                continue;
+            }
+            let replaced = match symlink {
+                Some(mode) => qm.replace_with_symlink(file, keep, mode == SymlinkMode::Relative),
+                None => qm.replace_with_hardlink(file, keep),
+            };
+            match replaced {
+                Ok(()) => linked += 1,
+                Err(e) => skipped.push((file.clone(), e.to_string())),
             }
-            potential_space_savings += group_size;
-            duplicate_groups.push(files.clone());
         }
     }
-    if potential_space_savings > 0 {
+    (linked, skipped)
+}
+
+/// `  [target]` suffix naming the target a file came from, when there are several.
+fn root_label(report: &ScanReport, file: &Path) -> String {
+    match report.root_of(file) {
+        Some(root) if report.roots.len() > 1 => format!("  [{}]", root.given.display()),
+        _ => String::new(),
+    }
+}
+
+/// Summarize the links met during a walk, listing the ones that lead nowhere.
+fn print_symlinks(symlinks: &[Symlink]) {
+    if symlinks.is_empty() {
+        return;
+    }
+    let count = |status: LinkStatus| symlinks.iter().filter(|l| l.status == status).count();
+    println!("\n{} symbolic links; links are never counted as duplicates of what they point to.", symlinks.len());
+    let followed = count(LinkStatus::Followed);
+    if followed > 0 {
+        println!("  {} followed, their targets scanned once under their own paths", followed);
+    }
+    let not_followed = count(LinkStatus::NotFollowed);
+    if not_followed > 0 {
+        println!("  {} not followed (--follow-symlinks always scans what they point to)", not_followed);
+    }
+    for link in symlinks.iter().filter(|l| matches!(l.status, LinkStatus::Loop | LinkStatus::Broken)) {
+        println!("  {} -> {} ({})", link.path.display(), link.target.display(), link.status);
+    }
+}
+
+/// Print the human-readable summary of a scan.
+pub fn print_scan_report(report: &ScanReport, args: &ScanArgs) {
+    let stats = &report.stats;
+    if stats.cache_hits > 0 {
         println!(
-            "Potential space savings: {:.2} MB",
-            potential_space_savings as f64 / 1024.0 / 1024.0
+            "Skipped {} unchanged files due to incremental scanning.",
+            stats.cache_hits
         );
     }
+    println!("\nProcessed {} files.", stats.files_scanned);
+    println!(
+        "  {} ruled out by size, {} by partial hash, {} fully hashed.",
+        stats.unique_size, stats.unique_partial, stats.files_hashed
+    );
 
-    if app.quarantine_all_dupes && !duplicate_groups.is_empty() {
-        println!("\nFound {} groups of duplicates.", duplicate_groups.len());
-        let mut qm = QuarantineManager::new().expect("Failed to create QuarantineManager");
-        let mut quarantined = 0;
-        for group in &duplicate_groups {
-            // Keep the first file, quarantine the rest
-            for file in &group[1..] {
-                match qm.quarantine_file(file) {
-                    Ok(_) => quarantined += 1,
-                    Err(e) => println!("Failed to quarantine {}: {}", file, e),
-                }
-            }
+    if !report.algorithm.is_empty() {
+        println!("Digest algorithm: {} (all file types)", report.algorithm);
+    }
+    if stats.spilled_bytes > 0 {
+        println!("File index spilled {} bytes to disk.", stats.spilled_bytes);
+    }
+    if !report.ignore_sources.is_empty() {
+        println!("Ignore rules from:");
+        for source in &report.ignore_sources {
+            println!("  {}", source.display());
         }
-        println!("Quarantined {} duplicate files.", quarantined);
     }
-
-    // === Content Similarity for Text Files ===
-    // Optimization: Bucket by file size to avoid O(n^2) on large sets
-    let text_exts = [
-        "txt", "md", "rs", "py", "toml", "json", "csv", "log", "cfg", "ini", "yaml", "yml",
-    ];
-    let mut text_buckets: std::collections::HashMap<u64, Vec<&String>> =
-        std::collections::HashMap::new();
-    for f in files.iter().filter(|f| {
-        let ext = std::path::Path::new(f)
-            .extension()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_lowercase();
-        text_exts.contains(&ext.as_str())
-    }) {
-        // Only consider files not in any duplicate group
-        if !hash_to_files
-            .values()
-            .any(|group| group.contains(f) && group.len() > 1)
-        {
-            let size = std::fs::metadata(f).map(|m| m.len()).unwrap_or(0);
-            text_buckets.entry(size).or_default().push(f);
+    if !report.skipped_mounts.is_empty() {
+        println!("Did not descend into:");
+        for mount in &report.skipped_mounts {
+            println!("  {} ({})", mount.path.display(), mount.fstype.as_deref().unwrap_or("another filesystem"));
         }
     }
-    let mut similar_groups: Vec<Vec<String>> = Vec::new();
-    let mut visited = std::collections::HashSet::new();
-    let similarity_threshold = app.similarity_threshold;
-    // Use Rayon for parallel similarity if there are many files in a bucket
-    for (_size, bucket) in text_buckets.iter() {
-        if bucket.len() < 2 {
-            continue;
+
+    if !report.directory_groups.is_empty() {
+        println!("\n=== Duplicate Directories ===");
+        for (i, group) in report.directory_groups.iter().enumerate() {
+            println!(
+                "Group {} ({} directories, {} files and {} bytes each):",
+                i + 1,
+                group.dirs.len(),
+                group.files,
+                group.size
+            );
+            for (j, d) in group.dirs.iter().enumerate() {
+                let keep = if j == 0 {
+                    format!("  (keep: {})", group.kept_by)
+                } else if group.reference.contains(d) {
+                    "  (reference)".to_string()
+                } else {
+                    String::new()
+                };
+                println!("  {}/{}{}", d.display(), root_label(report, d), keep);
+            }
         }
-        let pairs: Vec<_> = (0..bucket.len())
-            .flat_map(|i| (i + 1..bucket.len()).map(move |j| (i, j)))
-            .collect();
-        let results: Vec<_> = if bucket.len() > 20 {
-            use rayon::prelude::*;
-            pairs
-                .par_iter()
-                .map(|&(i, j)| {
-                    let f1 = bucket[i];
-                    let f2 = bucket[j];
-                    let text1 = std::fs::read_to_string(f1).unwrap_or_default();
-                    let text2 = std::fs::read_to_string(f2).unwrap_or_default();
-                    let max_len = text1.len().max(text2.len());
-                    if max_len == 0 {
-                        return None;
-                    }
-                    let dist = crate::similarity::levenshtein(&text1, &text2);
-                    let similarity = 1.0 - (dist as f64 / max_len as f64);
-                    if similarity >= similarity_threshold as f64 {
-                        Some((f1, f2))
-                    } else {
-                        None
-                    }
-                })
-                .collect()
-        } else {
-            pairs
-                .iter()
-                .map(|&(i, j)| {
-                    let f1 = bucket[i];
-                    let f2 = bucket[j];
-                    let text1 = std::fs::read_to_string(f1).unwrap_or_default();
-                    let text2 = std::fs::read_to_string(f2).unwrap_or_default();
-                    let max_len = text1.len().max(text2.len());
-                    if max_len == 0 {
-                        return None;
-                    }
-                    let dist = crate::similarity::levenshtein(&text1, &text2);
-                    let similarity = 1.0 - (dist as f64 / max_len as f64);
-                    if similarity >= similarity_threshold as f64 {
-                        Some((f1, f2))
-                    } else {
-                        None
-                    }
-                })
-                .collect()
-        };
-        for pair in results.into_iter().flatten() {
-            let (f1, f2) = pair;
-            if visited.contains(f1) || visited.contains(f2) {
-                continue;
+    }
+
+    // Groups inside duplicated directories are covered by the directory listing
+    let shown: Vec<_> = report.duplicate_groups.iter().filter(|g| !g.in_duplicate_dir).collect();
+    let collapsed = report.duplicate_groups.len() - shown.len();
+    if !shown.is_empty() {
+        println!("\n=== Duplicate File Groups ===");
+        for (i, group) in shown.into_iter().enumerate() {
+            let verified = if group.verified { ", verified" } else { "" };
+            println!(
+                "Group {} ({} files, {} bytes each{}):",
+                i + 1,
+                group.files.len(),
+                group.size,
+                verified
+            );
+            for (j, f) in group.files.iter().enumerate() {
+                let keep = if j == 0 {
+                    format!("  (keep: {})", group.kept_by)
+                } else if group.reference.contains(f) {
+                    "  (reference)".to_string()
+                } else {
+                    String::new()
+                };
+                println!("  {}{}{}", f.display(), root_label(report, f), keep);
+            }
+            for f in &group.hardlinks {
+                println!("  {}{} (hardlink, shares storage)", f.display(), root_label(report, f));
             }
-            similar_groups.push(vec![f1.to_string(), f2.to_string()]);
-            visited.insert(f1);
-            visited.insert(f2);
         }
     }
-    if !similar_groups.is_empty() {
+    if collapsed > 0 {
+        println!("{} more file groups lie entirely inside the duplicate directories above.", collapsed);
+    }
+    if !report.hardlink_sets.is_empty() {
         println!(
-            "\n=== Similar Text File Groups (>= {:.0}% similar) ===",
-            similarity_threshold * 100.0
+            "\n{} files are hardlinks already sharing storage; they are not counted as duplicates.",
+            stats.hardlinked_files
         );
-        for (i, group) in similar_groups.iter().enumerate() {
-            println!("Group {}:", i + 1);
-            for f in group {
-                println!("  {}", f);
-            }
-            println!("");
+        for set in &report.hardlink_sets {
+            let files: Vec<String> = set.files.iter().map(|f| f.display().to_string()).collect();
+            println!("  {}", files.join(" = "));
         }
     }
-
-    // === Image Similarity for Image Files ===
-    // Optimization: Bucket by file size to avoid O(n^2) on large sets
-    let image_exts = ["jpg", "jpeg", "png", "bmp", "gif", "webp"];
-    let mut image_buckets: std::collections::HashMap<u64, Vec<&String>> =
-        std::collections::HashMap::new();
-    for f in files.iter().filter(|f| {
-        let ext = std::path::Path::new(f)
-            .extension()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_lowercase();
-        image_exts.contains(&ext.as_str())
-    }) {
-        if !hash_to_files
-            .values()
-            .any(|group| group.contains(f) && group.len() > 1)
-        {
-            let size = std::fs::metadata(f).map(|m| m.len()).unwrap_or(0);
-            image_buckets.entry(size).or_default().push(f);
+    print_symlinks(&report.symlinks);
+    if stats.wasted_bytes > 0 {
+        println!(
+            "Potential space savings: {:.2} MB",
+            stats.wasted_bytes as f64 / 1024.0 / 1024.0
+        );
+    }
+    if report.owners.len() > 1 {
+        println!("\n=== Duplicate Bytes by Owner ===");
+        for owner in &report.owners {
+            println!(
+                "  {:<16} {:>6} copies {:>10.2} MB",
+                owner.label(),
+                owner.files,
+                owner.bytes as f64 / 1024.0 / 1024.0
+            );
         }
     }
-    let mut similar_image_groups: Vec<Vec<(String, f32)>> = Vec::new();
-    let mut visited_images = std::collections::HashSet::<String>::new();
-    let image_similarity_threshold = app.image_similarity_threshold;
-    let algorithm = app.image_hash_algorithm;
-    
-    for (_size, bucket) in image_buckets.iter() {
-        if bucket.len() < 2 {
-            continue;
+
+    if !report.collisions.is_empty() {
+        println!("\n=== Hash Collisions (same digest, different content) ===");
+        for c in &report.collisions {
+            println!("{} ({} bytes each):", c.hash, c.size);
+            for f in &c.files {
+                println!("  {}", f.display());
+            }
         }
-        
-        let pairs: Vec<_> = (0..bucket.len())
-            .flat_map(|i| (i + 1..bucket.len()).map(move |j| (i, j)))
-            .collect();
-            
-        let results: Vec<(String, String, f32)> = if bucket.len() > 20 {
-            use rayon::prelude::*;
-            pairs
-                .par_iter()
-                .filter_map(|&(i, j)| {
-                    let f1 = bucket[i];
-                    let f2 = bucket[j];
-                    compare_images_with_algorithm(f1, f2, algorithm)
-                        .filter(|&sim| sim >= image_similarity_threshold)
-                        .map(|sim| (f1.to_string(), f2.to_string(), sim))
-                })
-                .collect()
-        } else {
-            pairs
-                .iter()
-                .filter_map(|&(i, j)| {
-                    let f1 = bucket[i];
-                    let f2 = bucket[j];
-                    compare_images_with_algorithm(f1, f2, algorithm)
-                        .filter(|&sim| sim >= image_similarity_threshold)
-                        .map(|sim| (f1.to_string(), f2.to_string(), sim))
-                })
-                .collect()
-        };
-        for (f1, f2, similarity) in results.into_iter() {
-            if visited_images.contains(&f1) || visited_images.contains(&f2) {
-                continue;
+        println!("Only files that compared equal byte by byte are listed as duplicates.");
+    }
+
+    if !report.similar_text_groups.is_empty() {
+        println!(
+            "\n=== Similar Text File Groups (>= {:.0}% similar) ===",
+            args.similarity_threshold * 100.0
+        );
+        for (i, group) in report.similar_text_groups.iter().enumerate() {
+            println!("Group {}:", i + 1);
+            for f in &group.files {
+                println!("  {} ({:.1}%)", f.file.display(), f.similarity * 100.0);
             }
-            similar_image_groups.push(vec![(f1.clone(), 1.0), (f2.clone(), similarity)]);
-            visited_images.insert(f1);
-            visited_images.insert(f2);
+            println!();
         }
     }
-    if !similar_image_groups.is_empty() {
+
+    if !report.similar_image_groups.is_empty() {
         println!(
             "\n=== Similar Image File Groups (>= {:.0}% similar) ===",
-            image_similarity_threshold * 100.0
+            args.image_similarity_threshold * 100.0
         );
-        for (i, group) in similar_image_groups.iter().enumerate() {
+        for (i, group) in report.similar_image_groups.iter().enumerate() {
             println!("Group {}:", i + 1);
-            for (f, sim) in group {
-                println!("  {} ({:.0}%)", f, sim * 100.0);
+            for f in &group.files {
+                println!("  {} ({:.0}%)", f.file.display(), f.similarity * 100.0);
             }
-            println!("");
+            println!();
         }
     }
 
-    // Add similar image groups to JSON/HTML reports if enabled
-    let similar_image_groups_for_report: Vec<Vec<serde_json::Value>> = similar_image_groups
+    print_file_errors(&report.errors);
+}
+
+/// List files that could not be read, with the reason for each.
+fn print_file_errors(errors: &[FileError]) {
+    if errors.is_empty() {
+        return;
+    }
+    println!("\n=== Files That Could Not Be Read ({}) ===", errors.len());
+    for e in errors {
+        println!("  {}: {}", e.file.display(), e.reason);
+    }
+    println!("These files were left out of duplicate detection.");
+}
+
+
+pub fn run_hash(args: &HashArgs) -> ExitCode {
+    let config = args.hash.config();
+    let (roots, missing) = resolve_targets(&args.targets);
+    let files = collect_roots(&roots, &args.filters);
+    for target in &missing {
+        eprintln!("No valid file or directory found for: {}", target.display());
+    }
+    if files.is_empty() {
+        eprintln!("No files found to hash.");
+        return ExitCode::FAILURE;
+    }
+    // Same algorithm for every file so the printed digests can be compared
+    let algo = config.canonical_algorithm();
+    let results: Vec<Result<FileHash, FileError>> = files
+        .par_iter()
+        .map(|f| {
+            hash_file(f, algo)
+                .map(|hash| FileHash {
+                    root: root_of(&roots, f).map(|r| r.given.clone()).unwrap_or_default(),
+                    file: f.clone(),
+                    hash: hex::encode(hash),
+                    algorithm: algo.to_string(),
+                })
+                .map_err(|e| FileError { file: f.clone(), reason: e.to_string() })
+        })
+        .collect();
+    let mut hashes = Vec::new();
+    let mut errors: Vec<FileError> = missing
         .iter()
-        .map(|group| {
-            group
-                .iter()
-                .map(|(f, sim)| serde_json::json!({"file": f, "similarity": (sim * 100.0) as u8}))
-                .collect()
+        .map(|target| FileError {
+            file: target.clone(),
+            reason: "No such file or directory".to_string(),
         })
         .collect();
-    if let Some(ref jpath) = app.json_report {
-        let report_json = serde_json::to_value(&report).unwrap_or(serde_json::json!([]));
-        let mut obj = serde_json::Map::new();
-        obj.insert("file_hash_report".to_string(), report_json);
-        obj.insert(
-            "duplicate_groups".to_string(),
-            serde_json::json!(duplicate_groups),
-        );
-        obj.insert(
-            "similar_image_groups".to_string(),
-            serde_json::json!(similar_image_groups_for_report),
-        );
-        if let Err(_e) = fs::write(jpath, serde_json::to_string_pretty(&obj).unwrap()) {
-            eprintln!("Failed to write JSON report");
-        } else {
-            println!("JSON report written to {}", jpath);
+    for result in results {
+        match result {
+            Ok(hash) => hashes.push(hash),
+            Err(e) => errors.push(e),
         }
     }
-    if let Some(ref hpath) = app.html_report {
-        let mut html = String::from(
-            "<html><head><title>dedcore Report</title></head><body><h1>dedcore File Hash Report</h1><table border=1><tr><th>File</th><th>Hash</th><th>Algorithm</th></tr>",
-        );
-        for r in &report {
-            html.push_str(&format!(
-                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
-                r.file, r.hash, r.algorithm
-            ));
-        }
-        html.push_str("</table>");
-        if !duplicate_groups.is_empty() {
-            html.push_str("<h2>Duplicate File Groups</h2>");
-            for (i, group) in duplicate_groups.iter().enumerate() {
-                html.push_str(&format!("<b>Group {}</b><ul>", i + 1));
-                for f in group {
-                    html.push_str(&format!("<li>{}</li>", f));
+    for r in &hashes {
+        println!("{}  {:<8} {}", r.hash, r.algorithm, r.file.display());
+    }
+    // Missing targets were already reported above
+    for e in &errors[missing.len()..] {
+        eprintln!("Failed to hash {}: {}", e.file.display(), e.reason);
+    }
+    if let Some(ref jpath) = args.json_report {
+        let report = serde_json::json!({ "hashes": hashes, "errors": errors });
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => {
+                if let Err(e) = fs::write(jpath, json) {
+                    eprintln!("Failed to write JSON report: {}", e);
+                    return ExitCode::FAILURE;
                 }
-                html.push_str("</ul>");
+                println!("JSON report written to {}", jpath.display());
             }
-        }
-        if !similar_image_groups.is_empty() {
-            html.push_str("<h2>Similar Image File Groups</h2>");
-            for (i, group) in similar_image_groups.iter().enumerate() {
-                html.push_str(&format!("<b>Group {}</b><ul>", i + 1));
-                for (f, sim) in group {
-                    html.push_str(&format!("<li>{} ({:.0}%)</li>", f, sim * 100.0));
-                }
-                html.push_str("</ul>");
+            Err(e) => {
+                eprintln!("Failed to serialize JSON report: {}", e);
+                return ExitCode::FAILURE;
             }
         }
-        html.push_str("</body></html>");
-        if let Err(_e) = fs::write(hpath, html) {
-            eprintln!("Failed to write HTML report: {}", hpath);
-        } else {
-            println!("HTML report written to {}", hpath);
-        }
     }
+    if errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
 
-    if let Some(cmd) = &app.cmd {
-        match cmd {
-            AppCmd::Quarantine(qcmd) => match qcmd {
-                QuarantineCmd::File { file } => {
-                    let mut qm =
-                        QuarantineManager::new().expect("Failed to create QuarantineManager");
-                    match qm.quarantine_file(file) {
-                        Ok(_) => println!("File quarantined: {}", file),
-                        Err(e) => eprintln!("Failed to quarantine file: {}: {}", file, e),
-                    }
-                }
-                QuarantineCmd::Commit => {
-                    let mut qm =
-                        QuarantineManager::new().expect("Failed to create QuarantineManager");
-                    match qm.commit_deletions() {
-                        Ok(count) => println!("{} quarantined files permanently deleted.", count),
-                        Err(e) => eprintln!("Failed to commit deletions: {}", e),
-                    }
+pub fn run_compare(args: &CompareArgs) -> ExitCode {
+    let algo = args.hash.config().canonical_algorithm();
+    let mut comparison = match compare(args.a.clone(), args.b.clone(), &args.filters, algo) {
+        Ok(comparison) => comparison,
+        Err(e) => {
+            eprintln!("Cannot compare: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let sets = match args.only {
+        Some(set) => vec![set],
+        None => vec![CompareSet::OnlyA, CompareSet::OnlyB, CompareSet::Common],
+    };
+    match args.format {
+        CompareFormat::Json => {
+            if !sets.contains(&CompareSet::OnlyA) {
+                comparison.only_a.clear();
+            }
+            if !sets.contains(&CompareSet::OnlyB) {
+                comparison.only_b.clear();
+            }
+            if !sets.contains(&CompareSet::Common) {
+                comparison.common.clear();
+            }
+            match serde_json::to_string_pretty(&comparison) {
+                Ok(json) => println!("{}", json),
+                Err(e) => {
+                    eprintln!("Failed to serialize comparison: {}", e);
+                    return ExitCode::FAILURE;
                 }
-                QuarantineCmd::Rollback => {
-                    let mut qm =
-                        QuarantineManager::new().expect("Failed to create QuarantineManager");
-                    match qm.rollback() {
-                        Ok(count) => println!("{} quarantined files restored.", count),
-                        Err(e) => eprintln!("Failed to rollback quarantined files: {}", e),
+            }
+        }
+        CompareFormat::Paths => {
+            for set in &sets {
+                for path in set.paths(&comparison) {
+                    match args.only {
+                        Some(_) => println!("{}", path.display()),
+                        None => println!("{}\t{}", set.label(), path.display()),
                     }
                 }
-                QuarantineCmd::List => {
-                    let qm = QuarantineManager::new().expect("Failed to create QuarantineManager");
-                    let files = qm.list_quarantined_files();
-                    if files.is_empty() {
-                        println!("No files are currently quarantined.");
-                    } else {
-                        println!("Currently quarantined files:");
-                        for rec in files {
-                            println!("{} ({} bytes)", rec.original_path, rec.file_size);
-                        }
-                    }
+            }
+        }
+        CompareFormat::Text => print_comparison(&comparison, args, &sets),
+    }
+    for e in &comparison.errors {
+        eprintln!("Failed to read {}: {}", e.file.display(), e.reason);
+    }
+    if comparison.errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn print_comparison(comparison: &Comparison, args: &CompareArgs, sets: &[CompareSet]) {
+    println!("A: {}", args.a.display());
+    println!("B: {}", args.b.display());
+    println!("Digest algorithm: {}", comparison.algorithm);
+    for set in sets {
+        match set {
+            CompareSet::OnlyA => {
+                println!("\n=== Only in A ({}) ===", comparison.only_a.len());
+                for f in &comparison.only_a {
+                    println!("  {}", f.display());
                 }
-                QuarantineCmd::Restore { original_path } => {
-                    let mut qm =
-                        QuarantineManager::new().expect("Failed to create QuarantineManager");
-                    let files: Vec<_> = qm.list_quarantined_files().into_iter().cloned().collect();
-                    let rec = files.iter().find(|rec| rec.original_path == *original_path);
-                    if let Some(rec) = rec {
-                        let quarantine_path = &rec.quarantine_path;
-                        let original_path = &rec.original_path;
-                        if std::path::Path::new(quarantine_path).exists() {
-                            if let Some(parent) = std::path::Path::new(original_path).parent() {
-                                let _ = std::fs::create_dir_all(parent);
-                            }
-                            match std::fs::rename(quarantine_path, original_path) {
-                                Ok(_) => {
-                                    println!("Restored {}", original_path);
-                                    let _ = qm.remove_quarantined_file(original_path);
-                                }
-                                Err(e) => println!("Failed to restore {}: {}", original_path, e),
-                            }
-                        } else {
-                            println!("Quarantined file not found: {}", quarantine_path);
-                        }
-                    } else {
-                        println!("No quarantined entry found for {}", original_path);
-                    }
+            }
+            CompareSet::OnlyB => {
+                println!("\n=== Only in B ({}) ===", comparison.only_b.len());
+                for f in &comparison.only_b {
+                    println!("  {}", f.display());
                 }
-            },
-            AppCmd::Recovery(rcmd) => match rcmd {
-                RecoveryCmd::List => {
-                    let log = crate::safety::QuarantineManager::read_recovery_log();
-                    if log.is_empty() {
-                        println!("No recovery history found.");
-                    } else {
-                        for entry in &log {
-                            let ts = entry
-                                .get("timestamp")
-                                .and_then(|v| v.as_str())
-                                .unwrap_or("");
-                            let action = entry.get("action").and_then(|v| v.as_str()).unwrap_or("");
-                            let path = entry
-                                .get("original_path")
-                                .and_then(|v| v.as_str())
-                                .unwrap_or("");
-                            println!("{} | {} | {}", ts, action, path);
-                        }
+            }
+            CompareSet::Common => {
+                println!("\n=== In Both ({} distinct contents) ===", comparison.common.len());
+                for c in &comparison.common {
+                    println!("  {} bytes:", c.size);
+                    for f in &c.a {
+                        println!("    A  {}", f.display());
                     }
-                }
-                RecoveryCmd::Restore { original_path } => {
-                    let log = crate::safety::QuarantineManager::read_recovery_log();
-                    let entry = log.iter().find(|e| {
-                        e.get("original_path").and_then(|v| v.as_str())
-                            == Some(original_path.as_str())
-                            && e.get("action").and_then(|v| v.as_str()) == Some("quarantined")
-                    });
-                    if let Some(entry) = entry {
-                        let quarantine_path = entry
-                            .get("quarantine_path")
-                            .and_then(|v| v.as_str())
-                            .unwrap();
-                        if std::path::Path::new(quarantine_path).exists() {
-                            if let Some(parent) = std::path::Path::new(original_path).parent() {
-                                let _ = std::fs::create_dir_all(parent);
-                            }
-                            match std::fs::rename(quarantine_path, original_path) {
-                                Ok(_) => println!("Restored {}", original_path),
-                                Err(e) => println!("Failed to restore: {}", e),
-                            }
-                        } else {
-                            println!("Quarantined file not found: {}", quarantine_path);
-                        }
-                    } else {
-                        println!("No quarantined entry found for {}", original_path);
+                    for f in &c.b {
+                        println!("    B  {}", f.display());
                     }
                 }
-            },
+            }
         }
-        return;
     }
 }
 
-#[allow(dead_code)]
-pub fn run() {
-    let args: Vec<String> = std::env::args().collect();
-    run_with_args(args.iter());
-}
-
-#[allow(dead_code)]
-pub fn run_with_ui(path: String, security: String, speed: String) {
-    use crate::hashing::{HashConfig, Security, Speed};
-    use std::path::Path;
-    let security = match security.to_lowercase().as_str() {
-        "low" => Security::Low,
-        "medium" => Security::Medium,
-        "maximum" => Security::Maximum,
-        _ => Security::High,
-    };
-    let speed = match speed.to_lowercase().as_str() {
-        "fastest" => Speed::Fastest,
-        "mostsecure" => Speed::MostSecure,
-        _ => Speed::Balanced,
+/// Exits with failure when any source file is missing, modified or unreadable.
+pub fn run_verify_backup(args: &VerifyBackupArgs) -> ExitCode {
+    let algo = args.hash.config().canonical_algorithm();
+    let cache = Path::new(".dedcore_cache.json");
+    let check = match verify_backup(args.source.clone(), args.backup.clone(), &args.filters, algo, Some(cache)) {
+        Ok(check) => check,
+        Err(e) => {
+            eprintln!("Cannot verify backup: {}", e);
+            return ExitCode::FAILURE;
+        }
     };
-    let default_policy = HashConfig::new(security, speed);
-    let mut files: Vec<String> = Vec::new();
-    let scan_target;
-    if Path::new(&path).is_dir() {
-        scan_target = format!("directory: {}", path);
-        files = collect_files_recursively_with_filter(&path, None, None, None, None, None, None);
-    } else if Path::new(&path).is_file() {
-        scan_target = format!("file: {}", path);
-        files.push(path.clone());
+    if args.json {
+        match serde_json::to_string_pretty(&check) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Failed to serialize the result: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
     } else {
-        eprintln!("No valid file or directory found for: {}", path);
-        return;
+        print_backup_check(&check, args);
     }
-    if files.is_empty() {
-        eprintln!("No files found to hash.");
-        return;
+    if check.is_complete() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
     }
-    println!("\n=== DEDCORE File Hasher ===");
-    println!("Scanning {}", scan_target);
-    println!("Files to process: {}\n", files.len());
-    let pb = indicatif::ProgressBar::new(files.len() as u64);
-    pb.set_style(
-        indicatif::ProgressStyle::with_template(
-            "[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
-        )
-        .unwrap()
-        .progress_chars("##-"),
+}
+
+fn print_backup_check(check: &BackupCheck, args: &VerifyBackupArgs) {
+    println!("Source: {}", args.source.display());
+    println!("Backup: {}", args.backup.display());
+    println!(
+        "Digest algorithm: {} ({} files hashed, {} from cache)",
+        check.algorithm, check.files_hashed, check.cache_hits
     );
-    let mut hash_to_files: HashMap<Vec<u8>, Vec<String>> = HashMap::new();
-    let mut algo_summary: std::collections::BTreeMap<String, String> =
-        std::collections::BTreeMap::new();
-    for f in &files {
-        let ext = Path::new(f)
-            .extension()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_string();
-        let algo = default_policy.choose_algorithm(&ext);
-        println!(
-            "[INFO] Using {:?} for '{}' (type: '{}', security: {:?}, speed: {:?})",
-            algo, f, ext, default_policy.security, default_policy.speed
-        );
-        let hash = crate::hashing::hash_file(f, algo.clone()).unwrap_or_default();
-        hash_to_files
-            .entry(hash.clone())
-            .or_default()
-            .push(f.clone());
-        if !ext.is_empty() {
-            algo_summary
-                .entry(ext.clone())
-                .or_insert_with(|| format!("{:?}", algo));
+    if !check.missing.is_empty() {
+        println!("\n=== Missing From Backup ({}) ===", check.missing.len());
+        for e in &check.missing {
+            println!("  {}  {}", e.hash, e.file.display());
         }
-        pb.inc(1);
     }
-    pb.finish_with_message("done");
-    if !algo_summary.is_empty() {
-        println!("\nAlgorithm selection summary:");
-        println!("{:<12} | {}", "File Type", "Algorithm");
-        println!("{:-<12}-+-{:-<10}", "", "");
-        for (ext, algo) in &algo_summary {
-            println!("{:<12} | {}", ext, algo);
+    if !check.modified.is_empty() {
+        println!("\n=== Modified Since Backup ({}) ===", check.modified.len());
+        for m in &check.modified {
+            println!("  {}  {}", m.source.hash, m.source.file.display());
+            println!("  {}  {} (backup)", m.backup.hash, m.backup.file.display());
+        }
+    }
+    if !check.extra.is_empty() {
+        println!("\n=== Only In Backup ({}) ===", check.extra.len());
+        for e in &check.extra {
+            println!("  {}  {}", e.hash, e.file.display());
         }
     }
-    // Group duplicates
-    let mut duplicate_groups: Vec<Vec<String>> = Vec::new();
-    let mut potential_space_savings: u64 = 0;
-    for files in hash_to_files.values() {
-        if files.len() > 1 {
-            // Calculate space savings: sum size of all but one file
-            let mut group_size = 0;
-            for file in &files[1..] {
-                if let Ok(meta) = fs::metadata(file) {
-                    group_size += meta.len();
+    if !check.errors.is_empty() {
+        println!("\n=== Could Not Be Read ({}) ===", check.errors.len());
+        for e in &check.errors {
+            println!("  {}: {}", e.file.display(), e.reason);
+        }
+    }
+    println!(
+        "\n{} verified, {} missing, {} modified, {} extra.",
+        check.verified,
+        check.missing.len(),
+        check.modified.len(),
+        check.extra.len()
+    );
+    if check.is_complete() {
+        println!("Backup is complete.");
+    }
+}
+
+fn open_quarantine() -> Option<QuarantineManager> {
+    match QuarantineManager::new() {
+        Ok(qm) => Some(qm),
+        Err(e) => {
+            eprintln!("Failed to initialize quarantine system: {}", e);
+            None
+        }
+    }
+}
+
+pub fn run_quarantine(cmd: &QuarantineCmd) -> ExitCode {
+    match cmd {
+        QuarantineCmd::File { file } => {
+            let Some(mut qm) = open_quarantine() else { return ExitCode::FAILURE };
+            match qm.quarantine_file(file) {
+                Ok(_) => println!("File quarantined: {}", file.display()),
+                Err(e) => {
+                    eprintln!("Failed to quarantine file: {}: {}", file.display(), e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        QuarantineCmd::Commit => {
+            let Some(mut qm) = open_quarantine() else { return ExitCode::FAILURE };
+            match qm.commit_deletions() {
+                Ok(count) => println!("{} quarantined files permanently deleted.", count),
+                Err(e) => {
+                    eprintln!("Failed to commit deletions: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        QuarantineCmd::Rollback => {
+            let Some(mut qm) = open_quarantine() else { return ExitCode::FAILURE };
+            match qm.rollback() {
+                Ok(count) => println!("{} quarantined files restored.", count),
+                Err(e) => {
+                    eprintln!("Failed to rollback quarantined files: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        QuarantineCmd::List => {
+            let Some(qm) = open_quarantine() else { return ExitCode::FAILURE };
+            let files = qm.list_quarantined_files();
+            if files.is_empty() {
+                println!("No files are currently quarantined.");
+            } else {
+                println!("Currently quarantined files:");
+                for rec in files {
+                    let kind = if rec.is_dir { "directory, " } else { "" };
+                    println!("{} ({}{} bytes)", rec.original_path.display(), kind, rec.file_size);
+                }
+            }
+            let replaced = qm.list_replaced_files();
+            if !replaced.is_empty() {
+                println!("Files replaced by links:");
+                for rec in replaced {
+                    println!(
+                        "{} -> {} ({:?}, {} bytes)",
+                        rec.original_path.display(),
+                        rec.kept_path.display(),
+                        rec.kind,
+                        rec.file_size
+                    );
+                }
+            }
+        }
+        QuarantineCmd::Restore { original_path } => {
+            let Some(mut qm) = open_quarantine() else { return ExitCode::FAILURE };
+            if qm.replacement(original_path).is_some() {
+                return restore_replacement(&mut qm, original_path);
+            }
+            match qm.restore(original_path) {
+                Ok(()) => println!("Restored {}", original_path.display()),
+                Err(e) => {
+                    eprintln!("Failed to restore {}: {}", original_path.display(), e);
+                    return ExitCode::FAILURE;
                 }
             }
-            potential_space_savings += group_size;
-            duplicate_groups.push(files.clone());
         }
     }
-    if potential_space_savings > 0 {
-        println!(
-            "Potential space savings: {:.2} MB",
-            potential_space_savings as f64 / 1024.0 / 1024.0
-        );
+    ExitCode::SUCCESS
+}
+
+fn restore_replacement(qm: &mut QuarantineManager, original_path: &Path) -> ExitCode {
+    match qm.restore_replacement(original_path) {
+        Ok(()) => {
+            println!("Restored {} as an independent file", original_path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to restore {}: {}", original_path.display(), e);
+            ExitCode::FAILURE
+        }
     }
-    if !duplicate_groups.is_empty() {
-        println!("\nFound {} groups of duplicates.", duplicate_groups.len());
+}
+
+/// A path recorded in a recovery log entry, in either of the forms [`crate::paths`] writes.
+fn logged_path(entry: &serde_json::Value, key: &str) -> Option<PathBuf> {
+    let value = entry.get(key)?.clone();
+    serde_json::from_value::<LosslessPath>(value).ok().map(|p| p.0)
+}
+
+pub fn run_recovery(cmd: &RecoveryCmd) -> ExitCode {
+    match cmd {
+        RecoveryCmd::List => {
+            let log = crate::safety::QuarantineManager::read_recovery_log();
+            if log.is_empty() {
+                println!("No recovery history found.");
+            } else {
+                for entry in &log {
+                    let ts = entry
+                        .get("timestamp")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("");
+                    let action = entry.get("action").and_then(|v| v.as_str()).unwrap_or("");
+                    let path = logged_path(entry, "original_path").unwrap_or_default();
+                    println!("{} | {} | {}", ts, action, path.display());
+                }
+            }
+        }
+        RecoveryCmd::Restore { original_path } => {
+            let Some(mut qm) = open_quarantine() else { return ExitCode::FAILURE };
+            if qm.replacement(original_path).is_some() {
+                return restore_replacement(&mut qm, original_path);
+            }
+            if qm.list_quarantined_files().iter().any(|rec| rec.original_path == *original_path) {
+                return match qm.restore(original_path) {
+                    Ok(()) => {
+                        println!("Restored {}", original_path.display());
+                        ExitCode::SUCCESS
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to restore {}: {}", original_path.display(), e);
+                        ExitCode::FAILURE
+                    }
+                };
+            }
+            let log = crate::safety::QuarantineManager::read_recovery_log();
+            let entry = log.iter().find(|e| {
+                logged_path(e, "original_path").as_ref() == Some(original_path)
+                    && e.get("action").and_then(|v| v.as_str()) == Some("quarantined")
+            });
+            let Some(entry) = entry else {
+                eprintln!("No quarantined entry found for {}", original_path.display());
+                return ExitCode::FAILURE;
+            };
+            let quarantine_path = logged_path(entry, "quarantine_path").unwrap_or_default();
+            match qm.restore_unrecorded(&quarantine_path, original_path) {
+                Ok(()) => println!("Restored {}", original_path.display()),
+                Err(e) => {
+                    eprintln!("Failed to restore {}: {}", original_path.display(), e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+pub fn run_report(args: &ReportArgs) -> ExitCode {
+    let history = match fs::read_to_string(&args.history) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", args.history.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let scans: Vec<serde_json::Value> = match serde_json::from_str(&history) {
+        Ok(scans) => scans,
+        Err(e) => {
+            eprintln!("Failed to parse {}: {}", args.history.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if scans.is_empty() {
+        println!("No scans recorded in {}.", args.history.display());
+        return ExitCode::SUCCESS;
+    }
+    let skip = args.last.map_or(0, |n| scans.len().saturating_sub(n));
+    println!("{:<20} | {:>10} | {:>10} | {:>12}", "Timestamp", "Files", "Dup groups", "Savings (MB)");
+    println!("{:-<20}-+-{:->10}-+-{:->10}-+-{:->12}", "", "", "", "");
+    let mut total_savings = 0.0;
+    for scan in scans.iter().skip(skip) {
+        let ts = scan.get("timestamp").and_then(|v| v.as_str()).unwrap_or("");
+        let summary = scan.get("summary");
+        let field = |name: &str| summary.and_then(|s| s.get(name));
+        let processed = field("processed_files").and_then(|v| v.as_u64()).unwrap_or(0);
+        let groups = field("duplicate_groups").and_then(|v| v.as_u64()).unwrap_or(0);
+        let savings = field("potential_space_savings_mb").and_then(|v| v.as_f64()).unwrap_or(0.0);
+        total_savings += savings;
+        println!("{:<20} | {:>10} | {:>10} | {:>12.2}", ts, processed, groups, savings);
+    }
+    println!("\n{} scans, {:.2} MB of potential savings reported in total.", scans.len() - skip, total_savings);
+    ExitCode::SUCCESS
+}
+
+#[allow(dead_code)]
+pub fn run() -> ExitCode {
+    run_with_args(std::env::args_os())
+}
+
+#[allow(dead_code)]
+pub fn run_with_ui(path: String, security: String, speed: String) {
+    let security = Security::from_str(&security, true).unwrap_or(Security::High);
+    let speed = Speed::from_str(&speed, true).unwrap_or(Speed::Balanced);
+    let mut args = ScanArgs::new(vec![PathBuf::from(&path)]);
+    args.hash = HashOpts { security, speed };
+    // The user may choose to quarantine at the end
+    args.verify = true;
+    let policy = match args.keep_policy() {
+        Ok(policy) => policy,
+        Err(e) => {
+            eprintln!("Invalid keep policy in {}", e);
+            return;
+        }
+    };
+    let scanner = Scanner::new(args.scan_options().keep_policy(policy));
+    println!("\n=== DEDCORE File Hasher ===");
+    println!("Scanning {}\n", path);
+    let report = scan_with_progress_bar(&scanner, Vec::new());
+    if report.stats.files_scanned == 0 {
+        eprintln!("No files found to hash.");
+        return;
+    }
+    print_scan_report(&report, &args);
+    if !report.duplicate_groups.is_empty() {
+        println!("\nFound {} groups of duplicates.", report.duplicate_groups.len());
         if Confirm::new("Quarantine all duplicates (all but one per group)?")
             .with_default(false)
             .prompt()
             .unwrap_or(false)
         {
-            let mut qm = QuarantineManager::new().expect("Failed to create QuarantineManager");
-            let mut quarantined = 0;
-            for group in &duplicate_groups {
-                // Keep the first file, quarantine the rest
-                for file in &group[1..] {
-                    match qm.quarantine_file(file) {
-                        Ok(_) => quarantined += 1,
-                        Err(e) => println!("Failed to quarantine {}: {}", file, e),
-                    }
-                }
-            }
+            let Some(mut qm) = open_quarantine() else { return };
+            let quarantined = quarantine_duplicates(&mut qm, &report);
             println!("Quarantined {} duplicate files.", quarantined);
         }
     }
@@ -0,0 +1,169 @@
+//! Set operations on the content of two trees.
+//!
+//! [`compare`] walks both sides with the regular walker and hashes their files,
+//! then sorts every file into content found only in A, only in B, or in both,
+//! whatever the files are called or wherever they sit. Like the scanner it
+//! never prints; the `compare` subcommand renders the [`Comparison`].
+
+use crate::hashing::{hash_file, HashKind};
+use crate::scanner::FileError;
+use crate::walker::{collect_roots, resolve_targets, FileFilter, ScanRoot};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+/// Content present on both sides, with every path holding it.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommonContent {
+    pub hash: String,
+    pub size: u64,
+    #[serde(with = "crate::paths::vec")]
+    pub a: Vec<PathBuf>,
+    #[serde(with = "crate::paths::vec")]
+    pub b: Vec<PathBuf>,
+}
+
+/// Which side of a comparison a file came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    A,
+    B,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Comparison {
+    pub a: Option<ScanRoot>,
+    pub b: Option<ScanRoot>,
+    /// Files in A whose content B does not have.
+    #[serde(with = "crate::paths::vec")]
+    pub only_a: Vec<PathBuf>,
+    /// Files in B whose content A does not have.
+    #[serde(with = "crate::paths::vec")]
+    pub only_b: Vec<PathBuf>,
+    pub common: Vec<CommonContent>,
+    /// Files that could not be read; they are in none of the sets above.
+    pub errors: Vec<FileError>,
+    /// Digest algorithm used on both sides.
+    pub algorithm: String,
+}
+
+/// Compare the content of `a` and `b`.
+///
+/// Only files whose size occurs on both sides are hashed; any other file is
+/// unique to its side without being read. Fails when either side doesn't exist.
+pub fn compare(a: PathBuf, b: PathBuf, filter: &FileFilter, algo: HashKind) -> Result<Comparison, String> {
+    let (mut roots, missing) = resolve_targets(&[a.clone(), b.clone()]);
+    if let Some(target) = missing.first() {
+        return Err(format!("{}: No such file or directory", target.display()));
+    }
+    // The same path on both sides resolves to a single root
+    let root_b = if roots.len() == 1 { roots[0].clone() } else { roots.pop().unwrap() };
+    let root_a = roots.pop().unwrap();
+
+    let mut files: Vec<(Side, PathBuf)> = Vec::new();
+    files.extend(collect_roots(std::slice::from_ref(&root_a), filter).into_iter().map(|f| (Side::A, f)));
+    files.extend(collect_roots(std::slice::from_ref(&root_b), filter).into_iter().map(|f| (Side::B, f)));
+
+    let mut comparison = Comparison { algorithm: algo.to_string(), ..Default::default() };
+    let mut sized: Vec<(Side, PathBuf, u64)> = Vec::new();
+    for (side, file) in files {
+        match fs::metadata(&file) {
+            Ok(meta) => sized.push((side, file, meta.len())),
+            Err(e) => comparison.errors.push(FileError { file, reason: e.to_string() }),
+        }
+    }
+    let sizes_a: HashSet<u64> = sized.iter().filter(|(s, _, _)| *s == Side::A).map(|(_, _, n)| *n).collect();
+    let sizes_b: HashSet<u64> = sized.iter().filter(|(s, _, _)| *s == Side::B).map(|(_, _, n)| *n).collect();
+    let (to_hash, unique): (Vec<_>, Vec<_>) = sized
+        .into_iter()
+        .partition(|(_, _, size)| sizes_a.contains(size) && sizes_b.contains(size));
+    for (side, file, _) in unique {
+        match side {
+            Side::A => comparison.only_a.push(file),
+            Side::B => comparison.only_b.push(file),
+        }
+    }
+
+    let hashed: Vec<_> = to_hash
+        .into_par_iter()
+        .map(|(side, file, size)| {
+            let hash = hash_file(&file, algo);
+            (side, file, size, hash)
+        })
+        .collect();
+    let mut by_hash: BTreeMap<Vec<u8>, CommonContent> = BTreeMap::new();
+    for (side, file, size, hash) in hashed {
+        match hash {
+            Ok(hash) => {
+                let entry = by_hash.entry(hash.clone()).or_insert_with(|| CommonContent {
+                    hash: hex::encode(&hash),
+                    size,
+                    a: Vec::new(),
+                    b: Vec::new(),
+                });
+                match side {
+                    Side::A => entry.a.push(file),
+                    Side::B => entry.b.push(file),
+                }
+            }
+            Err(e) => comparison.errors.push(FileError { file, reason: e.to_string() }),
+        }
+    }
+    for mut content in by_hash.into_values() {
+        if content.a.is_empty() {
+            comparison.only_b.append(&mut content.b);
+        } else if content.b.is_empty() {
+            comparison.only_a.append(&mut content.a);
+        } else {
+            content.a.sort();
+            content.b.sort();
+            comparison.common.push(content);
+        }
+    }
+    comparison.only_a.sort();
+    comparison.only_b.sort();
+    comparison.common.sort_by(|x, y| x.a.cmp(&y.a));
+    comparison.errors.sort_by(|x, y| x.file.cmp(&y.file));
+    comparison.a = Some(root_a);
+    comparison.b = Some(root_b);
+    Ok(comparison)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_compare_sorts_content_into_sets() {
+        let dir = tempdir().unwrap();
+        let (a, b) = (dir.path().join("old"), dir.path().join("new"));
+        fs::create_dir_all(a.join("nested")).unwrap();
+        fs::create_dir(&b).unwrap();
+        fs::write(a.join("nested/photo.jpg"), "shared").unwrap();
+        fs::write(b.join("renamed.jpg"), "shared").unwrap();
+        fs::write(a.join("draft.txt"), "only in a").unwrap();
+        // Same size as draft.txt, so it has to be hashed to tell them apart
+        fs::write(b.join("final.txt"), "only in b").unwrap();
+        fs::write(b.join("big.bin"), "only in b, and no size match").unwrap();
+
+        let c = compare(a, b, &FileFilter::default(), HashKind::Blake3).unwrap();
+        assert_eq!(c.only_a.len(), 1);
+        assert!(c.only_a[0].ends_with("draft.txt"));
+        assert_eq!(c.only_b.len(), 2);
+        assert_eq!(c.common.len(), 1);
+        assert!(c.common[0].a[0].ends_with("nested/photo.jpg"));
+        assert!(c.common[0].b[0].ends_with("renamed.jpg"));
+        assert!(c.errors.is_empty());
+    }
+
+    #[test]
+    fn test_compare_missing_side() {
+        let dir = tempdir().unwrap();
+        let err = compare(dir.path().to_path_buf(), dir.path().join("gone"), &FileFilter::default(), HashKind::Blake3)
+            .unwrap_err();
+        assert!(err.contains("gone"));
+    }
+}
@@ -0,0 +1,55 @@
+//! Optional settings file, `~/.dedcore/config.json` by default.
+//!
+//! ```json
+//! { "keep": ["dir:/srv/master", "oldest"] }
+//! ```
+
+use crate::keep::KeepPolicy;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Keep policy used when none is given on the command line.
+    pub keep: KeepPolicy,
+}
+
+impl Config {
+    pub fn default_path() -> PathBuf {
+        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+        home.join(".dedcore").join("config.json")
+    }
+
+    /// Read the config at `path`. A missing file is an empty config; a malformed one is an error.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        assert!(Config::load(&path).unwrap().keep.rules.is_empty());
+
+        fs::write(&path, r#"{ "keep": ["oldest", "regex:master"] }"#).unwrap();
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.keep.rules.len(), 2);
+
+        fs::write(&path, r#"{ "keep": ["biggest"] }"#).unwrap();
+        assert!(Config::load(&path).unwrap_err().contains("unknown keep rule"));
+    }
+}
@@ -0,0 +1,207 @@
+//! Merkle hashing of directories.
+//!
+//! A directory's digest is built from its children: the content digest of each
+//! file and the digest of each subdirectory, optionally with their names. Two
+//! directories with equal digests hold the same tree of content, so a copied
+//! folder shows up as one match instead of a pair for every file in it.
+//!
+//! File digests come from the duplicate groups of a finished scan. A file that
+//! is in no group has content found nowhere else, so a directory holding one
+//! can't match any other directory and is never hashed. Directories that hold
+//! anything the scan did not cover (filtered or unreadable files, symlinks,
+//! special files) are left out too, since moving them would move more than
+//! was compared.
+
+use crate::hashing::{hash_bytes, HashKind};
+use crate::paths::escape_bytes;
+use crate::scanner::{DuplicateGroup, HardlinkSet};
+use crate::walker::{allocated_bytes, link_count, root_of, ScanRoot};
+use clap::ValueEnum;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What has to agree for two directories to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DirMatch {
+    /// Same content under the same names
+    Names,
+    /// Same content, however the files are named
+    Content,
+}
+
+/// Directories with identical digests, before a keeper is chosen.
+pub(crate) struct DirCandidates {
+    pub hash: String,
+    /// Files in each directory, counted recursively.
+    pub files: usize,
+    /// Bytes in each directory, by file length.
+    pub size: u64,
+    /// Each directory with the bytes on disk that removing it would free.
+    pub dirs: Vec<(PathBuf, u64)>,
+}
+
+/// What is known about one directory once its children are hashed.
+struct DirInfo {
+    hash: String,
+    files: usize,
+    size: u64,
+    freeable: u64,
+}
+
+/// Find directories under `roots` whose whole content is duplicated elsewhere.
+///
+/// Nested matches are dropped when every one of their directories sits in a
+/// directory that matched as a whole.
+pub(crate) fn duplicate_directories(
+    roots: &[ScanRoot],
+    groups: &[DuplicateGroup],
+    hardlink_sets: &[HardlinkSet],
+    mode: DirMatch,
+) -> Vec<DirCandidates> {
+    // Content identity of every file that has a copy elsewhere
+    let mut content: HashMap<&Path, (String, u64)> = HashMap::new();
+    for group in groups {
+        for f in group.files.iter().chain(&group.hardlinks) {
+            content.insert(f, (group.hash.clone(), group.size));
+        }
+    }
+    for set in hardlink_sets {
+        for f in &set.files {
+            content.entry(f).or_insert_with(|| (escape_bytes(set.files[0].as_os_str().as_encoded_bytes()), set.size));
+        }
+    }
+
+    // Every directory between those files and the root they were found under
+    let mut dirs: HashSet<&Path> = HashSet::new();
+    for file in content.keys() {
+        let Some(root) = root_of(roots, file).filter(|r| r.is_dir) else { continue };
+        let mut dir = file.parent();
+        while let Some(d) = dir.filter(|d| d.starts_with(&root.path)) {
+            if !dirs.insert(d) {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+    // Children before parents
+    let mut dirs: Vec<&Path> = dirs.into_iter().collect();
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+
+    let mut hashed: HashMap<&Path, Option<DirInfo>> = HashMap::new();
+    for dir in dirs {
+        let info = hash_directory(dir, &content, &hashed, mode);
+        hashed.insert(dir, info);
+    }
+
+    let mut by_hash: BTreeMap<&str, Vec<(&Path, &DirInfo)>> = BTreeMap::new();
+    for (dir, info) in &hashed {
+        if let Some(info) = info.as_ref().filter(|i| i.files > 0) {
+            by_hash.entry(&info.hash).or_default().push((dir, info));
+        }
+    }
+    by_hash.retain(|_, dirs| dirs.len() > 1);
+    let matched: HashSet<&Path> = by_hash.values().flatten().map(|(d, _)| *d).collect();
+    let mut found: Vec<DirCandidates> = by_hash
+        .into_values()
+        .filter(|dirs| !dirs.iter().all(|(d, _)| d.parent().is_some_and(|p| matched.contains(p))))
+        .map(|mut dirs| {
+            dirs.sort_by_key(|(d, _)| *d);
+            let (_, first) = dirs[0];
+            DirCandidates {
+                hash: first.hash.clone(),
+                files: first.files,
+                size: first.size,
+                dirs: dirs.iter().map(|(d, i)| (d.to_path_buf(), i.freeable)).collect(),
+            }
+        })
+        .collect();
+    found.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.dirs.cmp(&b.dirs)));
+    found
+}
+
+/// Digest of `dir` from its children, or `None` if any child is not accounted for.
+fn hash_directory(
+    dir: &Path,
+    content: &HashMap<&Path, (String, u64)>,
+    hashed: &HashMap<&Path, Option<DirInfo>>,
+    mode: DirMatch,
+) -> Option<DirInfo> {
+    let mut info = DirInfo { hash: String::new(), files: 0, size: 0, freeable: 0 };
+    let mut entries: Vec<String> = Vec::new();
+    for entry in fs::read_dir(dir).ok()? {
+        let entry = entry.ok()?;
+        let path = entry.path();
+        let file_type = entry.file_type().ok()?;
+        let name = if mode == DirMatch::Names { escape_bytes(entry.file_name().as_encoded_bytes()) } else { String::new() };
+        if file_type.is_file() {
+            let (id, size) = content.get(path.as_path())?;
+            let meta = entry.metadata().ok()?;
+            info.files += 1;
+            info.size += size;
+            // A file with names elsewhere keeps its storage when this directory goes
+            if link_count(&meta) <= 1 {
+                info.freeable += allocated_bytes(&meta);
+            }
+            entries.push(format!("f\0{}\0{}", name, id));
+        } else if file_type.is_dir() {
+            let id = match hashed.get(path.as_path()) {
+                Some(sub) => {
+                    let sub = sub.as_ref()?;
+                    info.files += sub.files;
+                    info.size += sub.size;
+                    info.freeable += sub.freeable;
+                    sub.hash.clone()
+                }
+                // Only empty directories hold no candidate files
+                None if fs::read_dir(&path).ok()?.next().is_none() => "empty".to_string(),
+                None => return None,
+            };
+            entries.push(format!("d\0{}\0{}", name, id));
+        } else {
+            return None;
+        }
+    }
+    entries.sort();
+    info.hash = hex::encode(hash_bytes(entries.join("\n").as_bytes(), HashKind::Blake3));
+    Some(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{ScanOptions, Scanner};
+    use tempfile::tempdir;
+
+    fn tree(base: &Path, files: &[(&str, &str)]) {
+        for (name, data) in files {
+            let path = base.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_copied_tree_matches_once() {
+        let dir = tempdir().unwrap();
+        let files = [("a.txt", "alpha"), ("sub/b.txt", "beta"), ("sub/deep/c.txt", "gamma")];
+        tree(&dir.path().join("orig"), &files);
+        tree(&dir.path().join("copy"), &files);
+        // Same content under other names only matches when names are ignored
+        tree(&dir.path().join("renamed"), &[("x.txt", "alpha"), ("sub/y.txt", "beta"), ("sub/deep/z.txt", "gamma")]);
+        // One extra file and the directory is no longer a copy
+        tree(&dir.path().join("partial"), &[("a.txt", "alpha"), ("sub/b.txt", "beta"), ("sub/extra.txt", "delta")]);
+
+        let report = Scanner::new(ScanOptions::new([dir.path()])).run();
+        let found = duplicate_directories(&report.roots, &report.duplicate_groups, &report.hardlink_sets, DirMatch::Names);
+        assert_eq!(found.len(), 1);
+        let dirs: Vec<&Path> = found[0].dirs.iter().map(|(d, _)| d.as_path()).collect();
+        assert!(dirs[0].ends_with("copy") && dirs[1].ends_with("orig"));
+        assert_eq!(found[0].files, 3);
+        assert_eq!(found[0].size, 14);
+
+        let found = duplicate_directories(&report.roots, &report.duplicate_groups, &report.hardlink_sets, DirMatch::Content);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].dirs.len(), 3);
+    }
+}
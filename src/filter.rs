@@ -0,0 +1,603 @@
+//! Filter expressions for `--filter`.
+//!
+//! ```text
+//! size > 10M and ext in (jpg, png) and not path ~ "/thumbs/" and mtime < 2w
+//! ```
+//!
+//! Conditions combine with `and`, `or`, `not` and parentheses; `and` binds
+//! tighter than `or`. The fields are:
+//!
+//! - `size`: file length, compared with `<`, `<=`, `>`, `>=`, `=` or `!=`.
+//!   Sizes take a unit: `K`, `M`, `G` and `T` (also written `KiB` etc.) are
+//!   powers of 1024, `KB`, `MB`, `GB` and `TB` powers of 1000.
+//! - `mtime` (or `age`): time since the last modification, so `mtime < 2w`
+//!   means changed within the last two weeks. Units are `s`, `m`, `h`, `d`,
+//!   `w` and `y`; a bare number counts days.
+//! - `ext`, `name` and `path`: compared with `=` or `!=`, against a list with
+//!   `in (a, b)`, or against a regular expression with `~` or `!~`. `ext` is
+//!   compared without regard to case.
+//! - `uid` and `gid`: owner ids, compared like sizes. `user` and `group` take
+//!   a name or an id, with `=` or `!=`.
+//! - `mode`: permission bits in octal; `mode = 644` for exactly those bits,
+//!   `mode & 022` for any of them, e.g. group- or world-writable files.
+//!
+//! Values can be quoted with `"` when they contain spaces or operators.
+//!
+//! [`PathGlob`] is the simpler filter behind `--include` and `--exclude`.
+
+use crate::owner::{mode_bits, owner_ids, parse_group, parse_mode, parse_user};
+use glob::{MatchOptions, Pattern};
+use regex::Regex;
+use std::fmt;
+use std::fs::Metadata;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+/// Parse a size such as `1500`, `10M` or `2.5GB` into bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (number, unit) = split_unit(s);
+    let factor: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => return Err(format!("unknown size unit in '{}' (expected K, M, G, T, KB, MB, GB or TB)", s)),
+    };
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{}'", s))?;
+    if number < 0.0 {
+        return Err(format!("invalid size '{}'", s));
+    }
+    Ok((number * factor as f64).round() as u64)
+}
+
+/// Parse a duration such as `90s`, `12h` or `2w` into seconds. A bare number counts days.
+pub fn parse_duration(s: &str) -> Result<u64, String> {
+    let (number, unit) = split_unit(s);
+    let factor: u64 = match unit {
+        "s" => 1,
+        "m" | "min" => 60,
+        "h" => 3_600,
+        "" | "d" => 86_400,
+        "w" => 7 * 86_400,
+        "y" => 365 * 86_400,
+        _ => return Err(format!("unknown time unit in '{}' (expected s, m, h, d, w or y)", s)),
+    };
+    let number: f64 = number.parse().map_err(|_| format!("invalid duration '{}'", s))?;
+    if number < 0.0 {
+        return Err(format!("invalid duration '{}'", s));
+    }
+    Ok((number * factor as f64).round() as u64)
+}
+
+fn split_unit(s: &str) -> (&str, &str) {
+    let at = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    s.split_at(at)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Cmp {
+    fn holds<T: PartialOrd>(self, a: T, b: T) -> bool {
+        match self {
+            Cmp::Lt => a < b,
+            Cmp::Le => a <= b,
+            Cmp::Gt => a > b,
+            Cmp::Ge => a >= b,
+            Cmp::Eq => a == b,
+            Cmp::Ne => a != b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextField {
+    Ext,
+    Name,
+    Path,
+}
+
+#[derive(Debug, Clone)]
+enum TextTest {
+    Eq(String),
+    Ne(String),
+    In(Vec<String>),
+    Matches(Regex),
+    NotMatches(Regex),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Size(Cmp, u64),
+    Age(Cmp, u64),
+    Uid(Cmp, u32),
+    Gid(Cmp, u32),
+    Mode(Cmp, u32),
+    /// Any of these permission bits set.
+    ModeAny(u32),
+    Text(TextField, TextTest),
+}
+
+impl Expr {
+    fn eval(&self, path: &Path, meta: &Metadata, now: u64) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(path, meta, now) && b.eval(path, meta, now),
+            Expr::Or(a, b) => a.eval(path, meta, now) || b.eval(path, meta, now),
+            Expr::Not(a) => !a.eval(path, meta, now),
+            Expr::Size(cmp, size) => cmp.holds(meta.len(), *size),
+            // Without ownership metadata nothing matches, not even a negation
+            Expr::Uid(cmp, uid) => owner_ids(meta).is_some_and(|(u, _)| cmp.holds(u, *uid)),
+            Expr::Gid(cmp, gid) => owner_ids(meta).is_some_and(|(_, g)| cmp.holds(g, *gid)),
+            Expr::Mode(cmp, mode) => mode_bits(meta).is_some_and(|m| cmp.holds(m, *mode)),
+            Expr::ModeAny(bits) => mode_bits(meta).is_some_and(|m| m & bits != 0),
+            Expr::Age(cmp, secs) => {
+                // Files without a usable mtime never match a time condition
+                let Some(modified) = meta.modified().ok().and_then(|m| m.duration_since(UNIX_EPOCH).ok()) else {
+                    return false;
+                };
+                cmp.holds(now.saturating_sub(modified.as_secs()), *secs)
+            }
+            Expr::Text(field, test) => {
+                let value = match field {
+                    TextField::Ext => path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default(),
+                    TextField::Name => path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+                    TextField::Path => path.to_string_lossy().to_string(),
+                };
+                match test {
+                    TextTest::Eq(s) => value == *s,
+                    TextTest::Ne(s) => value != *s,
+                    TextTest::In(list) => list.contains(&value),
+                    TextTest::Matches(re) => re.is_match(&value),
+                    TextTest::NotMatches(re) => !re.is_match(&value),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(&'static str),
+    Open,
+    Close,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(w) => write!(f, "'{}'", w),
+            Token::Quoted(s) => write!(f, "\"{}\"", s),
+            Token::Op(op) => write!(f, "'{}'", op),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+        }
+    }
+}
+
+const OPERATORS: &[&str] = &["<=", ">=", "==", "!=", "!~", "<", ">", "=", "~", "&"];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '"' {
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i + 2,
+                    // Only quotes and backslashes are escaped, so regexes keep theirs
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) if c == '"' || c == '\\' => value.push(c),
+                        Some((_, c)) => {
+                            value.push('\\');
+                            value.push(c);
+                        }
+                        None => return Err("unterminated string".to_string()),
+                    },
+                    Some((_, c)) => value.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            };
+            tokens.push(Token::Quoted(value));
+            rest = &rest[end..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if let Some(token) = match c {
+            '(' => Some(Token::Open),
+            ')' => Some(Token::Close),
+            ',' => Some(Token::Comma),
+            _ => None,
+        } {
+            tokens.push(token);
+            rest = &rest[1..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "()<>=!~&,\"".contains(c))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("unexpected '{}'", c));
+            }
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case(word) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.peek() == Some(&Token::Open) {
+            self.pos += 1;
+            let expr = self.or()?;
+            return match self.next() {
+                Some(Token::Close) => Ok(expr),
+                Some(t) => Err(format!("expected ')' but found {}", t)),
+                None => Err("missing ')'".to_string()),
+            };
+        }
+        self.condition()
+    }
+
+    /// A word or quoted string.
+    fn value(&mut self, after: &str) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Word(w)) | Some(Token::Quoted(w)) => Ok(w),
+            Some(t) => Err(format!("expected a value after {} but found {}", after, t)),
+            None => Err(format!("expected a value after {}", after)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Expr, String> {
+        let field = match self.next() {
+            Some(Token::Word(w)) => w.to_ascii_lowercase(),
+            Some(t) => return Err(format!("expected a field but found {}", t)),
+            None => return Err("expected a field".to_string()),
+        };
+        let op = if self.keyword("in") {
+            "in"
+        } else {
+            match self.next() {
+                Some(Token::Op(op)) => op,
+                Some(t) => return Err(format!("expected an operator after '{}' but found {}", field, t)),
+                None => return Err(format!("expected an operator after '{}'", field)),
+            }
+        };
+        let cmp = match op {
+            "<" => Some(Cmp::Lt),
+            "<=" => Some(Cmp::Le),
+            ">" => Some(Cmp::Gt),
+            ">=" => Some(Cmp::Ge),
+            "=" | "==" => Some(Cmp::Eq),
+            "!=" => Some(Cmp::Ne),
+            _ => None,
+        };
+        let after = format!("'{} {}'", field, op);
+        match field.as_str() {
+            "size" | "mtime" | "age" => {
+                let cmp = cmp.ok_or_else(|| format!("'{}' can't be used with '{}'", op, field))?;
+                let value = self.value(&after)?;
+                if field == "size" {
+                    Ok(Expr::Size(cmp, parse_size(&value)?))
+                } else {
+                    Ok(Expr::Age(cmp, parse_duration(&value)?))
+                }
+            }
+            "uid" | "gid" => {
+                let cmp = cmp.ok_or_else(|| format!("'{}' can't be used with '{}'", op, field))?;
+                let value = self.value(&after)?;
+                let id = value.parse().map_err(|_| format!("invalid {} '{}'", field, value))?;
+                Ok(if field == "uid" { Expr::Uid(cmp, id) } else { Expr::Gid(cmp, id) })
+            }
+            "user" | "group" => {
+                let cmp = cmp.filter(|c| matches!(c, Cmp::Eq | Cmp::Ne));
+                let cmp = cmp.ok_or_else(|| format!("'{}' can't be used with '{}'", op, field))?;
+                let value = self.value(&after)?;
+                Ok(if field == "user" { Expr::Uid(cmp, parse_user(&value)?) } else { Expr::Gid(cmp, parse_group(&value)?) })
+            }
+            "mode" => {
+                let value = self.value(&after)?;
+                let mode = parse_mode(&value)?;
+                match (op, cmp) {
+                    ("&", _) => Ok(Expr::ModeAny(mode)),
+                    (_, Some(cmp @ (Cmp::Eq | Cmp::Ne))) => Ok(Expr::Mode(cmp, mode)),
+                    _ => Err(format!("'{}' can't be used with 'mode'", op)),
+                }
+            }
+            "ext" | "name" | "path" => {
+                let text_field = match field.as_str() {
+                    "ext" => TextField::Ext,
+                    "name" => TextField::Name,
+                    _ => TextField::Path,
+                };
+                let normalize = |s: String| {
+                    if text_field == TextField::Ext {
+                        s.trim_start_matches('.').to_lowercase()
+                    } else {
+                        s
+                    }
+                };
+                let test = match op {
+                    "=" | "==" => TextTest::Eq(normalize(self.value(&after)?)),
+                    "!=" => TextTest::Ne(normalize(self.value(&after)?)),
+                    "~" | "!~" => {
+                        let pattern = self.value(&after)?;
+                        let re = Regex::new(&pattern).map_err(|e| format!("invalid regex pattern: {}", e))?;
+                        if op == "~" { TextTest::Matches(re) } else { TextTest::NotMatches(re) }
+                    }
+                    "in" => TextTest::In(self.list(&after)?.into_iter().map(normalize).collect()),
+                    _ => return Err(format!("'{}' can't be used with '{}'", op, field)),
+                };
+                Ok(Expr::Text(text_field, test))
+            }
+            _ => Err(format!(
+                "unknown field '{}' (expected size, mtime, age, ext, name, path, uid, gid, user, group or mode)",
+                field
+            )),
+        }
+    }
+
+    fn list(&mut self, after: &str) -> Result<Vec<String>, String> {
+        if self.next() != Some(Token::Open) {
+            return Err(format!("expected '(' after {}", after));
+        }
+        let mut items = vec![self.value(after)?];
+        loop {
+            match self.next() {
+                Some(Token::Comma) => items.push(self.value(after)?),
+                Some(Token::Close) => return Ok(items),
+                Some(t) => return Err(format!("expected ',' or ')' but found {}", t)),
+                None => return Err("missing ')'".to_string()),
+            }
+        }
+    }
+}
+
+const GLOB_MATCH: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// A glob for `--include` and `--exclude`.
+///
+/// A pattern without a `/` is matched against the file name (`*.jpg`), one with
+/// a `/` against the whole path: absolute patterns from the root, relative ones
+/// at any depth, so `thumbs/*` matches `/photos/2024/thumbs/a.jpg`.
+#[derive(Debug, Clone)]
+pub struct PathGlob {
+    source: String,
+    pattern: Pattern,
+    name_only: bool,
+}
+
+impl PathGlob {
+    pub fn matches(&self, path: &Path) -> bool {
+        if self.name_only {
+            path.file_name().is_some_and(|name| self.pattern.matches_with(&name.to_string_lossy(), GLOB_MATCH))
+        } else {
+            self.pattern.matches_with(&path.to_string_lossy(), GLOB_MATCH)
+        }
+    }
+}
+
+impl FromStr for PathGlob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name_only = !s.contains('/');
+        let full = if name_only || s.starts_with('/') { s.to_string() } else { format!("**/{}", s) };
+        let pattern = Pattern::new(&full).map_err(|e| format!("invalid glob '{}': {}", s, e))?;
+        Ok(PathGlob { source: s.to_string(), pattern, name_only })
+    }
+}
+
+impl fmt::Display for PathGlob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// A parsed `--filter` expression.
+#[derive(Debug, Clone)]
+pub struct FilterExpr {
+    source: String,
+    expr: Expr,
+}
+
+impl FilterExpr {
+    /// Whether a file with the given metadata satisfies the expression.
+    ///
+    /// `now` is the scan start time in seconds since the epoch.
+    pub fn matches(&self, path: &Path, meta: &Metadata, now: u64) -> bool {
+        self.expr.eval(path, meta, now)
+    }
+}
+
+impl FromStr for FilterExpr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s)?, pos: 0 };
+        if parser.tokens.is_empty() {
+            return Err("empty filter".to_string());
+        }
+        let expr = parser.or()?;
+        if let Some(t) = parser.peek() {
+            return Err(format!("unexpected {} (conditions are joined with 'and' or 'or')", t));
+        }
+        Ok(FilterExpr { source: s.to_string(), expr })
+    }
+}
+
+impl fmt::Display for FilterExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_units() {
+        assert_eq!(parse_size("1500"), Ok(1500));
+        assert_eq!(parse_size("10M"), Ok(10 << 20));
+        assert_eq!(parse_size("1.5KiB"), Ok(1536));
+        assert_eq!(parse_size("2GB"), Ok(2_000_000_000));
+        assert!(parse_size("3 parsecs").is_err());
+        assert_eq!(parse_duration("2w"), Ok(14 * 86_400));
+        assert_eq!(parse_duration("30"), Ok(30 * 86_400));
+        assert_eq!(parse_duration("90s"), Ok(90));
+    }
+
+    #[test]
+    fn test_expressions() {
+        let dir = tempdir().unwrap();
+        let thumbs = dir.path().join("thumbs");
+        fs::create_dir(&thumbs).unwrap();
+        let big = dir.path().join("Big.JPG");
+        let thumb = thumbs.join("small.png");
+        let old = dir.path().join("old.png");
+        fs::write(&big, vec![0u8; 2048]).unwrap();
+        fs::write(&thumb, vec![0u8; 2048]).unwrap();
+        fs::write(&old, vec![0u8; 2048]).unwrap();
+        let month_ago = SystemTime::now() - Duration::from_secs(30 * 86_400);
+        fs::File::options().write(true).open(&old).unwrap().set_modified(month_ago).unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        let matching = |expr: &str| -> Vec<String> {
+            let filter: FilterExpr = expr.parse().unwrap();
+            [&big, &thumb, &old]
+                .iter()
+                .filter(|p| filter.matches(p, &fs::metadata(p).unwrap(), now))
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .collect()
+        };
+        assert_eq!(
+            matching(r#"size > 1K and ext in (jpg,png) and not path ~ "/thumbs/" and mtime < 2w"#),
+            ["Big.JPG"]
+        );
+        assert_eq!(matching("size >= 2KiB and (name = old.png or ext = .JPG)"), ["Big.JPG", "old.png"]);
+        assert_eq!(matching("mtime > 1w or name ~ ^small"), ["small.png", "old.png"]);
+        assert_eq!(matching("size < 1K"), Vec::<String>::new());
+        assert_eq!(matching(r#"name ~ "\.(JPG|png)$" and name !~ "^s""#), ["Big.JPG", "old.png"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ownership_fields() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("shared.txt");
+        fs::write(&file, "x").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o664)).unwrap();
+        let meta = fs::metadata(&file).unwrap();
+        let (uid, gid) = owner_ids(&meta).unwrap();
+        let holds = |expr: &str| expr.parse::<FilterExpr>().unwrap().matches(&file, &meta, 0);
+        assert!(holds("mode = 664 and mode & 020 and not mode & 002"));
+        assert!(holds(&format!("uid = {} and gid <= {}", uid, gid)));
+        assert!(holds(&format!("user = {} and group != {}", uid, gid + 1)));
+        assert!(!holds(&format!("uid > {}", uid)));
+        assert!("mode > 644".parse::<FilterExpr>().is_err());
+        assert!("user < 1000".parse::<FilterExpr>().is_err());
+    }
+
+    #[test]
+    fn test_path_globs() {
+        let glob = |g: &str, p: &str| g.parse::<PathGlob>().unwrap().matches(Path::new(p));
+        assert!(glob("*.jpg", "/photos/a.jpg"));
+        assert!(!glob("*.jpg", "/photos.jpg/a.png"));
+        assert!(glob("thumbs/*", "/photos/2024/thumbs/a.jpg"));
+        assert!(!glob("thumbs/*", "/photos/thumbs/sub/a.jpg"));
+        assert!(glob("thumbs/**", "/photos/thumbs/sub/a.jpg"));
+        assert!(glob("/photos/*/a.jpg", "/photos/2024/a.jpg"));
+        assert!(!glob("/2024/*", "/photos/2024/a.jpg"));
+        assert!("[".parse::<PathGlob>().is_err());
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        for (expr, message) in [
+            ("size >", "expected a value"),
+            ("size ~ 10", "can't be used"),
+            ("color = red", "unknown field"),
+            ("ext = jpg png", "unexpected 'png'"),
+            ("(size > 1", "missing ')'"),
+            ("name = \"open", "unterminated"),
+        ] {
+            let err = expr.parse::<FilterExpr>().unwrap_err();
+            assert!(err.contains(message), "{}: {}", expr, err);
+        }
+    }
+}
@@ -1,14 +1,17 @@
 use std::fs::File;
-use std::io::{self, Read, BufReader};
+use std::io::{self, Read, BufRead, BufReader, Seek, SeekFrom};
 use sha2::{Sha256, Digest as ShaDigest};
 use blake3;
 use xxhash_rust::xxh3::Xxh3;
 use rayon::prelude::*;
 use memmap2::Mmap;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 
 use std::fmt;
+use std::path::{Path, PathBuf};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum HashKind {
     Sha256,
     Blake3,
@@ -25,7 +28,7 @@ impl fmt::Display for HashKind {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, ValueEnum)]
 pub enum Security {
     Low,
     Medium,
@@ -33,10 +36,11 @@ pub enum Security {
     Maximum,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, ValueEnum)]
 pub enum Speed {
     Fastest,
     Balanced,
+    #[value(name = "mostsecure", alias = "most-secure")]
     MostSecure,
 }
 
@@ -50,6 +54,10 @@ impl HashConfig {
     pub fn new(security: Security, speed: Speed) -> Self {
         Self { security, speed }
     }
+    /// Per-extension algorithm choice, for hashing files one at a time.
+    ///
+    /// Digests produced by different algorithms can't be compared, so anything
+    /// that groups files must use [`HashConfig::canonical_algorithm`] instead.
     pub fn choose_algorithm(&self, ext: &str) -> HashKind {
         match (ext.to_lowercase().as_str(), &self.security, &self.speed) {
             ("txt" | "md" | "rs" | "py", Security::High | Security::Maximum, _) => HashKind::Sha256,
@@ -61,6 +69,15 @@ impl HashConfig {
             _ => HashKind::Sha256,
         }
     }
+
+    /// The single algorithm used for every file in a scan, so any two digests are comparable.
+    pub fn canonical_algorithm(&self) -> HashKind {
+        match (&self.security, &self.speed) {
+            (Security::High | Security::Maximum, _) | (_, Speed::MostSecure) => HashKind::Sha256,
+            (_, Speed::Fastest) => HashKind::XxHash3,
+            _ => HashKind::Blake3,
+        }
+    }
 }
 
 // 8KB buffer. Why? Because it feels right.
@@ -81,13 +98,12 @@ pub fn hash_bytes(data: &[u8], algo: HashKind) -> Vec<u8> {
         HashKind::XxHash3 => {
             let mut hasher = Xxh3::new();
             hasher.update(data);
-            let result = hasher.digest().to_le_bytes().to_vec();
-            result
+            hasher.digest().to_le_bytes().to_vec()
         },
     }
 }
 
-pub fn hash_file(path: &str, algo: HashKind) -> io::Result<Vec<u8>> {
+pub fn hash_file<P: AsRef<Path>>(path: P, algo: HashKind) -> io::Result<Vec<u8>> {
     let file = File::open(path)?;
     let metadata = file.metadata()?;
     let use_mmap = metadata.len() > 10 * 1024 * 1024;
@@ -147,16 +163,80 @@ pub fn hash_file(path: &str, algo: HashKind) -> io::Result<Vec<u8>> {
     }
 }
 
-#[allow(dead_code)]
-pub fn hash_files_parallel(paths: &[&str], algo: HashKind) -> Vec<(String, Vec<u8>)> {
+/// Bytes read from each end of a file by [`hash_file_partial`].
+pub const PARTIAL_BLOCK: u64 = 4096;
+
+/// Hash only the first and last `block` bytes of a file.
+///
+/// Cheap way to split same-size candidates before committing to a full read.
+/// Files no larger than `2 * block` are hashed completely, so for them the
+/// result is identical to [`hash_file`].
+pub fn hash_file_partial<P: AsRef<Path>>(path: P, algo: HashKind, block: u64) -> io::Result<Vec<u8>> {
+    let path = path.as_ref();
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len <= block * 2 {
+        drop(file);
+        return hash_file(path, algo);
+    }
+    let mut data = vec![0u8; (block * 2) as usize];
+    let (head, tail) = data.split_at_mut(block as usize);
+    file.read_exact(head)?;
+    file.seek(SeekFrom::Start(len - block))?;
+    file.read_exact(tail)?;
+    Ok(hash_bytes(&data, algo))
+}
+
+/// Hash many files at once, keeping the per-file outcome in input order.
+///
+/// A file that cannot be read yields its error rather than an empty digest,
+/// so unreadable files never look identical to each other.
+pub fn hash_files_parallel(paths: &[&Path], algo: HashKind) -> Vec<(PathBuf, io::Result<Vec<u8>>)> {
     paths.par_iter()
-        .map(|path| {
-            let hash = hash_file(path, algo.clone()).unwrap_or_default();
-            (path.to_string(), hash)
-        })
+        .map(|path| (path.to_path_buf(), hash_file(path, algo)))
         .collect()
 }
 
+/// Compare two files byte by byte.
+///
+/// Digests only say two files are very likely identical; this is the check
+/// to run before acting on that, whatever algorithm produced the digests.
+pub fn files_identical<A: AsRef<Path>, B: AsRef<Path>>(a: A, b: B) -> io::Result<bool> {
+    compare_files(a, b).map_err(|(_, e)| e)
+}
+
+/// Which of the two files given to [`compare_files`] could not be read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompareSide {
+    First,
+    Second,
+}
+
+/// Like [`files_identical`], but an error says which file it came from.
+pub fn compare_files<A: AsRef<Path>, B: AsRef<Path>>(a: A, b: B) -> Result<bool, (CompareSide, io::Error)> {
+    let first = |e| (CompareSide::First, e);
+    let second = |e| (CompareSide::Second, e);
+    let fa = File::open(a).map_err(first)?;
+    let fb = File::open(b).map_err(second)?;
+    if fa.metadata().map_err(first)?.len() != fb.metadata().map_err(second)?.len() {
+        return Ok(false);
+    }
+    let mut ra = BufReader::with_capacity(64 * 1024, fa);
+    let mut rb = BufReader::with_capacity(64 * 1024, fb);
+    loop {
+        let (ba, bb) = (ra.fill_buf().map_err(first)?, rb.fill_buf().map_err(second)?);
+        if ba.is_empty() || bb.is_empty() {
+            return Ok(ba.is_empty() && bb.is_empty());
+        }
+        let n = ba.len().min(bb.len());
+        if ba[..n] != bb[..n] {
+            return Ok(false);
+        }
+        ra.consume(n);
+        rb.consume(n);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,13 +268,65 @@ mod tests {
         assert_eq!(xxhash3, expected_xxhash3);
     }
 
+    #[test]
+    fn test_canonical_algorithm_ignores_extension() {
+        let config = HashConfig::new(Security::Low, Speed::Balanced);
+        assert_eq!(config.canonical_algorithm(), HashKind::Blake3);
+        assert_ne!(config.choose_algorithm("zip"), config.choose_algorithm("bak"));
+        assert_eq!(HashConfig::new(Security::Medium, Speed::Fastest).canonical_algorithm(), HashKind::XxHash3);
+        assert_eq!(HashConfig::new(Security::Low, Speed::MostSecure).canonical_algorithm(), HashKind::Sha256);
+        assert_eq!(HashConfig::new(Security::High, Speed::Fastest).canonical_algorithm(), HashKind::Sha256);
+    }
+
+    #[test]
+    fn test_hash_file_partial() {
+        let mut small = NamedTempFile::new().unwrap();
+        write!(small, "hello world").unwrap();
+        let small = small.path().to_str().unwrap();
+        assert_eq!(
+            hash_file_partial(small, HashKind::Blake3, 8).unwrap(),
+            hash_file(small, HashKind::Blake3).unwrap()
+        );
+
+        // Same head and tail, different middle: partial hashes collide, full hashes don't
+        let mut a = NamedTempFile::new().unwrap();
+        let mut b = NamedTempFile::new().unwrap();
+        write!(a, "head-AAAA-tail").unwrap();
+        write!(b, "head-BBBB-tail").unwrap();
+        let (a, b) = (a.path().to_str().unwrap(), b.path().to_str().unwrap());
+        assert_eq!(
+            hash_file_partial(a, HashKind::Sha256, 4).unwrap(),
+            hash_file_partial(b, HashKind::Sha256, 4).unwrap()
+        );
+        assert_eq!(hash_file_partial(a, HashKind::Sha256, 4).unwrap(), hash_bytes(b"headtail", HashKind::Sha256));
+        assert_ne!(hash_file(a, HashKind::Sha256).unwrap(), hash_file(b, HashKind::Sha256).unwrap());
+    }
+
+    #[test]
+    fn test_files_identical() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b, c) = (dir.path().join("a"), dir.path().join("b"), dir.path().join("c"));
+        let big = vec![7u8; 200 * 1024];
+        let mut other = big.clone();
+        other[150 * 1024] = 8;
+        std::fs::write(&a, &big).unwrap();
+        std::fs::write(&b, &big).unwrap();
+        std::fs::write(&c, &other).unwrap();
+        let (a, b, c) = (a.to_str().unwrap(), b.to_str().unwrap(), c.to_str().unwrap());
+        assert!(files_identical(a, b).unwrap());
+        assert!(!files_identical(a, c).unwrap());
+        assert!(files_identical(a, "/does/not/exist").is_err());
+        assert_eq!(compare_files("/does/not/exist", a).unwrap_err().0, CompareSide::First);
+        assert_eq!(compare_files(a, "/does/not/exist").unwrap_err().0, CompareSide::Second);
+    }
+
     #[test]
     fn test_hash_files_parallel() {
         let mut file1 = NamedTempFile::new().unwrap();
         let mut file2 = NamedTempFile::new().unwrap();
         write!(file1, "hello world").unwrap();
         write!(file2, "rustacean").unwrap();
-        let paths = [file1.path().to_str().unwrap(), file2.path().to_str().unwrap()];
+        let paths = [file1.path(), file2.path()];
         let results = hash_files_parallel(&paths, HashKind::Sha256);
         let expected1 = vec![
             0xb9, 0x4d, 0x27, 0xb9, 0x93, 0x4d, 0x3e, 0x08,
@@ -202,13 +334,20 @@ mod tests {
             0xc4, 0x84, 0xef, 0xe3, 0x7a, 0x53, 0x80, 0xee,
             0x90, 0x88, 0xf7, 0xac, 0xe2, 0xef, 0xcd, 0xe9
         ];
-        let expected2 = vec![
-            0x6e, 0x7e, 0x0e, 0x6e, 0x7e, 0x0e, 0x6e, 0x7e,
-            0x0e, 0x6e, 0x7e, 0x0e, 0x6e, 0x7e, 0x0e, 0x6e,
-            0x7e, 0x0e, 0x6e, 0x7e, 0x0e, 0x6e, 0x7e, 0x0e,
-            0x6e, 0x7e, 0x0e, 0x6e, 0x7e, 0x0e, 0x6e, 0x7e
-        ]; // Placeholder, will update below
-        assert_eq!(results[0].1, expected1);
-        // Compute expected2 using hash_file_sha256 for "rustacean"
-            }
+        assert_eq!(results[0].1.as_ref().unwrap(), &expected1);
+        // Compute expected2 using hash_bytes for "rustacean"
+        let expected2 = hash_bytes(b"rustacean", HashKind::Sha256);
+        assert_eq!(results[1].1.as_ref().unwrap(), &expected2);
+    }
+
+    #[test]
+    fn test_hash_files_parallel_reports_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing1 = dir.path().join("gone1");
+        let missing2 = dir.path().join("gone2");
+        let paths = [missing1.as_path(), missing2.as_path()];
+        let results = hash_files_parallel(&paths, HashKind::Blake3);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, r)| r.is_err()));
+    }
 }
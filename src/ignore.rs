@@ -0,0 +1,203 @@
+//! Gitignore-style ignore files.
+//!
+//! Every walked directory may hold a `.dedcoreignore`, and with
+//! [`FileFilter::gitignore`](crate::walker::FileFilter::gitignore) a
+//! `.gitignore`, whose rules apply to everything below it. A global ignore
+//! file applies to every scan root. Rules use gitignore syntax: `#` comments,
+//! `!` to re-include, a trailing `/` for directories only, and patterns with a
+//! `/` in them anchored to the directory of the file. Later and deeper rules
+//! win. Ignored directories are pruned, so nothing inside them is even listed.
+
+use glob::{MatchOptions, Pattern};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::DirEntry;
+
+/// Name of the per-directory ignore file.
+pub const IGNORE_FILE: &str = ".dedcoreignore";
+
+const MATCH: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+struct Rule {
+    pattern: Pattern,
+    negate: bool,
+    dir_only: bool,
+    /// Matched against the path relative to the ignore file rather than the name alone.
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        // Trailing spaces only count when escaped
+        let mut line = line.trim_end_matches(' ').to_string();
+        if line.ends_with('\\') {
+            line.push(' ');
+        }
+        let (negate, mut line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest.to_string()),
+            None => (false, line.strip_prefix('\\').map(str::to_string).unwrap_or(line)),
+        };
+        let dir_only = line.ends_with('/');
+        if dir_only {
+            line.pop();
+        }
+        let anchored = line.contains('/');
+        let line = line.trim_start_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+        Some(Rule { pattern: Pattern::new(line).ok()?, negate, dir_only, anchored })
+    }
+
+    fn matches(&self, relative: &str, name: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.pattern.matches_with(relative, MATCH)
+        } else {
+            self.pattern.matches_with(name, MATCH)
+        }
+    }
+}
+
+/// The rules of one ignore file, relative to the directory they apply to.
+struct IgnoreFile {
+    base: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    fn load(path: &Path, base: &Path) -> Option<Self> {
+        let text = fs::read_to_string(path).ok()?;
+        let rules: Vec<Rule> = text.lines().filter_map(Rule::parse).collect();
+        Some(IgnoreFile { base: base.to_path_buf(), rules })
+    }
+
+    /// `Some(true)` if the last matching rule ignores `path`, `Some(false)` if it re-includes it.
+    fn verdict(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.base).ok()?;
+        let relative = relative.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "/");
+        let name = path.file_name()?.to_string_lossy();
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(&relative, &name, is_dir))
+            .map(|rule| !rule.negate)
+    }
+}
+
+/// Where ignore rules come from during a walk.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreOptions {
+    /// Read `.dedcoreignore` files.
+    pub dedcoreignore: bool,
+    /// Read `.gitignore` files, and skip `.git` directories.
+    pub gitignore: bool,
+    /// Rules applied from every root down.
+    pub global: Option<PathBuf>,
+}
+
+/// Ignore rules in effect at the current point of a depth-first walk.
+pub(crate) struct IgnoreStack<'a> {
+    options: &'a IgnoreOptions,
+    global: Option<IgnoreFile>,
+    /// Ignore files by the depth of the directory holding them, outermost first.
+    levels: Vec<(usize, Vec<IgnoreFile>)>,
+    /// Every ignore file read so far.
+    pub sources: BTreeSet<PathBuf>,
+}
+
+impl<'a> IgnoreStack<'a> {
+    pub fn new(options: &'a IgnoreOptions, root: &Path) -> Self {
+        let mut sources = BTreeSet::new();
+        let global = options.global.as_ref().and_then(|path| {
+            let file = IgnoreFile::load(path, root)?;
+            sources.insert(path.clone());
+            Some(file)
+        });
+        IgnoreStack { options, global, levels: Vec::new(), sources }
+    }
+
+    /// Whether the walk should take `entry`; a directory that is refused should be pruned.
+    ///
+    /// Entries must come in depth-first order, as `walkdir` yields them.
+    pub fn admit(&mut self, entry: &DirEntry) -> bool {
+        let depth = entry.depth();
+        while self.levels.last().is_some_and(|(d, _)| *d >= depth) {
+            self.levels.pop();
+        }
+        let is_dir = entry.file_type().is_dir();
+        // Targets named explicitly are always taken
+        if depth > 0 {
+            if self.options.gitignore && is_dir && entry.file_name() == ".git" {
+                return false;
+            }
+            let files = self.global.iter().chain(self.levels.iter().flat_map(|(_, files)| files));
+            // Deeper files come later and take precedence
+            let ignored = files.rev().find_map(|f| f.verdict(entry.path(), is_dir)).unwrap_or(false);
+            if ignored {
+                return false;
+            }
+        }
+        if is_dir {
+            let mut names = Vec::new();
+            if self.options.gitignore {
+                names.push(".gitignore");
+            }
+            if self.options.dedcoreignore {
+                names.push(IGNORE_FILE);
+            }
+            let files: Vec<IgnoreFile> = names
+                .into_iter()
+                .filter_map(|name| {
+                    let path = entry.path().join(name);
+                    let file = IgnoreFile::load(&path, entry.path())?;
+                    self.sources.insert(path);
+                    Some(file)
+                })
+                .collect();
+            if !files.is_empty() {
+                self.levels.push((depth, files));
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignored(rules: &str, path: &str, is_dir: bool) -> Option<bool> {
+        let file = IgnoreFile { base: PathBuf::from("/r"), rules: rules.lines().filter_map(Rule::parse).collect() };
+        file.verdict(Path::new(path), is_dir)
+    }
+
+    #[test]
+    fn test_gitignore_syntax() {
+        assert_eq!(ignored("*.log", "/r/a/b/x.log", false), Some(true));
+        assert_eq!(ignored("# comment\n\n", "/r/x", false), None);
+        assert_eq!(ignored("*.log\n!keep.log", "/r/keep.log", false), Some(false));
+        assert_eq!(ignored("build/", "/r/a/build", true), Some(true));
+        assert_eq!(ignored("build/", "/r/a/build", false), None);
+        // A slash anchors the pattern to the ignore file's directory
+        assert_eq!(ignored("/target", "/r/target", true), Some(true));
+        assert_eq!(ignored("/target", "/r/sub/target", true), None);
+        assert_eq!(ignored("doc/*.md", "/r/doc/a.md", false), Some(true));
+        assert_eq!(ignored("doc/*.md", "/r/doc/x/a.md", false), None);
+        assert_eq!(ignored("**/cache", "/r/a/b/cache", true), Some(true));
+        assert_eq!(ignored("**/cache", "/r/cache", true), Some(true));
+        assert_eq!(ignored("a/**/z", "/r/a/b/c/z", false), Some(true));
+        assert_eq!(ignored("\\#hash", "/r/#hash", false), Some(true));
+    }
+}
@@ -0,0 +1,229 @@
+//! Compact record of every file in a scan.
+//!
+//! A scan of tens of millions of files can't keep a `PathBuf` and a struct
+//! per file around. [`FileIndex`] packs each file's path and the few numbers
+//! the scan needs into one byte buffer and hands out a small id instead. Once
+//! the buffer outgrows its budget it is moved to a temporary file, removed
+//! again with the index, so memory stays bounded at the cost of a read whenever
+//! a spilled record is needed again. Of the index itself only the offset of
+//! each record, eight bytes, stays in memory; the scan keeps more alongside
+//! it, see [`Scanner`](crate::scanner::Scanner).
+
+use crate::paths::path_from_bytes;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::PathBuf;
+use tempfile::NamedTempFile;
+
+/// Memory an index uses for records before it starts spilling them to disk.
+pub const DEFAULT_INDEX_MEMORY: usize = 256 << 20;
+
+/// Size, mtime, allocated bytes, link count, owner flag and uid, inode flag,
+/// device and inode, path length.
+const HEADER: usize = 8 * 4 + 1 + 4 + 1 + 8 * 2 + 4;
+
+/// What a scan keeps about a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub size: u64,
+    pub mtime: u64,
+    pub allocated: u64,
+    pub nlink: u64,
+    pub uid: Option<u32>,
+    /// Device and inode number.
+    pub inode: Option<(u64, u64)>,
+}
+
+impl FileRecord {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let path = self.path.as_os_str().as_encoded_bytes();
+        for n in [self.size, self.mtime, self.allocated, self.nlink] {
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        out.push(self.uid.is_some() as u8);
+        out.extend_from_slice(&self.uid.unwrap_or(0).to_le_bytes());
+        let (dev, ino) = self.inode.unwrap_or_default();
+        out.push(self.inode.is_some() as u8);
+        out.extend_from_slice(&dev.to_le_bytes());
+        out.extend_from_slice(&ino.to_le_bytes());
+        out.extend_from_slice(&(path.len() as u32).to_le_bytes());
+        out.extend_from_slice(path);
+    }
+
+    /// The record whose header is `header`, and the length of its path.
+    fn decode_header(header: &[u8]) -> (Self, usize) {
+        let u64_at = |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+        let record = FileRecord {
+            path: PathBuf::new(),
+            size: u64_at(0),
+            mtime: u64_at(8),
+            allocated: u64_at(16),
+            nlink: u64_at(24),
+            uid: (header[32] != 0).then(|| u32_at(33)),
+            inode: (header[37] != 0).then(|| (u64_at(38), u64_at(46))),
+        };
+        (record, u32_at(54) as usize)
+    }
+}
+
+/// Append-only store of [`FileRecord`]s, addressed by the id [`FileIndex::push`] returns.
+#[derive(Debug)]
+pub struct FileIndex {
+    /// Records not spilled yet.
+    memory: Vec<u8>,
+    /// Where each record starts, counting the spilled bytes first.
+    offsets: Vec<u64>,
+    spill: Option<NamedTempFile>,
+    spilled: u64,
+    budget: usize,
+}
+
+impl FileIndex {
+    /// An index holding up to `budget` bytes of records in memory.
+    pub fn new(budget: usize) -> Self {
+        Self { memory: Vec::new(), offsets: Vec::new(), spill: None, spilled: 0, budget }
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Bytes of records moved to disk so far.
+    pub fn spilled_bytes(&self) -> u64 {
+        self.spilled
+    }
+
+    /// Add a record and return its id.
+    pub fn push(&mut self, record: &FileRecord) -> u32 {
+        let id = self.offsets.len() as u32;
+        self.offsets.push(self.spilled + self.memory.len() as u64);
+        record.encode(&mut self.memory);
+        if self.memory.len() >= self.budget {
+            // Without room on disk the records simply stay in memory
+            if self.spill_memory().is_err() {
+                self.budget = usize::MAX;
+            }
+        }
+        id
+    }
+
+    fn spill_memory(&mut self) -> io::Result<()> {
+        if self.spill.is_none() {
+            self.spill = Some(NamedTempFile::new()?);
+        }
+        self.spill.as_mut().unwrap().as_file_mut().write_all(&self.memory)?;
+        self.spilled += self.memory.len() as u64;
+        self.memory.clear();
+        Ok(())
+    }
+
+    /// The record with the given id.
+    ///
+    /// # Panics
+    ///
+    /// If `id` was not returned by [`push`](Self::push).
+    pub fn get(&self, id: u32) -> io::Result<FileRecord> {
+        let offset = self.offsets[id as usize];
+        if offset >= self.spilled {
+            let start = (offset - self.spilled) as usize;
+            let (mut record, len) = FileRecord::decode_header(&self.memory[start..start + HEADER]);
+            record.path = path_from_bytes(self.memory[start + HEADER..start + HEADER + len].to_vec());
+            return Ok(record);
+        }
+        let spill = self.spill.as_ref().unwrap().as_file();
+        let mut header = [0; HEADER];
+        read_exact_at(spill, &mut header, offset)?;
+        let (mut record, len) = FileRecord::decode_header(&header);
+        let mut path = vec![0; len];
+        read_exact_at(spill, &mut path, offset + HEADER as u64)?;
+        record.path = path_from_bytes(path);
+        Ok(record)
+    }
+
+    /// The path of the record with the given id.
+    pub fn path(&self, id: u32) -> io::Result<PathBuf> {
+        self.get(id).map(|record| record.path)
+    }
+
+    /// Call `f` with every record and its id, in id order, reading spilled records sequentially.
+    pub fn for_each<F: FnMut(u32, FileRecord)>(&self, mut f: F) -> io::Result<()> {
+        let mut id = 0;
+        if let Some(spill) = &self.spill {
+            // A handle of its own, so that reading leaves the append position alone
+            let mut reader = BufReader::new(File::open(spill.path())?);
+            let mut header = [0; HEADER];
+            while id < self.offsets.len() && self.offsets[id] < self.spilled {
+                reader.read_exact(&mut header)?;
+                let (mut record, len) = FileRecord::decode_header(&header);
+                let mut path = vec![0; len];
+                reader.read_exact(&mut path)?;
+                record.path = path_from_bytes(path);
+                f(id as u32, record);
+                id += 1;
+            }
+        }
+        for id in id..self.offsets.len() {
+            f(id as u32, self.get(id as u32)?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(not(unix))]
+fn read_exact_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom};
+    file.seek(SeekFrom::Start(offset))?;
+    let read = file.read_exact(buf);
+    // Later spills append where the cursor is
+    file.seek(SeekFrom::End(0))?;
+    read
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes the record for `path` takes.
+    fn record_len(path: &str) -> usize {
+        HEADER + path.len()
+    }
+
+    fn record(path: &str, size: u64) -> FileRecord {
+        FileRecord { path: PathBuf::from(path), size, mtime: 7, allocated: 4096, nlink: 1, uid: Some(1000), inode: Some((64769, size + 2)) }
+    }
+
+    #[test]
+    fn test_index_spills_and_reads_back() {
+        let records: Vec<FileRecord> = (0..50).map(|i| record(&format!("/data/file-{}.bin", i), i)).collect();
+        // Spill after every few records
+        let mut index = FileIndex::new(record_len("/data/file-00.bin") * 3);
+        let ids: Vec<u32> = records.iter().map(|r| index.push(r)).collect();
+        assert_eq!(index.len(), 50);
+        assert!(index.spilled_bytes() > 0);
+        for (id, r) in ids.iter().zip(&records).rev() {
+            assert_eq!(&index.get(*id).unwrap(), r);
+        }
+        let mut seen = Vec::new();
+        index.for_each(|id, r| seen.push((id, r))).unwrap();
+        assert_eq!(seen.len(), 50);
+        assert!(seen.iter().all(|(id, r)| r == &records[*id as usize]));
+
+        let mut index = FileIndex::new(DEFAULT_INDEX_MEMORY);
+        let id = index.push(&FileRecord { uid: None, inode: None, ..record("/x", 0) });
+        assert_eq!(index.spilled_bytes(), 0);
+        assert_eq!(index.get(id).unwrap().uid, None);
+        assert_eq!(index.get(id).unwrap().inode, None);
+    }
+}
@@ -0,0 +1,214 @@
+//! Choosing which file of a duplicate group survives.
+//!
+//! A [`KeepPolicy`] is an ordered list of [`KeepRule`]s. Each rule narrows the
+//! candidates down to the ones it likes best, later rules only break the ties
+//! left by earlier ones, and whatever still ties at the end is settled by path
+//! order so the choice never depends on walk order.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// One criterion for picking the file to keep.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum KeepRule {
+    /// Oldest modification time
+    Oldest,
+    /// Newest modification time
+    Newest,
+    ShortestPath,
+    LongestPath,
+    /// Found under the earliest target given
+    RootOrder,
+    /// Inside this directory; see [`KeepPolicy::resolve`]
+    InDir(PathBuf),
+    /// Path matches this pattern
+    Matches(Regex),
+}
+
+impl FromStr for KeepRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(dir) = s.strip_prefix("dir:") {
+            return Ok(KeepRule::InDir(PathBuf::from(dir)));
+        }
+        if let Some(pattern) = s.strip_prefix("regex:") {
+            return Regex::new(pattern)
+                .map(KeepRule::Matches)
+                .map_err(|e| format!("invalid regex pattern: {}", e));
+        }
+        match s {
+            "oldest" => Ok(KeepRule::Oldest),
+            "newest" => Ok(KeepRule::Newest),
+            "shortest-path" => Ok(KeepRule::ShortestPath),
+            "longest-path" => Ok(KeepRule::LongestPath),
+            "root-order" => Ok(KeepRule::RootOrder),
+            _ => Err(format!(
+                "unknown keep rule '{}' (expected oldest, newest, shortest-path, longest-path, root-order, dir:PATH or regex:PATTERN)",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for KeepRule {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<KeepRule> for String {
+    fn from(rule: KeepRule) -> Self {
+        rule.to_string()
+    }
+}
+
+impl fmt::Display for KeepRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeepRule::Oldest => write!(f, "oldest"),
+            KeepRule::Newest => write!(f, "newest"),
+            KeepRule::ShortestPath => write!(f, "shortest-path"),
+            KeepRule::LongestPath => write!(f, "longest-path"),
+            KeepRule::RootOrder => write!(f, "root-order"),
+            KeepRule::InDir(dir) => write!(f, "dir:{}", dir.display()),
+            KeepRule::Matches(re) => write!(f, "regex:{}", re.as_str()),
+        }
+    }
+}
+
+/// What a policy gets to know about each file in a group.
+#[derive(Debug, Clone)]
+pub struct KeepCandidate<'a> {
+    pub path: &'a Path,
+    pub mtime: u64,
+    /// Index of the target the file was found under, in the order given.
+    pub root: Option<usize>,
+}
+
+impl KeepRule {
+    /// Lower is better.
+    fn score(&self, c: &KeepCandidate) -> i128 {
+        let miss = |hit: bool| if hit { 0 } else { 1 };
+        match self {
+            KeepRule::Oldest => c.mtime as i128,
+            KeepRule::Newest => -(c.mtime as i128),
+            KeepRule::ShortestPath => c.path.as_os_str().len() as i128,
+            KeepRule::LongestPath => -(c.path.as_os_str().len() as i128),
+            KeepRule::RootOrder => c.root.map_or(i128::MAX, |r| r as i128),
+            KeepRule::InDir(dir) => miss(c.path.starts_with(dir)),
+            KeepRule::Matches(re) => miss(re.is_match(&c.path.to_string_lossy())),
+        }
+    }
+}
+
+/// Name given to the fallback when no rule could tell the candidates apart.
+pub const PATH_ORDER: &str = "path-order";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct KeepPolicy {
+    pub rules: Vec<KeepRule>,
+}
+
+impl KeepPolicy {
+    pub fn new(rules: Vec<KeepRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Index of the file to keep, and the name of the rule that picked it.
+    pub fn choose(&self, candidates: &[KeepCandidate]) -> (usize, String) {
+        let mut left: Vec<usize> = (0..candidates.len()).collect();
+        for rule in &self.rules {
+            let best = left.iter().map(|&i| rule.score(&candidates[i])).min();
+            let narrowed: Vec<usize> = left
+                .iter()
+                .copied()
+                .filter(|&i| Some(rule.score(&candidates[i])) == best)
+                .collect();
+            if narrowed.len() < left.len() {
+                left = narrowed;
+                if left.len() == 1 {
+                    return (left[0], rule.to_string());
+                }
+            }
+        }
+        let first = left.into_iter().min_by_key(|&i| candidates[i].path).unwrap_or(0);
+        (first, PATH_ORDER.to_string())
+    }
+
+    /// The same policy with every `dir:` rule made canonical, like the paths
+    /// it is matched against.
+    ///
+    /// A directory that doesn't resolve is an error, since its rule could
+    /// never match.
+    pub fn resolve(&self) -> Result<Self, String> {
+        let rules = self
+            .rules
+            .iter()
+            .map(|rule| match rule {
+                KeepRule::InDir(dir) => fs::canonicalize(dir).map(KeepRule::InDir).map_err(|e| format!("{}: {}", rule, e)),
+                rule => Ok(rule.clone()),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(path: &str, mtime: u64, root: usize) -> KeepCandidate<'_> {
+        KeepCandidate { path: Path::new(path), mtime, root: Some(root) }
+    }
+
+    #[test]
+    fn test_rules_break_ties_in_order() {
+        let files = [
+            candidate("/b/archive/photo.jpg", 100, 1),
+            candidate("/a/photo.jpg", 100, 0),
+            candidate("/a/tmp/photo.jpg", 50, 0),
+        ];
+        let policy = |rules: &str| KeepPolicy::new(rules.split(' ').map(|r| r.parse().unwrap()).collect());
+
+        assert_eq!(policy("oldest").choose(&files), (2, "oldest".to_string()));
+        assert_eq!(policy("shortest-path").choose(&files), (1, "shortest-path".to_string()));
+        // Both candidates under /a tie on root order, newest decides
+        assert_eq!(policy("root-order newest").choose(&files), (1, "newest".to_string()));
+        assert_eq!(policy("dir:/b").choose(&files), (0, "dir:/b".to_string()));
+        assert_eq!(policy("regex:/tmp/ regex:archive").choose(&files), (2, "regex:/tmp/".to_string()));
+        // Nothing matches the first pattern, the second one decides
+        assert_eq!(policy("regex:nope regex:archive").choose(&files), (0, "regex:archive".to_string()));
+        assert_eq!(KeepPolicy::default().choose(&files), (1, PATH_ORDER.to_string()));
+    }
+
+    #[test]
+    fn test_resolve_makes_dirs_canonical() {
+        let policy = KeepPolicy::new(vec!["dir:.".parse().unwrap(), KeepRule::Newest]).resolve().unwrap();
+        let cwd = std::env::current_dir().unwrap().canonicalize().unwrap();
+        assert!(matches!(&policy.rules[0], KeepRule::InDir(dir) if *dir == cwd));
+        assert!(matches!(policy.rules[1], KeepRule::Newest));
+
+        let missing = KeepPolicy::new(vec!["dir:does/not/exist".parse().unwrap()]).resolve();
+        assert!(missing.unwrap_err().starts_with("dir:does/not/exist: "));
+    }
+
+    #[test]
+    fn test_rule_parsing_roundtrip() {
+        for s in ["oldest", "longest-path", "root-order", "dir:/srv/master", "regex:^/keep/.*\\.raw$"] {
+            assert_eq!(s.parse::<KeepRule>().unwrap().to_string(), s);
+        }
+        assert!("biggest".parse::<KeepRule>().is_err());
+        assert!("regex:(".parse::<KeepRule>().is_err());
+        let policy: KeepPolicy = serde_json::from_str(r#"["newest", "dir:/a"]"#).unwrap();
+        assert_eq!(policy.rules.len(), 2);
+    }
+}
@@ -1,14 +1,33 @@
 //! Deduplication core library
 
+pub mod backup;
+pub mod cache;
 pub mod cli;
+pub mod compare;
+pub mod config;
+pub mod dirtree;
+pub mod filter;
 pub mod hashing;
+pub mod ignore;
+pub mod index;
+pub mod keep;
+pub mod mounts;
+pub mod owner;
+pub mod paths;
+pub mod report;
+pub mod scanner;
 pub mod similarity;
 pub mod safety;
 pub mod types;
+pub mod walker;
 
 // Re-export commonly used items
+pub use backup::*;
 pub use cli::*;
+pub use compare::*;
 pub use hashing::*;
+pub use keep::*;
+pub use scanner::*;
 pub use similarity::*;
 pub use safety::*;
-pub use types::*;
+pub use walker::*;
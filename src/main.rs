@@ -1,13 +1,30 @@
 // Import from the library crate
 use dedcore::*;
 
+use clap::CommandFactory;
+use std::io::{self, IsTerminal, Write};
+use std::process::ExitCode;
+
 mod ui;  // Keep UI in the binary
 
-fn main() {
+fn main() -> ExitCode {
+    let args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+
+    // Any argument means we're being scripted: hand straight off to the CLI
+    if args.len() > 1 {
+        return cli::run_with_args(args);
+    }
+
+    // Bare invocation without a terminal (cron, CI, pipes): the menu would
+    // block forever on a prompt nobody can answer, so print usage instead.
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        let _ = cli::App::command().print_help();
+        return ExitCode::from(2);
+    }
+
     // Clear the terminal screen at program start
     print!("\x1B[2J\x1B[1;1H");
-    use std::io::{Write, stdout};
-    stdout().flush().ok();
+    io::stdout().flush().ok();
     ui::show_loading_screen();
     loop {
         let action = ui::main_menu();
@@ -22,4 +39,5 @@ fn main() {
             break;
         }
     }
+    ExitCode::SUCCESS
 }
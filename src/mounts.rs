@@ -0,0 +1,125 @@
+//! The mount table, for keeping walks out of pseudo filesystems.
+//!
+//! Filesystems such as `proc` and `sysfs` hold no real files, only views of
+//! the kernel that are huge, change while being read, or block when read. The
+//! walker never enters a directory where one is mounted, unless it was given
+//! as a target. The table comes from `/proc/self/mountinfo`; where that is
+//! missing nothing is known to be a pseudo filesystem.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Filesystem types that never hold files worth scanning.
+pub const PSEUDO_FILESYSTEMS: &[&str] = &[
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "nsfs",
+    "proc",
+    "pstore",
+    "rpc_pipefs",
+    "securityfs",
+    "selinuxfs",
+    "sysfs",
+    "tracefs",
+];
+
+/// A directory the walk did not descend into because of what is mounted there.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedMount {
+    #[serde(with = "crate::paths")]
+    pub path: PathBuf,
+    /// Filesystem type, when the mount table lists it.
+    pub fstype: Option<String>,
+}
+
+/// Mount points and their filesystem types, parsed from mountinfo text.
+///
+/// Later lines win, since a later mount on the same point hides the earlier one.
+fn parse_mountinfo(text: &str) -> HashMap<PathBuf, String> {
+    let mut mounts = HashMap::new();
+    for line in text.lines() {
+        // Optional fields end at a lone '-', then come the type and source
+        let Some((before, after)) = line.split_once(" - ") else { continue };
+        let (Some(point), Some(fstype)) = (before.split(' ').nth(4), after.split(' ').next()) else { continue };
+        mounts.insert(PathBuf::from(unescape(point)), fstype.to_string());
+    }
+    mounts
+}
+
+/// Undo the octal escapes mountinfo uses for spaces, tabs, newlines and backslashes.
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(at) = rest.find('\\') {
+        out.push_str(&rest[..at]);
+        let code = rest.get(at + 1..at + 4).and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[at + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[at + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn mount_table() -> &'static HashMap<PathBuf, String> {
+    static TABLE: OnceLock<HashMap<PathBuf, String>> = OnceLock::new();
+    TABLE.get_or_init(|| fs::read_to_string("/proc/self/mountinfo").map(|t| parse_mountinfo(&t)).unwrap_or_default())
+}
+
+/// Type of the filesystem mounted at exactly `path`, if one is.
+pub fn mounted_at(path: &Path) -> Option<&'static str> {
+    mount_table().get(path).map(String::as_str)
+}
+
+pub fn is_pseudo(fstype: &str) -> bool {
+    PSEUDO_FILESYSTEMS.contains(&fstype)
+}
+
+/// Whether `path` is on a pseudo filesystem, judged by the nearest mount at or above it.
+pub fn in_pseudo_filesystem(path: &Path) -> bool {
+    path.ancestors().find_map(mounted_at).is_some_and(is_pseudo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let text = "\
+23 28 0:22 / /proc rw,relatime - proc proc rw
+28 1 254:0 / / rw,relatime - ext4 /dev/vda rw
+36 35 98:0 /mnt1 /mnt/my\\040disk rw,noatime master:1 shared:7 - ext3 /dev/root rw
+37 28 0:5 / /mnt/my\\040disk rw - tmpfs tmpfs rw
+garbage
+";
+        let mounts = parse_mountinfo(text);
+        assert_eq!(mounts.len(), 3);
+        assert_eq!(mounts[Path::new("/proc")], "proc");
+        // Mounted over the ext3 one
+        assert_eq!(mounts[Path::new("/mnt/my disk")], "tmpfs");
+        assert_eq!(unescape("a\\134b\\011c\\x"), "a\\b\tc\\x");
+        assert!(is_pseudo("sysfs") && !is_pseudo("ext4"));
+    }
+}
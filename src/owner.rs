@@ -0,0 +1,178 @@
+//! File ownership and permissions.
+//!
+//! Owners are looked up in `/etc/passwd` and `/etc/group`, which covers local
+//! accounts; ids from a directory service that aren't mirrored there are shown
+//! as numbers. Whether a file can be deleted follows the Unix rules: write and
+//! search permission on its directory, and in a sticky directory owning the
+//! file or the directory. The process identity comes from `/proc`; where
+//! that is missing every file counts as deletable, and on platforms without
+//! Unix metadata owner and mode filters match nothing.
+
+use std::fs::{self, Metadata};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// User and group ids of the file's owner.
+#[cfg(unix)]
+pub fn owner_ids(meta: &Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.uid(), meta.gid()))
+}
+
+#[cfg(not(unix))]
+pub fn owner_ids(_meta: &Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+/// Permission bits, including setuid, setgid and sticky.
+#[cfg(unix)]
+pub fn mode_bits(meta: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub fn mode_bits(_meta: &Metadata) -> Option<u32> {
+    None
+}
+
+/// Find `key` in a passwd or group style file, by name or by id.
+fn lookup(table: &str, key: &str) -> Option<(String, u32)> {
+    table.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let id: u32 = fields.nth(1)?.parse().ok()?;
+        (name == key || id.to_string() == key).then(|| (name.to_string(), id))
+    })
+}
+
+fn lookup_file(path: &str, key: &str) -> Option<(String, u32)> {
+    lookup(&fs::read_to_string(path).ok()?, key)
+}
+
+/// Name of the user with this id.
+pub fn user_name(uid: u32) -> Option<String> {
+    lookup_file("/etc/passwd", &uid.to_string()).map(|(name, _)| name)
+}
+
+/// A user id from a name or a number.
+pub fn parse_user(s: &str) -> Result<u32, String> {
+    s.parse()
+        .ok()
+        .or_else(|| lookup_file("/etc/passwd", s).map(|(_, id)| id))
+        .ok_or_else(|| format!("unknown user '{}'", s))
+}
+
+/// A group id from a name or a number.
+pub fn parse_group(s: &str) -> Result<u32, String> {
+    s.parse()
+        .ok()
+        .or_else(|| lookup_file("/etc/group", s).map(|(_, id)| id))
+        .ok_or_else(|| format!("unknown group '{}'", s))
+}
+
+/// A mode in octal, such as `644` or `0o2775`.
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("invalid mode '{}' (expected octal, e.g. 644)", s)),
+    }
+}
+
+/// The user the scan runs as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub uid: u32,
+    pub gids: Vec<u32>,
+}
+
+impl Identity {
+    /// Effective ids of this process, where the platform exposes them.
+    pub fn current() -> Option<&'static Self> {
+        static CURRENT: OnceLock<Option<Identity>> = OnceLock::new();
+        CURRENT.get_or_init(Identity::read).as_ref()
+    }
+
+    fn read() -> Option<Self> {
+        let status = fs::read_to_string("/proc/self/status").ok()?;
+        // Uid and Gid list real, effective, saved and filesystem ids
+        let field = |name: &str| -> Vec<u32> {
+            status
+                .lines()
+                .find_map(|l| l.strip_prefix(name))
+                .map(|rest| rest.split_whitespace().filter_map(|n| n.parse().ok()).collect())
+                .unwrap_or_default()
+        };
+        let uid = *field("Uid:").get(1)?;
+        let mut gids = field("Groups:");
+        gids.push(*field("Gid:").get(1)?);
+        Some(Identity { uid, gids })
+    }
+
+    /// Whether this user may unlink a file owned by `file_uid` from a directory
+    /// with the given owner and mode.
+    pub fn may_unlink(&self, file_uid: u32, dir_uid: u32, dir_gid: u32, dir_mode: u32) -> bool {
+        if self.uid == 0 {
+            return true;
+        }
+        let bits = if self.uid == dir_uid {
+            dir_mode >> 6
+        } else if self.gids.contains(&dir_gid) {
+            dir_mode >> 3
+        } else {
+            dir_mode
+        };
+        // Write to change the directory, search to reach the entry
+        if bits & 0o3 != 0o3 {
+            return false;
+        }
+        dir_mode & 0o1000 == 0 || self.uid == file_uid || self.uid == dir_uid
+    }
+
+    /// Whether this user may delete, or move away, the file at `path`.
+    ///
+    /// When the directory can't be inspected the answer is no.
+    pub fn can_delete(&self, path: &Path, meta: &Metadata) -> bool {
+        let Some(dir) = path.parent() else { return false };
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let Ok(dir_meta) = fs::metadata(dir) else { return false };
+        match (owner_ids(meta), owner_ids(&dir_meta), mode_bits(&dir_meta)) {
+            (Some((file_uid, _)), Some((dir_uid, dir_gid)), Some(dir_mode)) => {
+                self.may_unlink(file_uid, dir_uid, dir_gid, dir_mode)
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_and_modes() {
+        let passwd = "root:x:0:0:root:/root:/bin/sh\n# comment\nalice:x:1000:1000::/home/alice:/bin/sh\n";
+        assert_eq!(lookup(passwd, "alice"), Some(("alice".to_string(), 1000)));
+        assert_eq!(lookup(passwd, "1000"), Some(("alice".to_string(), 1000)));
+        assert_eq!(lookup(passwd, "bob"), None);
+        assert_eq!(parse_user("4242"), Ok(4242));
+        assert_eq!(parse_mode("644"), Ok(0o644));
+        assert_eq!(parse_mode("0o2775"), Ok(0o2775));
+        assert!(parse_mode("999").is_err());
+    }
+
+    #[test]
+    fn test_may_unlink() {
+        let alice = Identity { uid: 1000, gids: vec![1000, 50] };
+        assert!(alice.may_unlink(0, 1000, 1000, 0o755));
+        assert!(!alice.may_unlink(1000, 0, 0, 0o755));
+        // Group write through a supplementary group
+        assert!(alice.may_unlink(0, 0, 50, 0o775));
+        assert!(!alice.may_unlink(0, 0, 50, 0o765));
+        // Sticky /tmp: only the user's own files
+        assert!(alice.may_unlink(1000, 0, 0, 0o1777));
+        assert!(!alice.may_unlink(1001, 0, 0, 0o1777));
+        assert!(Identity { uid: 0, gids: vec![0] }.may_unlink(1001, 1001, 1001, 0o500));
+    }
+}
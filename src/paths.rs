@@ -0,0 +1,173 @@
+//! Lossless serialization of paths.
+//!
+//! Unix paths are arbitrary bytes, and serde's own `PathBuf` support fails on
+//! any that aren't UTF-8. Here a path that is valid UTF-8 is written as a plain
+//! string, just as before, and any other as `{"bytes": "..."}`: its bytes with
+//! every one that isn't part of valid UTF-8 written as `\xNN`, and backslashes
+//! doubled. Both forms are read back to the exact original path.
+//!
+//! Use `#[serde(with = "crate::paths")]` on a `PathBuf` field, and
+//! [`paths::vec`](vec) on a `Vec<PathBuf>`.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Repr {
+    Text(String),
+    Bytes { bytes: String },
+}
+
+/// `bytes` as text, with invalid UTF-8 as `\xNN` escapes and backslashes doubled.
+pub fn escape_bytes(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        out.push_str(&chunk.valid().replace('\\', "\\\\"));
+        for byte in chunk.invalid() {
+            out.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    out
+}
+
+/// Undo [`escape_bytes`].
+pub fn unescape_bytes(text: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('\\') {
+        out.extend_from_slice(&rest.as_bytes()[..at]);
+        rest = &rest[at + 1..];
+        if let Some(after) = rest.strip_prefix('\\') {
+            out.push(b'\\');
+            rest = after;
+        } else {
+            let byte = rest
+                .strip_prefix('x')
+                .and_then(|hex| hex.get(..2))
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| format!("invalid escape in path '{}'", text))?;
+            out.push(byte);
+            rest = &rest[3..];
+        }
+    }
+    out.extend_from_slice(rest.as_bytes());
+    Ok(out)
+}
+
+#[cfg(unix)]
+fn to_repr(path: &Path) -> Repr {
+    use std::os::unix::ffi::OsStrExt;
+    match path.to_str() {
+        Some(text) => Repr::Text(text.to_string()),
+        None => Repr::Bytes { bytes: escape_bytes(path.as_os_str().as_bytes()) },
+    }
+}
+
+// Elsewhere a path that isn't Unicode can't be rebuilt from bytes, so it is written lossily
+#[cfg(not(unix))]
+fn to_repr(path: &Path) -> Repr {
+    Repr::Text(path.to_string_lossy().into_owned())
+}
+
+fn from_repr(repr: Repr) -> Result<PathBuf, String> {
+    match repr {
+        Repr::Text(text) => Ok(PathBuf::from(text)),
+        Repr::Bytes { bytes } => Ok(path_from_bytes(unescape_bytes(&bytes)?)),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+pub fn serialize<P, S>(path: &P, serializer: S) -> Result<S::Ok, S::Error>
+where
+    P: AsRef<Path> + ?Sized,
+    S: Serializer,
+{
+    to_repr(path.as_ref()).serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    from_repr(Repr::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// A path that serializes losslessly, for use inside other containers.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LosslessPath(pub PathBuf);
+
+impl Serialize for LosslessPath {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for LosslessPath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize(deserializer).map(LosslessPath)
+    }
+}
+
+/// For `Vec<PathBuf>` fields.
+pub mod vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(paths.iter().map(|p| to_repr(p)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+        let paths: Vec<LosslessPath> = Vec::deserialize(deserializer)?;
+        Ok(paths.into_iter().map(|p| p.0).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Record {
+        #[serde(with = "crate::paths")]
+        path: PathBuf,
+        #[serde(with = "crate::paths::vec")]
+        others: Vec<PathBuf>,
+    }
+
+    #[test]
+    fn test_escapes_round_trip() {
+        let bytes = b"caf\xe9 \\ \xff\xfe/ok \xc3\xa9";
+        let escaped = escape_bytes(bytes);
+        assert_eq!(escaped, "caf\\xe9 \\\\ \\xff\\xfe/ok \u{e9}");
+        assert_eq!(unescape_bytes(&escaped).unwrap(), bytes);
+        assert!(unescape_bytes("bad\\q").is_err());
+        assert!(unescape_bytes("short\\x4").is_err());
+    }
+
+    #[test]
+    fn test_utf8_paths_stay_plain_strings() {
+        let record = Record { path: PathBuf::from("/data/a\\b.txt"), others: vec![PathBuf::from("/x")] };
+        let json = serde_json::to_string(&record).unwrap();
+        assert_eq!(json, r#"{"path":"/data/a\\b.txt","others":["/x"]}"#);
+        assert_eq!(serde_json::from_str::<Record>(&json).unwrap(), record);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths_round_trip() {
+        let odd = path_from_bytes(b"/data/caf\xe9.txt".to_vec());
+        let record = Record { path: odd.clone(), others: vec![odd.clone(), PathBuf::from("/y")] };
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.contains(r#"{"bytes":"/data/caf\\xe9.txt"}"#));
+        assert_eq!(serde_json::from_str::<Record>(&json).unwrap(), record);
+    }
+}
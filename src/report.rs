@@ -0,0 +1,295 @@
+//! JSON and HTML rendering of a [`ScanReport`].
+
+use crate::scanner::ScanReport;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A path for display; names that aren't valid UTF-8 only appear lossily in HTML.
+fn escape_path(path: &Path) -> String {
+    escape_html(&path.to_string_lossy())
+}
+
+fn mb(bytes: u64) -> f64 {
+    bytes as f64 / 1024.0 / 1024.0
+}
+
+/// Write the full report as pretty-printed JSON.
+pub fn write_json_report<P: AsRef<Path>>(report: &ScanReport, path: P) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(report)?;
+    fs::write(path, json)
+}
+
+fn html_hash_table(report: &ScanReport) -> String {
+    let mut html = String::from("<table border=1><tr><th>File</th><th>Hash</th><th>Algorithm</th></tr>");
+    for r in &report.hashes {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_path(&r.file),
+            r.hash,
+            r.algorithm
+        ));
+    }
+    html.push_str("</table>");
+    html
+}
+
+fn html_groups(report: &ScanReport) -> String {
+    let mut html = String::new();
+    if !report.directory_groups.is_empty() {
+        html.push_str("<h2>Duplicate Directories</h2>");
+        for (i, group) in report.directory_groups.iter().enumerate() {
+            html.push_str(&format!(
+                "<b>Group {}</b> ({} files and {} bytes each)<ul>",
+                i + 1,
+                group.files,
+                group.size
+            ));
+            for (j, d) in group.dirs.iter().enumerate() {
+                let mut item = format!("{}/", escape_path(d));
+                if j == 0 {
+                    item = format!("<b>{}</b> <i>(kept: {})</i>", item, escape_html(&group.kept_by));
+                } else if group.reference.contains(d) {
+                    item.push_str(" <i>(reference)</i>");
+                }
+                html.push_str(&format!("<li>{}</li>", item));
+            }
+            html.push_str("</ul>");
+        }
+    }
+    // Groups inside duplicated directories are covered by the section above
+    let shown: Vec<_> = report.duplicate_groups.iter().filter(|g| !g.in_duplicate_dir).collect();
+    if !shown.is_empty() {
+        html.push_str("<h2>Duplicate File Groups</h2>");
+        for (i, group) in shown.into_iter().enumerate() {
+            let verified = if group.verified { ", verified byte-for-byte" } else { "" };
+            html.push_str(&format!(
+                "<b>Group {}</b> ({} bytes each{})<ul>",
+                i + 1,
+                group.size,
+                verified
+            ));
+            for (j, f) in group.files.iter().enumerate() {
+                let mut item = escape_path(f);
+                if let Some(root) = report.root_of(f).filter(|_| report.roots.len() > 1) {
+                    item.push_str(&format!(" <small>[{}]</small>", escape_path(&root.given)));
+                }
+                if j == 0 {
+                    item = format!("<b>{}</b> <i>(kept: {})</i>", item, escape_html(&group.kept_by));
+                } else if group.reference.contains(f) {
+                    item.push_str(" <i>(reference)</i>");
+                }
+                html.push_str(&format!("<li>{}</li>", item));
+            }
+            for f in &group.hardlinks {
+                html.push_str(&format!("<li>{} <i>(hardlink, shares storage)</i></li>", escape_path(f)));
+            }
+            html.push_str("</ul>");
+        }
+    }
+    if !report.hardlink_sets.is_empty() {
+        html.push_str("<h2>Hardlinked Files</h2><p>Already sharing storage, not counted as duplicates.</p><ul>");
+        for set in &report.hardlink_sets {
+            let files: Vec<String> = set.files.iter().map(|f| escape_path(f)).collect();
+            html.push_str(&format!("<li>{}</li>", files.join(" = ")));
+        }
+        html.push_str("</ul>");
+    }
+    if !report.symlinks.is_empty() {
+        html.push_str("<h2>Symbolic Links</h2><p>Never counted as duplicates of what they point to.</p>");
+        html.push_str("<table border=1><tr><th>Link</th><th>Target</th><th>Status</th></tr>");
+        for link in &report.symlinks {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_path(&link.path),
+                escape_path(&link.target),
+                link.status
+            ));
+        }
+        html.push_str("</table>");
+    }
+    // With a single owner this would only repeat the total
+    if report.owners.len() > 1 {
+        html.push_str("<h2>Duplicate Bytes by Owner</h2><table border=1><tr><th>Owner</th><th>Copies</th><th>MB</th></tr>");
+        for owner in &report.owners {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{:.2}</td></tr>",
+                escape_html(&owner.label()),
+                owner.files,
+                mb(owner.bytes)
+            ));
+        }
+        html.push_str("</table>");
+    }
+    if !report.collisions.is_empty() {
+        html.push_str("<h2>Hash Collisions</h2><p>These files share a digest but their contents differ.</p>");
+        for c in &report.collisions {
+            html.push_str(&format!("<b>{}</b> ({} bytes each)<ul>", c.hash, c.size));
+            for f in &c.files {
+                html.push_str(&format!("<li>{}</li>", escape_path(f)));
+            }
+            html.push_str("</ul>");
+        }
+    }
+    for (title, groups) in [
+        ("Similar Text File Groups", &report.similar_text_groups),
+        ("Similar Image File Groups", &report.similar_image_groups),
+    ] {
+        if groups.is_empty() {
+            continue;
+        }
+        html.push_str(&format!("<h2>{}</h2>", title));
+        for (i, group) in groups.iter().enumerate() {
+            html.push_str(&format!("<b>Group {}</b><ul>", i + 1));
+            for f in &group.files {
+                html.push_str(&format!(
+                    "<li>{} ({:.0}%)</li>",
+                    escape_path(&f.file),
+                    f.similarity * 100.0
+                ));
+            }
+            html.push_str("</ul>");
+        }
+    }
+    html
+}
+
+fn html_errors(report: &ScanReport) -> String {
+    if report.errors.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from("<h2>Files That Could Not Be Read</h2><table border=1><tr><th>File</th><th>Error</th></tr>");
+    for e in &report.errors {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td></tr>",
+            escape_path(&e.file),
+            escape_html(&e.reason)
+        ));
+    }
+    html.push_str("</table>");
+    html
+}
+
+/// Write a standalone HTML page for the report.
+pub fn write_html_report<P: AsRef<Path>>(report: &ScanReport, path: P) -> io::Result<()> {
+    let mut html = String::from(
+        "<html><head><title>dedcore Report</title></head><body><h1>dedcore File Hash Report</h1>",
+    );
+    html.push_str(&html_hash_table(report));
+    html.push_str(&html_groups(report));
+    html.push_str(&html_errors(report));
+    html.push_str("</body></html>");
+    fs::write(path, html)
+}
+
+/// Append the report to the running scan history in `json_path` and `html_path`.
+pub fn append_to_history<P: AsRef<Path>, Q: AsRef<Path>>(
+    report: &ScanReport,
+    json_path: P,
+    html_path: Q,
+) -> io::Result<()> {
+    let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let stats = &report.stats;
+
+    // JSON append
+    let mut all_scans = fs::read_to_string(&json_path)
+        .ok()
+        .and_then(|existing| serde_json::from_str::<serde_json::Value>(&existing).ok())
+        .unwrap_or_else(|| serde_json::json!([]));
+    let scan_entry = serde_json::json!({
+        "timestamp": timestamp,
+        "results": report.hashes,
+        "duplicate_groups": report.duplicate_groups,
+        "errors": report.errors,
+        "summary": {
+            "processed_files": stats.files_scanned,
+            "failed_files": stats.files_failed,
+            "duplicate_groups": stats.duplicate_groups,
+            "potential_space_savings_bytes": stats.wasted_bytes,
+            "potential_space_savings_mb": mb(stats.wasted_bytes),
+        }
+    });
+    if let Some(arr) = all_scans.as_array_mut() {
+        arr.push(scan_entry);
+    }
+    fs::write(&json_path, serde_json::to_string_pretty(&all_scans)?)?;
+
+    // HTML append
+    let mut html = fs::read_to_string(&html_path)
+        .unwrap_or_else(|_| String::from("<html><head><title>dedcore Report</title></head><body>"));
+    html.push_str(&format!("<h2>Scan at {}</h2>", timestamp));
+    html.push_str(&html_hash_table(report));
+    html.push_str(&html_errors(report));
+    html.push_str("<br/>");
+    if stats.wasted_bytes > 0 {
+        html.push_str(&format!(
+            "<p>Potential space savings: {:.2} MB</p>",
+            mb(stats.wasted_bytes)
+        ));
+    }
+    fs::write(&html_path, html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{DuplicateGroup, FileError, ScanReport};
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    fn sample_report() -> ScanReport {
+        let mut report = ScanReport::default();
+        report.duplicate_groups.push(DuplicateGroup {
+            hash: "ab".to_string(),
+            size: 10,
+            files: vec![PathBuf::from("<a>"), PathBuf::from("b")],
+            kept_by: "oldest".to_string(),
+            reference: Vec::new(),
+            hardlinks: Vec::new(),
+            reclaimable: 10,
+            verified: true,
+            in_duplicate_dir: false,
+        });
+        report.stats.files_scanned = 2;
+        report.stats.duplicate_groups = 1;
+        report.stats.wasted_bytes = 10;
+        report.errors.push(FileError {
+            file: PathBuf::from("locked"),
+            reason: "Permission denied (os error 13)".to_string(),
+        });
+        report.stats.files_failed = 1;
+        report
+    }
+
+    #[test]
+    fn test_html_report_escapes_paths() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("report.html");
+        write_html_report(&sample_report(), &path).unwrap();
+        let html = fs::read_to_string(path).unwrap();
+        assert!(html.contains("<li><b>&lt;a&gt;</b> <i>(kept: oldest)</i></li>"));
+        assert!(html.contains("<li>b</li>"));
+        assert!(html.contains("<td>locked</td><td>Permission denied (os error 13)</td>"));
+    }
+
+    #[test]
+    fn test_append_to_history_accumulates() {
+        let dir = tempdir().unwrap();
+        let json = dir.path().join("history.json");
+        let html = dir.path().join("history.html");
+        append_to_history(&sample_report(), &json, &html).unwrap();
+        append_to_history(&sample_report(), &json, &html).unwrap();
+        let scans: Vec<serde_json::Value> =
+            serde_json::from_str(&fs::read_to_string(json).unwrap()).unwrap();
+        assert_eq!(scans.len(), 2);
+        assert_eq!(scans[1]["summary"]["potential_space_savings_bytes"], 10);
+        assert_eq!(scans[1]["errors"][0]["file"], "locked");
+    }
+}
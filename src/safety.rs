@@ -1,3 +1,4 @@
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
 use std::fs;
 use serde::{Serialize, Deserialize};
@@ -6,59 +7,244 @@ use std::io::{Read, Write};
 use dirs;
 use serde_json;
 use chrono::Local;
+use crate::hashing::files_identical;
+use crate::walker::file_id;
+
+/// Path to `target` as seen from directory `from`. Both must be absolute.
+fn relative_path(from: &Path, target: &Path) -> PathBuf {
+    let from: Vec<_> = from.components().collect();
+    let to: Vec<_> = target.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut rel = PathBuf::new();
+    for _ in common..from.len() {
+        rel.push("..");
+    }
+    for c in &to[common..] {
+        rel.push(c);
+    }
+    rel
+}
+
+#[cfg(unix)]
+fn symlink_file(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink_file(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// Unused name next to `path`, for building a replacement before renaming it into place.
+fn temp_sibling(path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let parent = path.parent().ok_or("Invalid path")?;
+    let name = path.file_name().ok_or("Invalid filename")?;
+    for attempt in 0.. {
+        let mut temp = OsString::from(".");
+        temp.push(name);
+        temp.push(format!(".dedcore-{}-{}", std::process::id(), attempt));
+        let candidate = parent.join(temp);
+        if fs::symlink_metadata(&candidate).is_err() {
+            return Ok(candidate);
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(unix)]
+fn file_mode(meta: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode())
+}
+
+#[cfg(not(unix))]
+fn file_mode(_meta: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn restore_mode(path: &Path, mode: Option<u32>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match mode {
+        Some(mode) => fs::set_permissions(path, fs::Permissions::from_mode(mode)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn restore_mode(_path: &Path, _mode: Option<u32>) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Copy `from` to `to`, recursing into directories and recreating symlinks.
+///
+/// Permissions and modification times come along, so the copy can stand in
+/// for the original once that is removed.
+fn copy_tree(from: &Path, to: &Path) -> std::io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    let file_type = meta.file_type();
+    if file_type.is_symlink() {
+        return symlink_file(&fs::read_link(from)?, to);
+    }
+    if file_type.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        // Set last, the entries above would have changed it. Not every
+        // platform can open a directory for this, so it is best effort.
+        let _ = fs::File::open(to).and_then(|f| f.set_modified(meta.modified()?));
+        return restore_mode(to, file_mode(&meta));
+    }
+    if !file_type.is_file() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("{} is neither a file, a directory nor a symlink", from.display()),
+        ));
+    }
+    fs::copy(from, to)?;
+    fs::File::options().write(true).open(to)?.set_modified(meta.modified()?)
+}
+
+/// Copy `from` to `to`, removing the copy again if it fails halfway.
+fn copy_or_clean(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Err(e) = copy_tree(from, to) {
+        let _ = remove_path(to);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Move a file or a whole tree, copying and then removing it when `to` is on another filesystem.
+///
+/// A copy that fails halfway is removed again and the original left in place.
+fn move_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_or_clean(from, to)?;
+            remove_path(from)
+        }
+        result => result,
+    }
+}
+
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct QuarantineRecord {
-    pub original_path: String,
-    pub quarantine_path: String,
+    #[serde(with = "crate::paths")]
+    pub original_path: PathBuf,
+    #[serde(with = "crate::paths")]
+    pub quarantine_path: PathBuf,
+    /// Total size of the files, for a directory of all the files in it.
     pub file_size: u64,
     pub moved_at: std::time::SystemTime,
+    /// The record is a whole directory tree, restored as a unit.
+    #[serde(default)]
+    pub is_dir: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplacementKind {
+    Hardlink,
+    Symlink,
+}
+
+/// A duplicate that was replaced in place by a link to the file that was kept.
+///
+/// The content is still reachable through the kept file, so restoring only
+/// needs a fresh copy of it plus the original permissions and modification time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplacementRecord {
+    #[serde(with = "crate::paths")]
+    pub original_path: PathBuf,
+    #[serde(with = "crate::paths")]
+    pub kept_path: PathBuf,
+    pub kind: ReplacementKind,
+    pub file_size: u64,
+    /// Unix permission bits of the replaced file.
+    #[serde(default)]
+    pub mode: Option<u32>,
+    pub modified: std::time::SystemTime,
+    pub replaced_at: std::time::SystemTime,
 }
 
 #[derive(Debug)]
 pub struct QuarantineManager {
-    moved_files: HashMap<String, QuarantineRecord>,
+    moved_files: HashMap<PathBuf, QuarantineRecord>,
+    replaced_files: HashMap<PathBuf, ReplacementRecord>,
     quarantine_log: PathBuf,
+    replacement_log: PathBuf,
+    recovery_log: PathBuf,
     quarantine_dir: PathBuf,
 }
 
+/// Records stored at `path`, by the path they are about.
+///
+/// Logs are written as a list, since a path that isn't UTF-8 can't be a JSON
+/// key. Logs written as an object keyed by path are still read.
+fn read_records<T, K>(path: &Path, key: K) -> Result<HashMap<PathBuf, T>, Box<dyn std::error::Error>>
+where
+    T: serde::de::DeserializeOwned,
+    K: Fn(&T) -> PathBuf,
+{
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let mut file = fs::File::open(path)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let records: Vec<T> = serde_json::from_str(&contents)
+        .or_else(|_| serde_json::from_str::<HashMap<String, T>>(&contents).map(|keyed| keyed.into_values().collect()))
+        .unwrap_or_default();
+    Ok(records.into_iter().map(|r| (key(&r), r)).collect())
+}
+
+/// Records as written to a log, in path order.
+fn sorted_records<T>(records: &HashMap<PathBuf, T>) -> Vec<&T> {
+    let mut sorted: Vec<(&PathBuf, &T)> = records.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(b.0));
+    sorted.into_iter().map(|(_, r)| r).collect()
+}
+
+/// `path` as a JSON value, in the lossless form used by every log.
+fn path_value(path: &Path) -> serde_json::Value {
+    crate::paths::serialize(path, serde_json::value::Serializer).unwrap_or_default()
+}
+
 impl QuarantineManager {
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let quarantine_log = QuarantineManager::get_quarantine_log_path();
-        let quarantine_dir = QuarantineManager::get_quarantine_dir_path();
+        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+        Self::with_base_dir(home.join(".dedcore"))
+    }
+
+    /// Manager keeping its state, logs and quarantined files under `base` instead of `~/.dedcore`.
+    pub fn with_base_dir<P: AsRef<Path>>(base: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let base = base.as_ref();
+        let quarantine_dir = base.join("quarantine");
         if !quarantine_dir.exists() {
             fs::create_dir_all(&quarantine_dir)?;
         }
-        let moved_files = if quarantine_log.exists() {
-            let mut file = fs::File::open(&quarantine_log)?;
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
-            serde_json::from_str(&contents).unwrap_or_default()
-        } else {
-            HashMap::new()
-        };
+        let quarantine_log = base.join("quarantine.json");
+        let replacement_log = base.join("replacements.json");
         Ok(Self {
-            moved_files,
+            moved_files: read_records(&quarantine_log, |r: &QuarantineRecord| r.original_path.clone())?,
+            replaced_files: read_records(&replacement_log, |r: &ReplacementRecord| r.original_path.clone())?,
             quarantine_log,
+            replacement_log,
+            recovery_log: base.join("dedcore_recovery.json"),
             quarantine_dir,
         })
     }
     
-    fn get_quarantine_log_path() -> PathBuf {
-        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-        let dir = home.join(".dedcore");
-        if !dir.exists() {
-            let _ = fs::create_dir_all(&dir);
-        }
-        dir.join("quarantine.json")
-    }
-    
-    fn get_quarantine_dir_path() -> PathBuf {
-        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-        let dir = home.join(".dedcore").join("quarantine");
-        dir
-    }
-    
     pub fn get_quarantine_stats(&self) -> (usize, u64) {
         let count = self.moved_files.len();
         let total_size: u64 = self.moved_files.values()
@@ -71,90 +257,361 @@ impl QuarantineManager {
         self.moved_files.values().collect()
     }
     
-    pub fn quarantine_file(&mut self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let original_path = PathBuf::from(file_path);
-        
+    pub fn quarantine_file(&mut self, file_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
         // Skip if file doesn't exist
-        if !original_path.exists() {
+        if !file_path.exists() {
             return Ok(());
         }
         
-        let metadata = fs::metadata(&original_path)?;
-        let file_size = metadata.len();
-        
-        // Generate unique quarantine filename
-        let filename = original_path.file_name()
-            .ok_or("Invalid filename")?
-            .to_string_lossy();
-        let quarantine_name = format!("{}_{}", 
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)?
-                .as_secs(),
-            filename);
-        
-        let quarantine_path = self.quarantine_dir.join(quarantine_name);
+        let metadata = fs::metadata(file_path)?;
+        if metadata.is_dir() {
+            return Err(format!("{} is a directory", file_path.display()).into());
+        }
+        self.quarantine_entry(file_path, metadata.len(), false)
+    }
+
+    /// Move `file_path` into the quarantine directory under a fresh name and record it.
+    fn quarantine_entry(&mut self, file_path: &Path, file_size: u64, is_dir: bool) -> Result<(), Box<dyn std::error::Error>> {
+        // Generate unique quarantine filename, keeping the original name byte for byte
+        let filename = file_path.file_name().ok_or("Invalid filename")?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let unique_name = |prefix: String| {
+            let mut name = OsString::from(prefix);
+            name.push(filename);
+            name
+        };
+        // Files with the same name quarantined within a second must not overwrite each other
+        let mut quarantine_path = self.quarantine_dir.join(unique_name(format!("{}_", now)));
+        for attempt in 1.. {
+            if fs::symlink_metadata(&quarantine_path).is_err() {
+                break;
+            }
+            quarantine_path = self.quarantine_dir.join(unique_name(format!("{}-{}_", now, attempt)));
+        }
         
-        // Move file to quarantine, robust to cross-device
-        match fs::rename(&original_path, &quarantine_path) {
-            Ok(_) => {},
-            Err(e) if e.raw_os_error() == Some(18) => {
-                // Cross-device link error, fallback to copy+delete
-                fs::copy(&original_path, &quarantine_path)?;
-                fs::remove_file(&original_path)?;
-            },
+        // Move to quarantine. Across devices only copy for now: the original is
+        // removed once the record pointing at the copy has been saved, so a
+        // removal that fails halfway can still be restored from quarantine.
+        let copied = match fs::rename(file_path, &quarantine_path) {
+            Ok(()) => false,
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                copy_or_clean(file_path, &quarantine_path)?;
+                true
+            }
             Err(e) => return Err(Box::new(e)),
-        }
+        };
         
         let record = QuarantineRecord {
-            original_path: file_path.to_string(),
-            quarantine_path: quarantine_path.to_string_lossy().to_string(),
+            original_path: file_path.to_path_buf(),
+            quarantine_path,
             file_size,
             moved_at: std::time::SystemTime::now(),
+            is_dir,
         };
         
-        self.moved_files.insert(file_path.to_string(), record.clone());
+        self.moved_files.insert(file_path.to_path_buf(), record.clone());
+        if let Err(e) = self.save_state() {
+            if copied {
+                // The original is untouched, drop the copy nothing points at
+                self.moved_files.remove(file_path);
+                let _ = remove_path(&record.quarantine_path);
+            }
+            return Err(e);
+        }
+        self.log_recovery("quarantined", &record);
+        if copied {
+            remove_path(file_path).map_err(|e| {
+                format!(
+                    "{} was copied to {} but could not be removed: {}",
+                    file_path.display(),
+                    record.quarantine_path.display(),
+                    e
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Move the whole tree at `dir` into quarantine as a single record.
+    ///
+    /// Its structure is kept as is, and it is restored as a unit. Returns the
+    /// number of files in it.
+    pub fn quarantine_directory(&mut self, dir: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+        if !fs::symlink_metadata(dir)?.is_dir() {
+            return Err(format!("{} is not a directory", dir.display()).into());
+        }
+        let (mut files, mut size) = (0, 0);
+        for entry in walkdir::WalkDir::new(dir) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                files += 1;
+                size += entry.metadata()?.len();
+            }
+        }
+        self.quarantine_entry(dir, size, true)?;
+        Ok(files)
+    }
+
+    /// Move a quarantined file or directory back to where it came from.
+    pub fn restore(&mut self, original_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let record = self
+            .moved_files
+            .get(original_path)
+            .cloned()
+            .ok_or_else(|| format!("No quarantined entry found for {}", original_path.display()))?;
+        let quarantine_path = record.quarantine_path.as_path();
+        if !quarantine_path.exists() {
+            return Err(format!("Quarantined copy not found: {}", quarantine_path.display()).into());
+        }
+        let original = record.original_path.as_path();
+        // Never merge a tree into whatever has been put in its place since
+        if record.is_dir && fs::symlink_metadata(original).is_ok() {
+            return Err(format!("{} already exists", original.display()).into());
+        }
+        if let Some(parent) = original.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_path(quarantine_path, original)?;
+        self.moved_files.remove(original_path);
         self.save_state()?;
-        Self::log_recovery("quarantined", &record);
+        self.log_recovery("restored", &record);
+        Ok(())
+    }
+
+    /// Move back an entry that is only known from the recovery log, not from
+    /// the current quarantine state. Same checks and cross-device handling as
+    /// [`Self::restore`].
+    pub fn restore_unrecorded(&self, quarantine_path: &Path, original_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let meta = fs::symlink_metadata(quarantine_path)
+            .map_err(|_| format!("Quarantined copy not found: {}", quarantine_path.display()))?;
+        if meta.is_dir() && fs::symlink_metadata(original_path).is_ok() {
+            return Err(format!("{} already exists", original_path.display()).into());
+        }
+        if let Some(parent) = original_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_path(quarantine_path, original_path)?;
+        Ok(())
+    }
+
+    pub fn list_replaced_files(&self) -> Vec<&ReplacementRecord> {
+        self.replaced_files.values().collect()
+    }
+
+    pub fn replacement(&self, original_path: &Path) -> Option<&ReplacementRecord> {
+        self.replaced_files.get(original_path)
+    }
+
+    /// Replace `file_path` with a hardlink to `keep_path`.
+    ///
+    /// The two files are compared byte by byte right before the swap. The link
+    /// is created under a temporary name next to the file and renamed over it,
+    /// so the path never goes missing. Both files must be on the same filesystem.
+    pub fn replace_with_hardlink(&mut self, file_path: &Path, keep_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        self.replace_with_link(file_path, keep_path, ReplacementKind::Hardlink, |temp| {
+            fs::hard_link(keep_path, temp)
+        })
+    }
+
+    /// Replace `file_path` with a symlink to `keep_path`, relative to the file's
+    /// directory when `relative` is set.
+    ///
+    /// Checked and swapped in like hardlinks, so both files must be on the
+    /// same filesystem too.
+    pub fn replace_with_symlink(
+        &mut self,
+        file_path: &Path,
+        keep_path: &Path,
+        relative: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let keep = fs::canonicalize(keep_path)?;
+        let target = if relative {
+            let dir = fs::canonicalize(file_path.parent().ok_or("Invalid path")?)?;
+            relative_path(&dir, &keep)
+        } else {
+            keep
+        };
+        self.replace_with_link(file_path, keep_path, ReplacementKind::Symlink, |temp| symlink_file(&target, temp))
+    }
+
+    fn replace_with_link<F>(
+        &mut self,
+        file_path: &Path,
+        keep_path: &Path,
+        kind: ReplacementKind,
+        make_link: F,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: FnOnce(&Path) -> std::io::Result<()>,
+    {
+        let meta = fs::symlink_metadata(file_path)?;
+        if !meta.is_file() {
+            return Err(format!("{} is not a regular file", file_path.display()).into());
+        }
+        let keep_meta = fs::metadata(keep_path)?;
+        if let (Some(a), Some(b)) = (file_id(&meta), file_id(&keep_meta)) {
+            if a.0 != b.0 {
+                return Err(
+                    format!("{} and {} are on different filesystems", file_path.display(), keep_path.display()).into()
+                );
+            }
+            if a == b {
+                let msg = match kind {
+                    ReplacementKind::Hardlink => "is already a hardlink to",
+                    ReplacementKind::Symlink => "is the same file as",
+                };
+                return Err(format!("{} {} {}", file_path.display(), msg, keep_path.display()).into());
+            }
+        }
+        // The file may have changed since it was scanned; its content would be lost
+        if !files_identical(file_path, keep_path)? {
+            return Err(format!("{} no longer matches {}", file_path.display(), keep_path.display()).into());
+        }
+
+        let now = std::time::SystemTime::now();
+        let record = ReplacementRecord {
+            original_path: file_path.to_path_buf(),
+            kept_path: keep_path.to_path_buf(),
+            kind,
+            file_size: meta.len(),
+            mode: file_mode(&meta),
+            modified: meta.modified().unwrap_or(now),
+            replaced_at: now,
+        };
+        // Record the replacement before the swap so the original is never replaced unrecorded
+        self.replaced_files.insert(file_path.to_path_buf(), record.clone());
+        if let Err(e) = self.save_state() {
+            self.replaced_files.remove(file_path);
+            return Err(e);
+        }
+
+        let swapped = temp_sibling(file_path).and_then(|temp_path| {
+            make_link(&temp_path)?;
+            if let Err(e) = fs::rename(&temp_path, file_path) {
+                let _ = fs::remove_file(&temp_path);
+                return Err(e.into());
+            }
+            Ok(())
+        });
+        if let Err(e) = swapped {
+            self.replaced_files.remove(file_path);
+            let _ = self.save_state();
+            return Err(e);
+        }
+
+        let action = match kind {
+            ReplacementKind::Hardlink => "hardlinked",
+            ReplacementKind::Symlink => "symlinked",
+        };
+        self.log_replacement(action, &record);
+        Ok(())
+    }
+
+    /// Turn a replaced path back into an independent copy of the file.
+    pub fn restore_replacement(&mut self, original_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let record = self
+            .replaced_files
+            .get(original_path)
+            .cloned()
+            .ok_or_else(|| format!("No replacement recorded for {}", original_path.display()))?;
+        let original = record.original_path.as_path();
+        // The link still reads as the original content; fall back to the kept file if it is gone
+        let source = if original.exists() { original } else { record.kept_path.as_path() };
+
+        if let Some(parent) = original.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = temp_sibling(original)?;
+        let restored = fs::copy(source, &temp_path)
+            .and_then(|_| fs::File::options().write(true).open(&temp_path))
+            .and_then(|f| f.set_modified(record.modified))
+            .and_then(|_| restore_mode(&temp_path, record.mode))
+            .and_then(|_| fs::rename(&temp_path, original));
+        if let Err(e) = restored {
+            let _ = fs::remove_file(&temp_path);
+            return Err(Box::new(e));
+        }
+
+        self.replaced_files.remove(original_path);
+        self.save_state()?;
+        self.log_replacement("restored", &record);
         Ok(())
     }
     
+    /// Permanently delete quarantined files. Replacements are made permanent too,
+    /// so they can no longer be rolled back.
+    ///
+    /// Entries that can't be deleted stay quarantined, and make it an error
+    /// once everything else is done.
     pub fn commit_deletions(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
         let mut deleted_count = 0;
+        let mut failed = 0;
         let mut to_log = vec![];
         self.moved_files.retain(|_k, record| {
-            let quarantine_path = Path::new(&record.quarantine_path);
-            if quarantine_path.exists() {
-                if let Ok(_) = fs::remove_file(quarantine_path) {
+            let quarantine_path = record.quarantine_path.as_path();
+            if !quarantine_path.exists() {
+                return false; // remove missing from log too
+            }
+            match remove_path(quarantine_path) {
+                Ok(()) => {
                     deleted_count += 1;
                     to_log.push(record.clone());
+                    false // remove from log
+                }
+                Err(e) => {
+                    eprintln!("Failed to delete {}: {}", quarantine_path.display(), e);
+                    failed += 1;
+                    true // keep in log so it can still be restored or retried
                 }
-                false // remove from log
-            } else {
-                false // remove missing from log too
             }
         });
+        self.replaced_files.clear();
         self.save_state()?;
         for rec in to_log {
-            Self::log_recovery("deleted", &rec);
+            self.log_recovery("deleted", &rec);
+        }
+        if failed > 0 {
+            return Err(format!("deleted {}, but {} could not be deleted", deleted_count, failed).into());
         }
         Ok(deleted_count)
     }
     
+    /// Restore every quarantined and replaced file.
+    ///
+    /// Everything that can be restored is; anything that can't makes it an
+    /// error once the rest is done.
     pub fn rollback(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
         let mut restored_count = 0;
+        let replaced: Vec<PathBuf> = self.replaced_files.keys().cloned().collect();
+        for path in replaced {
+            match self.restore_replacement(&path) {
+                Ok(()) => restored_count += 1,
+                Err(e) => eprintln!("Failed to restore {}: {}", path.display(), e),
+            }
+        }
         let mut missing = vec![];
         self.moved_files.retain(|_k, record| {
-            let quarantine_path = Path::new(&record.quarantine_path);
-            let original_path = Path::new(&record.original_path);
+            let quarantine_path = record.quarantine_path.as_path();
+            let original_path = record.original_path.as_path();
             if quarantine_path.exists() {
+                if record.is_dir && fs::symlink_metadata(original_path).is_ok() {
+                    eprintln!("Not restoring {}: it already exists", original_path.display());
+                    return true;
+                }
                 if let Some(parent) = original_path.parent() {
                     let _ = fs::create_dir_all(parent);
                 }
-                if let Ok(_) = fs::rename(quarantine_path, original_path) {
-                    restored_count += 1;
-                    false // remove from log
-                } else {
-                    true // keep in log if failed to restore
+                match move_path(quarantine_path, original_path) {
+                    Ok(()) => {
+                        restored_count += 1;
+                        false // remove from log
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to restore {}: {}", original_path.display(), e);
+                        true // keep in log if failed to restore
+                    }
                 }
             } else {
                 missing.push(record.original_path.clone());
@@ -163,44 +620,64 @@ impl QuarantineManager {
         });
         self.save_state()?;
         if !missing.is_empty() {
-            println!("{} quarantined files were missing and could not be restored:", missing.len());
-            for m in missing {
-                println!("  {}", m);
+            eprintln!("{} quarantined files were missing and could not be restored:", missing.len());
+            for m in &missing {
+                eprintln!("  {}", m.display());
             }
         }
+        // Whatever is still recorded failed to come back
+        let failed = self.moved_files.len() + self.replaced_files.len() + missing.len();
+        if failed > 0 {
+            return Err(format!("restored {}, but {} could not be restored", restored_count, failed).into());
+        }
         Ok(restored_count)
     }
     
-    pub fn remove_quarantined_file(&mut self, path: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    pub fn remove_quarantined_file(&mut self, path: &Path) -> Result<bool, Box<dyn std::error::Error>> {
         let existed = self.moved_files.remove(path).is_some();
         self.save_state()?;
         Ok(existed)
     }
     
     pub fn save_state(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let json = serde_json::to_string_pretty(&self.moved_files)?;
+        let json = serde_json::to_string_pretty(&sorted_records(&self.moved_files))?;
         let mut file = std::fs::File::create(&self.quarantine_log)?;
         file.write_all(json.as_bytes())?;
+        let json = serde_json::to_string_pretty(&sorted_records(&self.replaced_files))?;
+        std::fs::write(&self.replacement_log, json)?;
         Ok(())
     }
     
-    fn log_recovery(action: &str, record: &QuarantineRecord) {
-        let log_path = QuarantineManager::get_recovery_log_path();
-        let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-        let entry = serde_json::json!({
-            "timestamp": timestamp,
+    fn log_recovery(&self, action: &str, record: &QuarantineRecord) {
+        self.append_recovery_entry(serde_json::json!({
             "action": action,
-            "original_path": record.original_path,
-            "quarantine_path": record.quarantine_path,
+            "original_path": path_value(&record.original_path),
+            "quarantine_path": path_value(&record.quarantine_path),
             "file_size": record.file_size,
-        });
-        let mut log = if let Ok(existing) = std::fs::read_to_string(&log_path) {
+            "is_dir": record.is_dir,
+        }));
+    }
+
+    fn log_replacement(&self, action: &str, record: &ReplacementRecord) {
+        self.append_recovery_entry(serde_json::json!({
+            "action": action,
+            "original_path": path_value(&record.original_path),
+            "kept_path": path_value(&record.kept_path),
+            "file_size": record.file_size,
+        }));
+    }
+
+    fn append_recovery_entry(&self, mut entry: serde_json::Value) {
+        let log_path = &self.recovery_log;
+        let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        entry["timestamp"] = serde_json::Value::String(timestamp);
+        let mut log = if let Ok(existing) = std::fs::read_to_string(log_path) {
             serde_json::from_str::<Vec<serde_json::Value>>(&existing).unwrap_or_else(|_| vec![])
         } else {
             vec![]
         };
         log.push(entry);
-        let _ = std::fs::write(&log_path, serde_json::to_string_pretty(&log).unwrap());
+        let _ = std::fs::write(log_path, serde_json::to_string_pretty(&log).unwrap());
     }
     
     fn get_recovery_log_path() -> PathBuf {
@@ -220,4 +697,197 @@ impl QuarantineManager {
             vec![]
         }
     }
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hardlink_replacement_and_rollback() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let state = tempdir().unwrap();
+        let data = tempdir().unwrap();
+        let keep = data.path().join("keep.txt");
+        let dupe = data.path().join("dupe.txt");
+        fs::write(&keep, "same").unwrap();
+        fs::write(&dupe, "same").unwrap();
+        fs::set_permissions(&dupe, fs::Permissions::from_mode(0o640)).unwrap();
+        let (keep, dupe) = (keep.as_path(), dupe.as_path());
+
+        let mut qm = QuarantineManager::with_base_dir(state.path()).unwrap();
+        qm.replace_with_hardlink(dupe, keep).unwrap();
+        assert_eq!(fs::metadata(dupe).unwrap().ino(), fs::metadata(keep).unwrap().ino());
+        let err = qm.replace_with_hardlink(dupe, keep).unwrap_err();
+        assert!(err.to_string().contains("already a hardlink"));
+        // No temp files left behind
+        assert_eq!(fs::read_dir(data.path()).unwrap().count(), 2);
+
+        // A file that changed since the scan is left alone
+        let changed = data.path().join("changed.txt");
+        fs::write(&changed, "edit").unwrap();
+        assert!(qm.replace_with_hardlink(&changed, keep).is_err());
+        assert_eq!(fs::read_to_string(&changed).unwrap(), "edit");
+        fs::remove_file(&changed).unwrap();
+
+        // The record survives a restart
+        let mut qm = QuarantineManager::with_base_dir(state.path()).unwrap();
+        assert_eq!(qm.list_replaced_files().len(), 1);
+        assert_eq!(qm.rollback().unwrap(), 1);
+        assert_ne!(fs::metadata(dupe).unwrap().ino(), fs::metadata(keep).unwrap().ino());
+        assert_eq!(fs::read_to_string(dupe).unwrap(), "same");
+        assert_eq!(fs::metadata(dupe).unwrap().permissions().mode() & 0o777, 0o640);
+        assert!(qm.list_replaced_files().is_empty());
+
+        let log: Vec<serde_json::Value> =
+            serde_json::from_str(&fs::read_to_string(state.path().join("dedcore_recovery.json")).unwrap()).unwrap();
+        let actions: Vec<&str> = log.iter().filter_map(|e| e["action"].as_str()).collect();
+        assert_eq!(actions, ["hardlinked", "restored"]);
+    }
+
+    #[test]
+    fn test_quarantine_directory_and_rollback() {
+        let state = tempdir().unwrap();
+        let data = tempdir().unwrap();
+        let copy = data.path().join("copy");
+        fs::create_dir_all(copy.join("sub")).unwrap();
+        fs::write(copy.join("a.txt"), "top").unwrap();
+        fs::write(copy.join("sub/a.txt"), "nested").unwrap();
+        fs::create_dir(copy.join("empty")).unwrap();
+
+        let mut qm = QuarantineManager::with_base_dir(state.path()).unwrap();
+        assert_eq!(qm.quarantine_directory(&copy).unwrap(), 2);
+        assert!(!copy.exists());
+        let records = qm.list_quarantined_files();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].is_dir);
+        assert_eq!(records[0].file_size, 9);
+
+        // Same names in the same second each get their own quarantine entry
+        fs::create_dir(&copy).unwrap();
+        fs::write(copy.join("a.txt"), "later").unwrap();
+        qm.quarantine_file(&copy.join("a.txt")).unwrap();
+        assert_eq!(fs::read_dir(state.path().join("quarantine")).unwrap().count(), 2);
+        qm.restore(&copy.join("a.txt")).unwrap();
+
+        // The tree is not merged into a directory that took its place
+        assert!(qm.restore(&copy).is_err());
+        fs::remove_dir_all(&copy).unwrap();
+        let mut qm = QuarantineManager::with_base_dir(state.path()).unwrap();
+        assert_eq!(qm.rollback().unwrap(), 1);
+        assert_eq!(fs::read_to_string(copy.join("a.txt")).unwrap(), "top");
+        assert_eq!(fs::read_to_string(copy.join("sub/a.txt")).unwrap(), "nested");
+        assert!(copy.join("empty").is_dir());
+    }
+
+    #[test]
+    fn test_restore_unrecorded_directory() {
+        let state = tempdir().unwrap();
+        let data = tempdir().unwrap();
+        let copy = data.path().join("copy");
+        fs::create_dir_all(copy.join("sub")).unwrap();
+        fs::write(copy.join("sub/a.txt"), "nested").unwrap();
+
+        let mut qm = QuarantineManager::with_base_dir(state.path()).unwrap();
+        qm.quarantine_directory(&copy).unwrap();
+        let quarantined = qm.list_quarantined_files()[0].quarantine_path.clone();
+        qm.remove_quarantined_file(&copy).unwrap();
+
+        fs::create_dir(&copy).unwrap();
+        assert!(qm.restore_unrecorded(&quarantined, &copy).is_err());
+        fs::remove_dir(&copy).unwrap();
+        qm.restore_unrecorded(&quarantined, &copy).unwrap();
+        assert_eq!(fs::read_to_string(copy.join("sub/a.txt")).unwrap(), "nested");
+        assert!(!quarantined.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_names_survive_quarantine() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let state = tempdir().unwrap();
+        let data = tempdir().unwrap();
+        let odd = data.path().join(OsStr::from_bytes(b"caf\xe9.txt"));
+        if fs::write(&odd, "latin-1").is_err() {
+            // Some filesystems only accept UTF-8 names
+            return;
+        }
+
+        let mut qm = QuarantineManager::with_base_dir(state.path()).unwrap();
+        qm.quarantine_file(&odd).unwrap();
+        assert!(!odd.exists());
+
+        let mut qm = QuarantineManager::with_base_dir(state.path()).unwrap();
+        assert_eq!(qm.list_quarantined_files()[0].original_path, odd);
+        qm.restore(&odd).unwrap();
+        assert_eq!(fs::read_to_string(&odd).unwrap(), "latin-1");
+
+        let log = fs::read_to_string(state.path().join("dedcore_recovery.json")).unwrap();
+        assert!(log.contains(r#""bytes": ""#) && log.contains(r"caf\\xe9.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_tree_keeps_structure() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let data = tempdir().unwrap();
+        let from = data.path().join("from");
+        fs::create_dir_all(from.join("sub")).unwrap();
+        fs::write(from.join("sub/run.sh"), "#!/bin/sh").unwrap();
+        fs::set_permissions(from.join("sub/run.sh"), fs::Permissions::from_mode(0o750)).unwrap();
+        std::os::unix::fs::symlink("sub/run.sh", from.join("link")).unwrap();
+
+        let to = data.path().join("to");
+        copy_tree(&from, &to).unwrap();
+        assert_eq!(fs::read_to_string(to.join("sub/run.sh")).unwrap(), "#!/bin/sh");
+        assert_eq!(fs::metadata(to.join("sub/run.sh")).unwrap().permissions().mode() & 0o777, 0o750);
+        assert_eq!(fs::read_link(to.join("link")).unwrap(), Path::new("sub/run.sh"));
+        let modified = |p: &Path| fs::metadata(p).unwrap().modified().unwrap();
+        assert_eq!(modified(&to.join("sub/run.sh")), modified(&from.join("sub/run.sh")));
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(relative_path(Path::new("/a/b/c"), Path::new("/a/d/f.txt")), Path::new("../../d/f.txt"));
+        assert_eq!(relative_path(Path::new("/a"), Path::new("/a/f.txt")), Path::new("f.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_replacement_and_restore() {
+        let state = tempdir().unwrap();
+        let data = tempdir().unwrap();
+        fs::create_dir_all(data.path().join("assets")).unwrap();
+        fs::create_dir_all(data.path().join("app/static")).unwrap();
+        let keep = data.path().join("assets/logo.svg");
+        let dupe = data.path().join("app/static/logo.svg");
+        let other = data.path().join("app/static/other.svg");
+        fs::write(&keep, "<svg/>").unwrap();
+        fs::write(&dupe, "<svg/>").unwrap();
+        fs::write(&other, "<svg></svg>").unwrap();
+        let (keep, dupe, other) = (keep.as_path(), dupe.as_path(), other.as_path());
+
+        let mut qm = QuarantineManager::with_base_dir(state.path()).unwrap();
+        // Content differs: refused
+        assert!(qm.replace_with_symlink(other, keep, true).is_err());
+        assert!(!fs::symlink_metadata(other).unwrap().file_type().is_symlink());
+
+        qm.replace_with_symlink(dupe, keep, true).unwrap();
+        assert_eq!(fs::read_link(dupe).unwrap(), Path::new("../../assets/logo.svg"));
+        assert_eq!(fs::read_to_string(dupe).unwrap(), "<svg/>");
+        assert_eq!(qm.replacement(dupe).unwrap().kind, ReplacementKind::Symlink);
+
+        qm.restore_replacement(dupe).unwrap();
+        assert!(fs::symlink_metadata(dupe).unwrap().is_file());
+        assert_eq!(fs::read_to_string(dupe).unwrap(), "<svg/>");
+
+        qm.replace_with_symlink(dupe, keep, false).unwrap();
+        assert_eq!(fs::read_link(dupe).unwrap(), fs::canonicalize(keep).unwrap());
+    }
+}
@@ -0,0 +1,1672 @@
+//! Library entry point for duplicate scanning.
+//!
+//! [`Scanner`] walks the configured targets, hashes the files it finds and
+//! groups them into a [`ScanReport`]. It never prints or prompts, so it can be
+//! embedded as-is; the CLI and the menu UI only render the report it returns.
+
+use crate::cache::{mtime_secs, HashCache};
+use crate::cli::ImageHashAlgorithm;
+use crate::dirtree::{duplicate_directories, DirMatch};
+use crate::keep::{KeepCandidate, KeepPolicy};
+use crate::mounts::SkippedMount;
+use crate::owner::{owner_ids, user_name};
+use crate::hashing::{
+    compare_files, hash_file, hash_file_partial, CompareSide, HashConfig, HashKind, Security, Speed, PARTIAL_BLOCK,
+};
+use crate::index::{FileIndex, FileRecord, DEFAULT_INDEX_MEMORY};
+use crate::similarity::{compare_images_with_algorithm, group_similar_text_files, text_similarity};
+use crate::walker::{
+    allocated_bytes, collect_roots, file_id, is_reference, link_count, now_secs, resolve_with_reference, root_of,
+    stream_roots, FileFilter, FoundFile, ScanRoot, Symlink,
+};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+/// Extensions considered for text similarity analysis.
+pub const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "rs", "py", "js", "ts", "java", "c", "cpp", "h", "hpp", "html", "css", "json",
+    "toml", "yaml", "yml", "xml", "csv", "log", "cfg", "ini",
+];
+
+/// Extensions considered for image similarity analysis.
+pub const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "bmp", "gif", "webp"];
+
+fn has_extension(file: &Path, exts: &[&str]) -> bool {
+    file.extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| exts.iter().any(|x| x.eq_ignore_ascii_case(ext)))
+}
+
+/// Everything a [`Scanner`] needs to know, built with chained setters.
+///
+/// ```no_run
+/// use dedcore::{ScanOptions, Scanner};
+///
+/// let report = Scanner::new(ScanOptions::new(["/data/photos"]).text_similarity(None)).run();
+/// println!("{} duplicate groups", report.duplicate_groups.len());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub targets: Vec<PathBuf>,
+    /// Read-only targets: their files are grouped with the rest and preferred
+    /// as the keeper, but never reported as removable.
+    pub reference: Vec<PathBuf>,
+    pub filter: FileFilter,
+    pub hash_config: HashConfig,
+    /// Where to keep the incremental hash cache; `None` disables caching.
+    pub cache_path: Option<PathBuf>,
+    /// Threshold for grouping similar text files; `None` skips the analysis.
+    pub text_similarity: Option<f32>,
+    /// Threshold for grouping similar images; `None` skips the analysis.
+    pub image_similarity: Option<f32>,
+    pub image_hash_algorithm: ImageHashAlgorithm,
+    /// Compare duplicate candidates byte by byte instead of trusting their digests.
+    pub verify: bool,
+    /// Decides which file of each duplicate group is kept.
+    pub keep_policy: KeepPolicy,
+    /// Also look for whole duplicated directories; `None` skips the analysis.
+    pub directories: Option<DirMatch>,
+    /// Bytes of file records kept in memory before the rest go to a temporary
+    /// file. This bounds the records only; see [`Scanner`] for what else a scan keeps.
+    pub index_memory: usize,
+    /// Return every scanned path in [`ScanReport::files`] and every digest in
+    /// [`ScanReport::hashes`]. Turning this off keeps huge scans from holding
+    /// all of them in memory at the end.
+    pub list_files: bool,
+}
+
+impl ScanOptions {
+    pub fn new<I, P>(targets: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        Self {
+            targets: targets.into_iter().map(Into::into).collect(),
+            reference: Vec::new(),
+            filter: FileFilter::default(),
+            hash_config: HashConfig::new(Security::High, Speed::Balanced),
+            cache_path: None,
+            text_similarity: Some(0.8),
+            image_similarity: Some(0.9),
+            image_hash_algorithm: ImageHashAlgorithm::Combined,
+            verify: false,
+            keep_policy: KeepPolicy::default(),
+            directories: None,
+            index_memory: DEFAULT_INDEX_MEMORY,
+            list_files: true,
+        }
+    }
+
+    pub fn reference<I, P>(mut self, dirs: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.reference = dirs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn filter(mut self, filter: FileFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn hash_config(mut self, config: HashConfig) -> Self {
+        self.hash_config = config;
+        self
+    }
+
+    pub fn cache_path<P: Into<PathBuf>>(mut self, path: Option<P>) -> Self {
+        self.cache_path = path.map(Into::into);
+        self
+    }
+
+    pub fn text_similarity(mut self, threshold: Option<f32>) -> Self {
+        self.text_similarity = threshold;
+        self
+    }
+
+    pub fn image_similarity(mut self, threshold: Option<f32>) -> Self {
+        self.image_similarity = threshold;
+        self
+    }
+
+    pub fn image_hash_algorithm(mut self, algorithm: ImageHashAlgorithm) -> Self {
+        self.image_hash_algorithm = algorithm;
+        self
+    }
+
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    pub fn keep_policy(mut self, policy: KeepPolicy) -> Self {
+        self.keep_policy = policy;
+        self
+    }
+
+    pub fn directories(mut self, mode: Option<DirMatch>) -> Self {
+        self.directories = mode;
+        self
+    }
+
+    pub fn index_memory(mut self, bytes: usize) -> Self {
+        self.index_memory = bytes;
+        self
+    }
+
+    pub fn list_files(mut self, list: bool) -> Self {
+        self.list_files = list;
+        self
+    }
+}
+
+/// Digest computed for a single file.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileHash {
+    #[serde(with = "crate::paths")]
+    pub file: PathBuf,
+    pub hash: String,
+    pub algorithm: String,
+    /// The target the file was found under, as it was given.
+    #[serde(with = "crate::paths")]
+    pub root: PathBuf,
+}
+
+/// Files with identical content.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub size: u64,
+    /// One path per physical copy, the one to keep first.
+    #[serde(with = "crate::paths::vec")]
+    pub files: Vec<PathBuf>,
+    /// The keep rule that picked `files[0]`.
+    pub kept_by: String,
+    /// Members under a reference root. When there are any, the keeper is one of them.
+    #[serde(with = "crate::paths::vec")]
+    pub reference: Vec<PathBuf>,
+    /// Further scanned paths that are hardlinks to one of `files`.
+    #[serde(with = "crate::paths::vec")]
+    pub hardlinks: Vec<PathBuf>,
+    /// Bytes on disk freed by removing every copy but `files[0]`.
+    ///
+    /// Counts allocated blocks rather than file length, and leaves out copies
+    /// with other hardlinks, since removing one of their names frees nothing.
+    pub reclaimable: u64,
+    /// Whether the files were compared byte by byte, not just by digest.
+    pub verified: bool,
+    /// Every copy lies inside a duplicated directory, which already accounts for it.
+    pub in_duplicate_dir: bool,
+}
+
+impl DuplicateGroup {
+    /// Bytes that would be freed by keeping a single copy.
+    pub fn wasted_bytes(&self) -> u64 {
+        self.reclaimable
+    }
+
+    /// The copies that may be removed or replaced: everything but the keeper
+    /// and files under a reference root.
+    pub fn removable(&self) -> impl Iterator<Item = &PathBuf> {
+        self.files[1..].iter().filter(|f| !self.reference.contains(f))
+    }
+}
+
+/// Directories holding the same tree of content.
+#[derive(Debug, Clone, Serialize)]
+pub struct DirectoryGroup {
+    /// Merkle digest of each directory.
+    pub hash: String,
+    /// Files in each directory, counted recursively.
+    pub files: usize,
+    /// Bytes in each directory, by file length.
+    pub size: u64,
+    /// The directories, the one to keep first.
+    #[serde(with = "crate::paths::vec")]
+    pub dirs: Vec<PathBuf>,
+    /// The keep rule that picked `dirs[0]`.
+    pub kept_by: String,
+    /// Directories that are or hold reference files. When there are any, the keeper is one of them.
+    #[serde(with = "crate::paths::vec")]
+    pub reference: Vec<PathBuf>,
+    /// Bytes on disk freed by removing every directory but the kept one.
+    pub reclaimable: u64,
+}
+
+impl DirectoryGroup {
+    /// The directories that may be removed: everything but the keeper and reference directories.
+    pub fn removable(&self) -> impl Iterator<Item = &PathBuf> {
+        self.dirs[1..].iter().filter(|d| !self.reference.contains(d))
+    }
+
+    /// Whether `path` is one of the removable directories or inside one.
+    pub fn removes(&self, path: &Path) -> bool {
+        self.removable().any(|d| path.starts_with(d))
+    }
+}
+
+/// Paths in the scan that lead to the same file, as hardlinks or through a
+/// bind mount, so already share their storage.
+#[derive(Debug, Clone, Serialize)]
+pub struct HardlinkSet {
+    pub size: u64,
+    #[serde(with = "crate::paths::vec")]
+    pub files: Vec<PathBuf>,
+}
+
+/// The removable duplicate copies owned by one user.
+#[derive(Debug, Clone, Serialize)]
+pub struct OwnerWaste {
+    pub uid: u32,
+    /// Account name, when the id is known locally.
+    pub user: Option<String>,
+    /// Removable copies the user owns.
+    pub files: usize,
+    /// Bytes on disk those copies take, leaving out copies with other hardlinks.
+    pub bytes: u64,
+}
+
+impl OwnerWaste {
+    /// The account name, or the bare id when it has none.
+    pub fn label(&self) -> String {
+        self.user.clone().unwrap_or_else(|| format!("uid {}", self.uid))
+    }
+}
+
+/// Files that share a digest but turned out to differ when compared byte by byte.
+///
+/// Any of them that are truly identical to each other are still reported as a
+/// [`DuplicateGroup`]; this records that the digest alone could not be trusted.
+#[derive(Debug, Clone, Serialize)]
+pub struct HashCollision {
+    pub hash: String,
+    pub size: u64,
+    #[serde(with = "crate::paths::vec")]
+    pub files: Vec<PathBuf>,
+}
+
+/// A member of a similarity group with its score against the group's first file.
+#[derive(Debug, Clone, Serialize)]
+pub struct SimilarFile {
+    #[serde(with = "crate::paths")]
+    pub file: PathBuf,
+    pub similarity: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SimilarGroup {
+    pub files: Vec<SimilarFile>,
+}
+
+/// A file that could not be examined, with the reason why.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileError {
+    #[serde(with = "crate::paths")]
+    pub file: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanStats {
+    pub files_scanned: usize,
+    /// Files ruled out without reading them because no other file has the same size.
+    pub unique_size: usize,
+    /// Files whose head and tail were read to split a size group.
+    pub partial_hashed: usize,
+    /// Files ruled out because their head or tail differs from every same-size file.
+    pub unique_partial: usize,
+    /// Files whose full content was hashed.
+    pub files_hashed: usize,
+    /// Files whose full digest was taken from the cache instead.
+    pub cache_hits: usize,
+    /// Files that could not be read; they are listed in [`ScanReport::errors`].
+    pub files_failed: usize,
+    /// Paths that are extra hardlinks or other paths to a file already in the scan.
+    pub hardlinked_files: usize,
+    pub duplicate_groups: usize,
+    pub duplicate_files: usize,
+    pub directory_groups: usize,
+    pub collisions: usize,
+    pub wasted_bytes: u64,
+    /// Bytes of file records that did not fit in memory and went to a temporary file.
+    pub spilled_bytes: u64,
+}
+
+/// Result of a scan. Nothing in here has been acted upon yet.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanReport {
+    /// Targets after canonicalization, with duplicates dropped.
+    pub roots: Vec<ScanRoot>,
+    /// Every file that was examined, unless [`ScanOptions::list_files`] is off.
+    #[serde(with = "crate::paths::vec")]
+    pub files: Vec<PathBuf>,
+    /// Ignore files whose rules shaped the walk.
+    #[serde(with = "crate::paths::vec")]
+    pub ignore_sources: Vec<PathBuf>,
+    /// Symbolic links met during the walk. A followed link is not a file of
+    /// its own: what it points to is listed under its real path.
+    pub symlinks: Vec<Symlink>,
+    /// Mount points the walk stayed out of: pseudo filesystems, and other
+    /// filesystems under `--one-file-system`.
+    pub skipped_mounts: Vec<SkippedMount>,
+    /// Full digests of the files that made it past the size and partial-hash
+    /// stages, unless [`ScanOptions::list_files`] is off.
+    pub hashes: Vec<FileHash>,
+    pub duplicate_groups: Vec<DuplicateGroup>,
+    /// Whole duplicated directories, when they were looked for.
+    pub directory_groups: Vec<DirectoryGroup>,
+    pub collisions: Vec<HashCollision>,
+    /// Files already deduplicated through hardlinks; never counted as duplicates.
+    pub hardlink_sets: Vec<HardlinkSet>,
+    /// Duplicate bytes by the owner of the removable copies, most first.
+    pub owners: Vec<OwnerWaste>,
+    pub similar_text_groups: Vec<SimilarGroup>,
+    pub similar_image_groups: Vec<SimilarGroup>,
+    pub errors: Vec<FileError>,
+    /// Digest algorithm used for every file in the scan.
+    pub algorithm: String,
+    pub stats: ScanStats,
+}
+
+/// Stages of duplicate detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanStage {
+    /// Reading file sizes as files are found, hashing the first and last
+    /// [`PARTIAL_BLOCK`] bytes of files that share a size, and the full
+    /// content of those that still share their partial digest.
+    Hashing,
+    /// Comparing duplicate candidates byte by byte.
+    Verify,
+}
+
+impl fmt::Display for ScanStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanStage::Hashing => write!(f, "hashing"),
+            ScanStage::Verify => write!(f, "verifying"),
+        }
+    }
+}
+
+/// Progress notifications emitted while a scan runs.
+#[derive(Debug, Clone, Copy)]
+pub enum ScanProgress {
+    /// A stage is about to start on this many files, or on files still being found if zero.
+    Stage { stage: ScanStage, total: usize },
+    /// The walk found one more file for the current stage.
+    Found,
+    /// One more file has been through the current stage.
+    Advanced,
+}
+
+impl ScanReport {
+    /// The most specific target `file` was found under.
+    pub fn root_of(&self, file: &Path) -> Option<&ScanRoot> {
+        root_of(&self.roots, file)
+    }
+}
+
+/// Finds the duplicates among the files under [`ScanOptions::targets`].
+///
+/// # Memory
+///
+/// The walk hands files over through a bounded queue, and file records go to
+/// a temporary file past [`ScanOptions::index_memory`]. Only the records
+/// spill, though. These stay in memory for the whole scan:
+///
+/// - the offset of every file's record, eight bytes per file;
+/// - an entry for every distinct file size, even one only a single file has;
+/// - the partial digest and inode of every file that shares its size;
+/// - the full digest of every file that shares its partial digest too;
+/// - the hash cache, read and written whole, with an entry for every file it
+///   ever hashed.
+///
+/// In a tree of tens of millions of files most sizes are shared, so this can
+/// still come to gigabytes. Turning off [`ScanOptions::list_files`] keeps the
+/// per-file lists out of the report, and `None` for
+/// [`ScanOptions::cache_path`] skips the cache.
+pub struct Scanner {
+    options: ScanOptions,
+}
+
+/// Files the walk may get ahead of the hashing threads by.
+const WALK_QUEUE: usize = 1024;
+
+/// A file that is still a duplicate candidate.
+struct Candidate {
+    id: u32,
+    file: PathBuf,
+    size: u64,
+    mtime: u64,
+}
+
+/// On-disk facts about a candidate, kept aside for the savings calculation.
+struct Physical {
+    mtime: u64,
+    allocated: u64,
+    nlink: u64,
+    uid: Option<u32>,
+    /// Other scanned paths to the same inode.
+    links: Vec<PathBuf>,
+}
+
+/// Hashing a candidate still needs, with the digest the cache already has for it, if any.
+enum Job {
+    Partial(Candidate, Option<Vec<u8>>),
+    Full(Candidate, Option<Vec<u8>>),
+}
+
+/// A digest, and whether it came from the cache.
+type Digested = Result<(Vec<u8>, bool), String>;
+
+/// File size and partial digest.
+type PartialKey = (u64, Vec<u8>);
+
+/// What is known about the files scanned so far, shared by the hashing threads.
+///
+/// A file is hashed as soon as another one turns up with the same size, and
+/// fully hashed as soon as another one has the same partial digest as well,
+/// so the stages overlap with each other and with the walk. Records go to
+/// the [`FileIndex`], which may spill them to disk; the maps here never do.
+struct ScanState {
+    files: FileIndex,
+    /// The only file of each size so far, or `None` once there are more.
+    sizes: HashMap<u64, Option<u32>>,
+    /// The same by size and partial digest, with whether the digest was cached.
+    partials: HashMap<PartialKey, Option<(u32, bool)>>,
+    /// Files by full digest, with their size.
+    digests: HashMap<Vec<u8>, (u64, Vec<u32>)>,
+    /// The first file scanned of each inode, among files whose size is not unique.
+    inodes: HashMap<(u64, u64), u32>,
+    /// Other scanned paths to the inode of a file.
+    links: HashMap<u32, Vec<PathBuf>>,
+    cache: Option<HashCache>,
+    algo: HashKind,
+    errors: Vec<FileError>,
+    stats: ScanStats,
+}
+
+impl ScanState {
+    fn record(&self, id: u32) -> FileRecord {
+        self.files.get(id).expect("the file index can't be read back")
+    }
+
+    fn candidate(&self, id: u32) -> Candidate {
+        let FileRecord { path, size, mtime, .. } = self.record(id);
+        Candidate { id, file: path, size, mtime }
+    }
+
+    /// Index a file and return the hashing it makes necessary.
+    fn add(&mut self, file: PathBuf, meta: &fs::Metadata) -> Vec<Job> {
+        self.stats.files_scanned += 1;
+        // Hardlinks, and a file reached twice through a bind mount or a path
+        // both listed and walked, share one inode: only the first path is a
+        // candidate. Such paths share a size too, so inodes are only tracked
+        // once a second file of a size turns up.
+        let inode = file_id(meta);
+        let shared_size = match self.sizes.get(&meta.len()) {
+            Some(&Some(first)) => {
+                if let Some(first_inode) = self.record(first).inode {
+                    self.inodes.insert(first_inode, first);
+                }
+                true
+            }
+            Some(None) => true,
+            None => false,
+        };
+        if let Some(first) = inode.filter(|_| shared_size).and_then(|inode| self.inodes.get(&inode)) {
+            self.links.entry(*first).or_default().push(file);
+            return Vec::new();
+        }
+        let record = FileRecord {
+            path: file,
+            size: meta.len(),
+            mtime: mtime_secs(meta),
+            allocated: allocated_bytes(meta),
+            nlink: link_count(meta),
+            uid: owner_ids(meta).map(|(uid, _)| uid),
+            inode,
+        };
+        let id = self.files.push(&record);
+        if let Some(inode) = inode.filter(|_| shared_size) {
+            self.inodes.insert(inode, id);
+        }
+        let first = match self.sizes.entry(record.size) {
+            Entry::Vacant(slot) => {
+                slot.insert(Some(id));
+                return Vec::new();
+            }
+            Entry::Occupied(mut slot) => slot.get_mut().take(),
+        };
+        let mut ready = vec![Candidate { id, file: record.path, size: record.size, mtime: record.mtime }];
+        ready.extend(first.map(|first| self.candidate(first)));
+        ready.into_iter().map(|c| self.partial_job(c)).collect()
+    }
+
+    fn partial_job(&self, c: Candidate) -> Job {
+        // Small files are hashed whole here, so a cached full digest will do
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(&c.file, c.size, c.mtime, self.algo)).and_then(|e| {
+            if c.size <= PARTIAL_BLOCK * 2 {
+                e.partial.clone().or_else(|| e.hash.clone())
+            } else {
+                e.partial.clone()
+            }
+        });
+        Job::Partial(c, cached)
+    }
+
+    fn full_job(&self, c: Candidate) -> Job {
+        let cached = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.get(&c.file, c.size, c.mtime, self.algo))
+            .and_then(|e| e.hash.clone());
+        Job::Full(c, cached)
+    }
+
+    /// Record a partial digest and return the full hashing it makes necessary.
+    fn partial_done(&mut self, c: Candidate, result: Digested) -> Vec<Job> {
+        let (hash, cached) = match result {
+            Ok(digested) => digested,
+            Err(reason) => {
+                self.errors.push(FileError { file: c.file, reason });
+                return Vec::new();
+            }
+        };
+        if !cached {
+            self.stats.partial_hashed += 1;
+            if let Some(cache) = self.cache.as_mut() {
+                cache.update(&c.file, c.size, c.mtime, self.algo, Some(hash.clone()), None);
+            }
+        }
+        let first = match self.partials.entry((c.size, hash.clone())) {
+            Entry::Vacant(slot) => {
+                slot.insert(Some((c.id, cached)));
+                return Vec::new();
+            }
+            Entry::Occupied(mut slot) => slot.get_mut().take(),
+        };
+        let mut ready = vec![(c, cached)];
+        ready.extend(first.map(|(first, cached)| (self.candidate(first), cached)));
+        let mut jobs = Vec::new();
+        for (c, cached) in ready {
+            if c.size <= PARTIAL_BLOCK * 2 {
+                // The partial hash already covered the whole file
+                self.full_done(c, Ok((hash.clone(), cached)));
+            } else {
+                jobs.push(self.full_job(c));
+            }
+        }
+        jobs
+    }
+
+    fn full_done(&mut self, c: Candidate, result: Digested) {
+        match result {
+            Ok((hash, cached)) => {
+                if cached {
+                    self.stats.cache_hits += 1;
+                } else {
+                    self.stats.files_hashed += 1;
+                    if let Some(cache) = self.cache.as_mut() {
+                        cache.update(&c.file, c.size, c.mtime, self.algo, None, Some(hash.clone()));
+                    }
+                }
+                self.digests.entry(hash).or_insert_with(|| (c.size, Vec::new())).1.push(c.id);
+            }
+            Err(reason) => self.errors.push(FileError { file: c.file, reason }),
+        }
+    }
+}
+
+/// Hashing threads share one [`ScanState`], only locking it between files.
+struct Pipeline {
+    state: Mutex<ScanState>,
+    algo: HashKind,
+}
+
+impl Pipeline {
+    fn add(&self, file: PathBuf, meta: &fs::Metadata) {
+        let jobs = self.state.lock().unwrap().add(file, meta);
+        self.run(jobs);
+    }
+
+    fn fail(&self, file: PathBuf, reason: String) {
+        let mut state = self.state.lock().unwrap();
+        state.stats.files_scanned += 1;
+        state.errors.push(FileError { file, reason });
+    }
+
+    /// Hash outside the lock, until nothing more becomes necessary.
+    fn run(&self, mut jobs: Vec<Job>) {
+        while let Some(job) = jobs.pop() {
+            match job {
+                Job::Partial(c, cached) => {
+                    let result = match cached {
+                        Some(hash) => Ok((hash, true)),
+                        None => hash_file_partial(&c.file, self.algo, PARTIAL_BLOCK)
+                            .map(|h| (h, false))
+                            .map_err(|e| e.to_string()),
+                    };
+                    jobs.extend(self.state.lock().unwrap().partial_done(c, result));
+                }
+                Job::Full(c, cached) => {
+                    let result = match cached {
+                        Some(hash) => Ok((hash, true)),
+                        None => hash_file(&c.file, self.algo).map(|h| (h, false)).map_err(|e| e.to_string()),
+                    };
+                    self.state.lock().unwrap().full_done(c, result);
+                }
+            }
+        }
+    }
+}
+
+impl Scanner {
+    pub fn new(options: ScanOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn options(&self) -> &ScanOptions {
+        &self.options
+    }
+
+    /// Scan and reference targets after canonicalization, and the ones that don't exist.
+    pub fn roots(&self) -> (Vec<ScanRoot>, Vec<PathBuf>) {
+        resolve_with_reference(&self.options.targets, &self.options.reference)
+    }
+
+    /// Walk the targets and return the files that pass the filters.
+    pub fn collect_files(&self) -> Vec<PathBuf> {
+        collect_roots(&self.roots().0, &self.options.filter)
+    }
+
+    pub fn run(&self) -> ScanReport {
+        self.run_with_progress(|_| {})
+    }
+
+    pub fn run_with_progress<F>(&self, progress: F) -> ScanReport
+    where
+        F: Fn(ScanProgress) + Sync,
+    {
+        self.run_with_files(Vec::new(), progress)
+    }
+
+    /// Walk the targets and scan what the walk finds, along with `files`,
+    /// which are taken as they are.
+    ///
+    /// The walk runs on a thread of its own and hands files over through a
+    /// bounded queue, so hashing starts right away and a walk that gets ahead
+    /// waits instead of piling up paths. A file both listed and walked is
+    /// scanned once.
+    pub fn run_with_files<F>(&self, files: Vec<PathBuf>, progress: F) -> ScanReport
+    where
+        F: Fn(ScanProgress) + Sync,
+    {
+        let (roots, missing) = self.roots();
+        let filter = &self.options.filter;
+        let pipeline = self.pipeline();
+        progress(ScanProgress::Stage { stage: ScanStage::Hashing, total: 0 });
+        let (tx, rx) = mpsc::sync_channel::<FoundFile>(WALK_QUEUE);
+        let (roots_ref, progress_ref) = (&roots, &progress);
+        let walk = thread::scope(|s| {
+            let walker = s.spawn(move || {
+                let listed: HashSet<PathBuf> = files.into_iter().collect();
+                let send = |file: FoundFile| {
+                    progress_ref(ScanProgress::Found);
+                    // The receiving end only goes away if a hashing thread panicked
+                    let _ = tx.send(file);
+                };
+                let walk = stream_roots(roots_ref, filter, |file| {
+                    if !listed.contains(&file.path) {
+                        send(file);
+                    }
+                });
+                for path in listed {
+                    send(FoundFile { path, named: true });
+                }
+                walk
+            });
+            let now = now_secs();
+            rx.into_iter().par_bridge().for_each(|file| {
+                if let Some(meta) = file.admit(filter, now) {
+                    pipeline.add(file.path, &meta);
+                }
+                progress(ScanProgress::Advanced);
+            });
+            walker.join().unwrap()
+        });
+        let mut report = self.report(pipeline, &progress);
+        report.ignore_sources = walk.ignore_sources;
+        report.symlinks = walk.symlinks;
+        report.skipped_mounts = walk.skipped_mounts;
+        report.errors.extend(missing.into_iter().map(|target| FileError {
+            file: target,
+            reason: "No such file or directory".to_string(),
+        }));
+        report.stats.files_failed = report.errors.len();
+        report
+    }
+
+    /// Find duplicates among an already collected list of files.
+    pub fn scan_files<F>(&self, mut files: Vec<PathBuf>, progress: F) -> ScanReport
+    where
+        F: Fn(ScanProgress) + Sync,
+    {
+        files.sort();
+        files.dedup();
+        let pipeline = self.pipeline();
+        progress(ScanProgress::Stage { stage: ScanStage::Hashing, total: files.len() });
+        files.into_par_iter().for_each(|file| {
+            match fs::metadata(&file) {
+                Ok(meta) => pipeline.add(file, &meta),
+                Err(e) => pipeline.fail(file, e.to_string()),
+            }
+            progress(ScanProgress::Advanced);
+        });
+        self.report(pipeline, &progress)
+    }
+
+    fn pipeline(&self) -> Pipeline {
+        let cache = self.options.cache_path.as_ref().map(|p| {
+            let mut cache = HashCache::load(p);
+            cache.clean();
+            cache
+        });
+        // One algorithm for the whole scan, otherwise identical files with
+        // different extensions would end up with incomparable digests
+        let algo = self.options.hash_config.canonical_algorithm();
+        let state = ScanState {
+            files: FileIndex::new(self.options.index_memory),
+            sizes: HashMap::new(),
+            partials: HashMap::new(),
+            digests: HashMap::new(),
+            inodes: HashMap::new(),
+            links: HashMap::new(),
+            cache,
+            algo,
+            errors: Vec::new(),
+            stats: ScanStats::default(),
+        };
+        Pipeline { state: Mutex::new(state), algo }
+    }
+
+    /// Group the digested files and analyse the groups.
+    fn report<F>(&self, pipeline: Pipeline, progress: &F) -> ScanReport
+    where
+        F: Fn(ScanProgress) + Sync,
+    {
+        let state = pipeline.state.into_inner().unwrap();
+        let mut report = ScanReport { roots: self.roots().0, algorithm: state.algo.to_string(), ..Default::default() };
+        report.stats = state.stats.clone();
+        report.errors = state.errors.clone();
+        report.stats.unique_size = state.sizes.values().filter(|first| first.is_some()).count();
+        report.stats.unique_partial = state.partials.values().filter(|first| first.is_some()).count();
+        report.stats.spilled_bytes = state.files.spilled_bytes();
+        if let Some(cache) = &state.cache {
+            // The cache only speeds up the next scan, losing it is not an error
+            let _ = cache.save();
+        }
+
+        // Every path to a hardlinked inode; the first in order stands for it
+        let names: HashMap<u32, Vec<PathBuf>> = state
+            .links
+            .iter()
+            .map(|(&id, others)| {
+                let mut names = others.clone();
+                names.push(state.record(id).path);
+                names.sort();
+                (id, names)
+            })
+            .collect();
+        let name = |id: u32| names.get(&id).map_or_else(|| state.record(id).path, |names| names[0].clone());
+        for (&id, names) in &names {
+            report.hardlink_sets.push(HardlinkSet { size: state.record(id).size, files: names.clone() });
+        }
+        report.hardlink_sets.sort_by(|a, b| a.files.cmp(&b.files));
+        report.stats.hardlinked_files = names.values().map(|names| names.len() - 1).sum();
+
+        let mut physical: HashMap<PathBuf, Physical> = HashMap::new();
+        let mut groups = Vec::new();
+        for (hash, (size, ids)) in &state.digests {
+            let hash = hex::encode(hash);
+            let files: Vec<(u32, PathBuf)> = ids.iter().map(|&id| (id, name(id))).collect();
+            for (_, file) in files.iter().filter(|_| self.options.list_files) {
+                report.hashes.push(FileHash {
+                    root: report.root_of(file).map(|r| r.given.clone()).unwrap_or_default(),
+                    file: file.clone(),
+                    hash: hash.clone(),
+                    algorithm: state.algo.to_string(),
+                });
+            }
+            if files.len() < 2 {
+                continue;
+            }
+            for (id, file) in &files {
+                let record = state.record(*id);
+                let links = names.get(id).map(|names| names[1..].to_vec()).unwrap_or_default();
+                physical.insert(
+                    file.clone(),
+                    Physical {
+                        mtime: record.mtime,
+                        allocated: record.allocated,
+                        nlink: record.nlink,
+                        uid: record.uid,
+                        links,
+                    },
+                );
+            }
+            let mut files: Vec<PathBuf> = files.into_iter().map(|(_, file)| file).collect();
+            files.sort();
+            groups.push(DuplicateGroup {
+                hash,
+                size: *size,
+                files,
+                kept_by: String::new(),
+                reference: Vec::new(),
+                hardlinks: Vec::new(),
+                reclaimable: 0,
+                verified: false,
+                in_duplicate_dir: false,
+            });
+        }
+        report.hashes.sort_by(|a, b| a.file.cmp(&b.file));
+
+        if self.options.verify {
+            // Byte-for-byte comparison
+            let total = groups.iter().map(|g| g.files.len()).sum();
+            progress(ScanProgress::Stage { stage: ScanStage::Verify, total });
+            let (verified, collisions, errors) = verify_groups(groups, progress);
+            groups = verified;
+            report.collisions = collisions;
+            report.errors.extend(errors);
+        }
+        // Copies that all live under reference roots leave nothing to act on
+        groups.retain(|g| g.files.iter().any(|f| !is_reference(&report.roots, f)));
+        for group in &mut groups {
+            group.reference = group.files.iter().filter(|f| is_reference(&report.roots, f)).cloned().collect();
+            group.hardlinks = group.files.iter().filter_map(|f| physical.get(f)).flat_map(|p| p.links.clone()).collect();
+            group.hardlinks.sort();
+        }
+        if let Some(mode) = self.options.directories {
+            report.directory_groups = self.directory_groups(&report.roots, &groups, &report.hardlink_sets, mode);
+        }
+        let dir_groups = &report.directory_groups;
+        for group in &mut groups {
+            // Keep a copy that survives removing the duplicate directories
+            let mtime = |f: &Path| physical.get(f).map_or(0, |p| p.mtime);
+            let avoid = |f: &Path| dir_groups.iter().any(|d| d.removes(f));
+            group.kept_by = self.keep_first(&mut group.files, &group.reference, avoid, &report.roots, mtime);
+            group.reclaimable = reclaimable(group, &physical);
+            group.in_duplicate_dir = !dir_groups.is_empty()
+                && group
+                    .files
+                    .iter()
+                    .all(|f| dir_groups.iter().flat_map(|d| &d.dirs).any(|d| f.starts_with(d)));
+        }
+        groups.sort_by(|a, b| b.wasted_bytes().cmp(&a.wasted_bytes()).then_with(|| a.files.cmp(&b.files)));
+        report.owners = owner_waste(&groups, &physical);
+        report.duplicate_groups = groups;
+        report.errors.sort_by(|a, b| a.file.cmp(&b.file));
+
+        // Exact duplicates are already reported, only look for near-duplicates among the rest
+        let duplicated: HashSet<&Path> = report
+            .duplicate_groups
+            .iter()
+            .flat_map(|g| g.files.iter().map(PathBuf::as_path))
+            .collect();
+        let (mut text_files, mut image_files) = (Vec::new(), Vec::new());
+        let mut listed = Vec::new();
+        state
+            .files
+            .for_each(|id, record| {
+                let file = names.get(&id).map_or(record.path, |names| names[0].clone());
+                if !duplicated.contains(file.as_path()) {
+                    if self.options.text_similarity.is_some() && has_extension(&file, TEXT_EXTENSIONS) {
+                        text_files.push(file.clone());
+                    } else if self.options.image_similarity.is_some() && has_extension(&file, IMAGE_EXTENSIONS) {
+                        image_files.push(file.clone());
+                    }
+                }
+                if self.options.list_files {
+                    listed.push(file);
+                }
+            })
+            .expect("the file index can't be read back");
+        if self.options.list_files {
+            listed.extend(names.values().flat_map(|names| names[1..].iter().cloned()));
+            listed.sort();
+            report.files = listed;
+        }
+        if let Some(threshold) = self.options.text_similarity {
+            text_files.sort();
+            report.similar_text_groups = find_similar_text(&text_files, threshold);
+        }
+        if let Some(threshold) = self.options.image_similarity {
+            image_files.sort();
+            let image_files: Vec<&Path> = image_files.iter().map(PathBuf::as_path).collect();
+            report.similar_image_groups =
+                find_similar_images(&image_files, threshold, self.options.image_hash_algorithm);
+        }
+
+        report.stats.files_failed = report.errors.len();
+        report.stats.duplicate_groups = report.duplicate_groups.len();
+        report.stats.duplicate_files = report.duplicate_groups.iter().map(|g| g.files.len()).sum();
+        report.stats.directory_groups = report.directory_groups.len();
+        report.stats.collisions = report.collisions.len();
+        report.stats.wasted_bytes = report.duplicate_groups.iter().map(|g| g.wasted_bytes()).sum();
+        report
+    }
+}
+
+impl Scanner {
+    /// Move the path the keep policy picks to the front, and return the rule that decided.
+    ///
+    /// Reference paths always win and the policy only chooses between them.
+    /// Paths to `avoid` are only kept when nothing else is left.
+    fn keep_first<A, M>(&self, paths: &mut Vec<PathBuf>, reference: &[PathBuf], avoid: A, roots: &[ScanRoot], mtime: M) -> String
+    where
+        A: Fn(&Path) -> bool,
+        M: Fn(&Path) -> u64,
+    {
+        let mut eligible: Vec<&PathBuf> = paths.iter().filter(|p| reference.contains(p)).collect();
+        if eligible.is_empty() {
+            eligible = paths.iter().filter(|p| !avoid(p)).collect();
+        }
+        if eligible.is_empty() {
+            eligible = paths.iter().collect();
+        }
+        let candidates: Vec<KeepCandidate> = eligible
+            .iter()
+            .map(|p| KeepCandidate {
+                path: p,
+                mtime: mtime(p),
+                root: root_of(roots, p).and_then(|r| roots.iter().position(|x| x.path == r.path)),
+            })
+            .collect();
+        let (keep, rule) = self.options.keep_policy.choose(&candidates);
+        let keep = paths.iter().position(|p| p == candidates[keep].path).unwrap();
+        let kept = paths.remove(keep);
+        paths.insert(0, kept);
+        match reference.len() {
+            0 => rule,
+            1 => "reference".to_string(),
+            _ => format!("reference, {}", rule),
+        }
+    }
+
+    /// Find duplicated directories and pick the one to keep in each group.
+    fn directory_groups(
+        &self,
+        roots: &[ScanRoot],
+        groups: &[DuplicateGroup],
+        hardlink_sets: &[HardlinkSet],
+        mode: DirMatch,
+    ) -> Vec<DirectoryGroup> {
+        // Removing a directory that holds a reference root would remove reference files
+        let holds_reference = |d: &Path| {
+            is_reference(roots, d) || roots.iter().any(|r| r.reference && r.path.starts_with(d))
+        };
+        let mut found = Vec::new();
+        for candidates in duplicate_directories(roots, groups, hardlink_sets, mode) {
+            let freeable: HashMap<PathBuf, u64> = candidates.dirs.iter().cloned().collect();
+            let mut dirs: Vec<PathBuf> = candidates.dirs.into_iter().map(|(d, _)| d).collect();
+            let reference: Vec<PathBuf> = dirs.iter().filter(|d| holds_reference(d)).cloned().collect();
+            if reference.len() == dirs.len() {
+                continue;
+            }
+            let mtime = |d: &Path| fs::metadata(d).map(|m| mtime_secs(&m)).unwrap_or(0);
+            let kept_by = self.keep_first(&mut dirs, &reference, |_| false, roots, mtime);
+            let mut group = DirectoryGroup {
+                hash: candidates.hash,
+                files: candidates.files,
+                size: candidates.size,
+                dirs,
+                kept_by,
+                reference,
+                reclaimable: 0,
+            };
+            group.reclaimable = group.removable().map(|d| freeable[d]).sum();
+            found.push(group);
+        }
+        found
+    }
+}
+
+/// Space freed by removing the copies the keep policy did not pick.
+fn reclaimable(group: &DuplicateGroup, physical: &HashMap<PathBuf, Physical>) -> u64 {
+    // A copy with a name outside this group survives the removal of its path
+    group
+        .removable()
+        .filter_map(|f| physical.get(f))
+        .filter(|p| p.nlink <= 1)
+        .map(|p| p.allocated)
+        .sum()
+}
+
+/// Total the removable copies of every group by the user owning them.
+fn owner_waste(groups: &[DuplicateGroup], physical: &HashMap<PathBuf, Physical>) -> Vec<OwnerWaste> {
+    let mut by_uid: BTreeMap<u32, (usize, u64)> = BTreeMap::new();
+    for group in groups {
+        for p in group.removable().filter_map(|f| physical.get(f)) {
+            let Some(uid) = p.uid else { continue };
+            let entry = by_uid.entry(uid).or_default();
+            entry.0 += 1;
+            if p.nlink <= 1 {
+                entry.1 += p.allocated;
+            }
+        }
+    }
+    let mut owners: Vec<OwnerWaste> = by_uid
+        .into_iter()
+        .map(|(uid, (files, bytes))| OwnerWaste { uid, user: user_name(uid), files, bytes })
+        .collect();
+    owners.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.uid.cmp(&b.uid)));
+    owners
+}
+
+/// Split each digest group into sets of byte-identical files.
+///
+/// Sets with more than one member come back as verified groups; a group that
+/// had to be split is also returned as a collision.
+fn verify_groups<F>(
+    groups: Vec<DuplicateGroup>,
+    progress: &F,
+) -> (Vec<DuplicateGroup>, Vec<HashCollision>, Vec<FileError>)
+where
+    F: Fn(ScanProgress) + Sync,
+{
+    let outcomes: Vec<_> = groups
+        .into_par_iter()
+        .map(|group| {
+            let mut identical: Vec<Vec<PathBuf>> = Vec::new();
+            let mut errors = Vec::new();
+            'files: for file in &group.files {
+                let mut i = 0;
+                while i < identical.len() {
+                    let set = &mut identical[i];
+                    match compare_files(&set[0], file) {
+                        Ok(true) => {
+                            set.push(file.clone());
+                            progress(ScanProgress::Advanced);
+                            continue 'files;
+                        }
+                        Ok(false) => i += 1,
+                        Err((CompareSide::First, e)) => {
+                            // The reference became unreadable; the next file in its set takes over
+                            let reference = set.remove(0);
+                            errors.push(FileError { file: reference, reason: e.to_string() });
+                            if set.is_empty() {
+                                identical.remove(i);
+                            }
+                        }
+                        Err((CompareSide::Second, e)) => {
+                            errors.push(FileError { file: file.clone(), reason: e.to_string() });
+                            progress(ScanProgress::Advanced);
+                            continue 'files;
+                        }
+                    }
+                }
+                identical.push(vec![file.clone()]);
+                progress(ScanProgress::Advanced);
+            }
+            (group, identical, errors)
+        })
+        .collect();
+
+    let mut verified = Vec::new();
+    let mut collisions = Vec::new();
+    let mut errors = Vec::new();
+    for (group, identical, group_errors) in outcomes {
+        if identical.len() > 1 {
+            collisions.push(HashCollision {
+                hash: group.hash.clone(),
+                size: group.size,
+                files: group.files.clone(),
+            });
+        }
+        errors.extend(group_errors);
+        verified.extend(identical.into_iter().filter(|set| set.len() > 1).map(|files| DuplicateGroup {
+            hash: group.hash.clone(),
+            size: group.size,
+            files,
+            kept_by: String::new(),
+            reference: Vec::new(),
+            hardlinks: Vec::new(),
+            reclaimable: 0,
+            verified: true,
+            in_duplicate_dir: false,
+        }));
+    }
+    (verified, collisions, errors)
+}
+
+fn find_similar_text(files: &[PathBuf], threshold: f32) -> Vec<SimilarGroup> {
+    if files.len() < 2 {
+        return Vec::new();
+    }
+    group_similar_text_files(files, threshold)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|group| {
+            let reference = &group[0];
+            let files = group
+                .iter()
+                .enumerate()
+                .map(|(i, file)| SimilarFile {
+                    file: file.clone(),
+                    similarity: if i == 0 {
+                        1.0
+                    } else {
+                        text_similarity(reference, file).unwrap_or(0.0)
+                    },
+                })
+                .collect();
+            SimilarGroup { files }
+        })
+        .collect()
+}
+
+// Optimization: Bucket by file size to avoid O(n^2) on large sets
+fn find_similar_images(files: &[&Path], threshold: f32, algorithm: ImageHashAlgorithm) -> Vec<SimilarGroup> {
+    let mut buckets: HashMap<u64, Vec<&Path>> = HashMap::new();
+    for f in files {
+        let size = fs::metadata(f).map(|m| m.len()).unwrap_or(0);
+        buckets.entry(size).or_default().push(f);
+    }
+    let mut groups = Vec::new();
+    let mut visited = HashSet::new();
+    for bucket in buckets.values() {
+        if bucket.len() < 2 {
+            continue;
+        }
+        let pairs: Vec<_> = (0..bucket.len())
+            .flat_map(|i| (i + 1..bucket.len()).map(move |j| (i, j)))
+            .collect();
+        let results: Vec<(&Path, &Path, f32)> = pairs
+            .par_iter()
+            .filter_map(|&(i, j)| {
+                let (f1, f2) = (bucket[i], bucket[j]);
+                compare_images_with_algorithm(f1, f2, algorithm)
+                    .filter(|&sim| sim >= threshold)
+                    .map(|sim| (f1, f2, sim))
+            })
+            .collect();
+        for (f1, f2, similarity) in results {
+            if visited.contains(f1) || visited.contains(f2) {
+                continue;
+            }
+            visited.insert(f1);
+            visited.insert(f2);
+            groups.push(SimilarGroup {
+                files: vec![
+                    SimilarFile { file: f1.to_path_buf(), similarity: 1.0 },
+                    SimilarFile { file: f2.to_path_buf(), similarity },
+                ],
+            });
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keep::KeepRule;
+    use crate::walker::collect_files_recursively_parallel;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_scanner_groups_duplicates() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.bin"), "same content").unwrap();
+        fs::write(dir.path().join("b.bin"), "same content").unwrap();
+        fs::write(dir.path().join("c.bin"), "other content").unwrap();
+
+        let report = Scanner::new(ScanOptions::new([dir.path()])).run();
+        assert_eq!(report.stats.files_scanned, 3);
+        assert_eq!(report.duplicate_groups.len(), 1);
+        let group = &report.duplicate_groups[0];
+        assert_eq!(group.files.len(), 2);
+        assert!(group.files[0].ends_with("a.bin"));
+        assert!(group.files[1].ends_with("b.bin"));
+        // Savings count the blocks the second copy occupies, not its length
+        let allocated = allocated_bytes(&fs::metadata(dir.path().join("b.bin")).unwrap());
+        assert_eq!(report.stats.wasted_bytes, allocated);
+        assert!(report.errors.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scanner_never_groups_unreadable_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.bin");
+        let b = dir.path().join("b.bin");
+        fs::write(&a, "secret one").unwrap();
+        fs::write(&b, "secret two").unwrap();
+        for f in [&a, &b] {
+            fs::set_permissions(f, fs::Permissions::from_mode(0o000)).unwrap();
+        }
+        // Root ignores permission bits, nothing to test there
+        if fs::read(&a).is_ok() {
+            return;
+        }
+
+        let report = Scanner::new(ScanOptions::new([dir.path()])).run();
+        assert!(report.duplicate_groups.is_empty());
+        assert_eq!(report.errors.len(), 2);
+        assert_eq!(report.stats.files_failed, 2);
+        assert!(report.errors.iter().all(|e| e.reason.contains("ermission")));
+    }
+
+    #[test]
+    fn test_scanner_overlapping_targets() {
+        let dir = tempdir().unwrap();
+        let photos = dir.path().join("photos");
+        fs::create_dir(&photos).unwrap();
+        fs::write(photos.join("only.jpg"), "the only copy").unwrap();
+
+        let report = Scanner::new(ScanOptions::new([dir.path(), photos.as_path(), photos.as_path()])).run();
+        assert_eq!(report.stats.files_scanned, 1);
+        assert!(report.duplicate_groups.is_empty());
+        assert!(report.hardlink_sets.is_empty());
+        assert_eq!(report.roots.len(), 2);
+        let file = &report.files[0];
+        assert_eq!(report.root_of(file).unwrap().given, photos);
+    }
+
+    #[test]
+    fn test_scanner_reports_missing_targets() {
+        let dir = tempdir().unwrap();
+        let missing = dir.path().join("nope");
+        let report = Scanner::new(ScanOptions::new([&missing])).run();
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].file, missing);
+    }
+
+    #[test]
+    fn test_scanner_uses_cache() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.bin"), "x").unwrap();
+        fs::write(data.join("b.bin"), "x").unwrap();
+        let options = ScanOptions::new([&data]).cache_path(Some(dir.path().join("cache.json")));
+
+        let first = Scanner::new(options.clone()).run();
+        assert_eq!(first.stats.files_hashed, 2);
+        let second = Scanner::new(options).run();
+        assert_eq!(second.stats.cache_hits, 2);
+        assert_eq!(second.duplicate_groups.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scanner_keeps_non_utf8_names() {
+        use crate::paths::LosslessPath;
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        let odd = data.join(OsStr::from_bytes(b"\xff\xfe.bin"));
+        if fs::write(&odd, "x").is_err() {
+            // Some filesystems only accept UTF-8 names
+            return;
+        }
+        fs::write(data.join("plain.bin"), "x").unwrap();
+        let options = ScanOptions::new([&data]).cache_path(Some(dir.path().join("cache.json")));
+
+        Scanner::new(options.clone()).run();
+        let report = Scanner::new(options).run();
+        assert_eq!(report.stats.cache_hits, 2);
+        assert_eq!(report.duplicate_groups.len(), 1);
+        assert!(report.duplicate_groups[0].files.contains(&odd));
+
+        let json = serde_json::to_value(&report).unwrap();
+        let files: Vec<LosslessPath> = serde_json::from_value(json["duplicate_groups"][0]["files"].clone()).unwrap();
+        assert!(files.contains(&LosslessPath(odd)));
+    }
+
+    #[test]
+    fn test_scanner_groups_across_extensions() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("archive.zip"), "same bytes").unwrap();
+        fs::write(dir.path().join("archive.zip.bak"), "same bytes").unwrap();
+        fs::write(dir.path().join("notes.txt"), "same bytes").unwrap();
+
+        // Chosen per extension, the zip would get BLAKE3 and the others SHA-256
+        let config = HashConfig::new(Security::Low, Speed::Balanced);
+        let report = Scanner::new(ScanOptions::new([dir.path()]).hash_config(config)).run();
+        assert_eq!(report.duplicate_groups.len(), 1);
+        assert_eq!(report.duplicate_groups[0].files.len(), 3);
+        assert!(report.hashes.iter().all(|h| h.algorithm == "BLAKE3"));
+        assert_eq!(report.algorithm, "BLAKE3");
+    }
+
+    #[test]
+    fn test_verify_splits_colliding_groups() {
+        let dir = tempdir().unwrap();
+        let file = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        // Pretend all four share a digest; only a/b and c/d really match
+        let files = vec![file("a", "1111"), file("b", "1111"), file("c", "2222"), file("d", "2222")];
+        let group = |hash: &str, files: Vec<PathBuf>| DuplicateGroup {
+            hash: hash.to_string(),
+            size: 4,
+            files,
+            kept_by: String::new(),
+            reference: Vec::new(),
+            hardlinks: Vec::new(),
+            reclaimable: 0,
+            verified: false,
+            in_duplicate_dir: false,
+        };
+        let (honest, group) = (group("01", files[..2].to_vec()), group("00", files.clone()));
+
+        let (verified, collisions, errors) = verify_groups(vec![group, honest], &|_| {});
+        assert!(errors.is_empty());
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].files, files);
+        assert_eq!(verified.len(), 3);
+        assert!(verified.iter().all(|g| g.verified && g.files.len() == 2));
+    }
+
+    #[test]
+    fn test_verify_blames_the_unreadable_reference() {
+        let dir = tempdir().unwrap();
+        let gone = dir.path().join("gone");
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "1111").unwrap();
+        fs::write(&b, "1111").unwrap();
+        let group = DuplicateGroup {
+            hash: "00".to_string(),
+            size: 4,
+            files: vec![gone.clone(), a.clone(), b.clone()],
+            kept_by: String::new(),
+            reference: Vec::new(),
+            hardlinks: Vec::new(),
+            reclaimable: 0,
+            verified: false,
+            in_duplicate_dir: false,
+        };
+
+        let (verified, _, errors) = verify_groups(vec![group], &|_| {});
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, gone);
+        assert_eq!(verified.len(), 1);
+        assert_eq!(verified[0].files, vec![a, b]);
+    }
+
+    #[test]
+    fn test_scanner_verify_option() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.bin"), "same content").unwrap();
+        fs::write(dir.path().join("b.bin"), "same content").unwrap();
+
+        let report = Scanner::new(ScanOptions::new([dir.path()])).run();
+        assert!(!report.duplicate_groups[0].verified);
+        let report = Scanner::new(ScanOptions::new([dir.path()]).verify(true)).run();
+        assert!(report.duplicate_groups[0].verified);
+        assert!(report.collisions.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scanner_hardlinks_are_not_duplicates() {
+        let dir = tempdir().unwrap();
+        let content = vec![3u8; 10_000];
+        fs::write(dir.path().join("a.bin"), &content).unwrap();
+        fs::hard_link(dir.path().join("a.bin"), dir.path().join("a-link.bin")).unwrap();
+        fs::write(dir.path().join("solo.bin"), "just me").unwrap();
+        fs::hard_link(dir.path().join("solo.bin"), dir.path().join("solo-link.bin")).unwrap();
+
+        let report = Scanner::new(ScanOptions::new([dir.path()])).run();
+        assert!(report.duplicate_groups.is_empty());
+        assert_eq!(report.hardlink_sets.len(), 2);
+        assert_eq!(report.stats.hardlinked_files, 2);
+        assert_eq!(report.stats.wasted_bytes, 0);
+
+        // A real copy of a: removing it frees its blocks, the linked original frees nothing
+        fs::write(dir.path().join("b.bin"), &content).unwrap();
+        let report = Scanner::new(ScanOptions::new([dir.path()])).run();
+        assert_eq!(report.duplicate_groups.len(), 1);
+        let group = &report.duplicate_groups[0];
+        assert_eq!(group.files.len(), 2);
+        assert_eq!(group.hardlinks.len(), 1);
+        assert!(group.files.iter().chain(&group.hardlinks).any(|f| f.ends_with("a-link.bin")));
+        let allocated = allocated_bytes(&fs::metadata(dir.path().join("b.bin")).unwrap());
+        assert_eq!(group.reclaimable, allocated);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scanner_same_file_through_two_paths() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("only.bin"), "the only copy").unwrap();
+        // Stands in for a bind mount: a second path to the same single-link inode
+        std::os::unix::fs::symlink(&data, dir.path().join("mirror")).unwrap();
+
+        let files = vec![data.join("only.bin"), dir.path().join("mirror/only.bin")];
+        let report = Scanner::new(ScanOptions::new(Vec::<PathBuf>::new())).scan_files(files, |_| {});
+        assert!(report.duplicate_groups.is_empty());
+        assert_eq!(report.stats.wasted_bytes, 0);
+        assert_eq!(report.hardlink_sets.len(), 1);
+        assert_eq!(report.files.len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scanner_totals_duplicates_by_owner() {
+        let dir = tempdir().unwrap();
+        let content = vec![5u8; 10_000];
+        for name in ["a.bin", "b.bin", "c.bin"] {
+            fs::write(dir.path().join(name), &content).unwrap();
+        }
+        // Handing a file to another user needs root
+        if std::os::unix::fs::chown(dir.path().join("c.bin"), Some(4242), None).is_err() {
+            return;
+        }
+
+        let report = Scanner::new(ScanOptions::new([dir.path()])).run();
+        let allocated = allocated_bytes(&fs::metadata(dir.path().join("c.bin")).unwrap());
+        // a.bin is kept, so only the two copies count
+        assert_eq!(report.owners.len(), 2);
+        assert!(report.owners.iter().all(|o| o.files == 1 && o.bytes == allocated));
+        let other = report.owners.iter().find(|o| o.uid == 4242).unwrap();
+        assert_eq!(other.label(), "uid 4242");
+        assert_eq!(report.owners.iter().map(|o| o.bytes).sum::<u64>(), report.stats.wasted_bytes);
+
+        let filter = FileFilter { owner: vec![4242], ..Default::default() };
+        let files = collect_files_recursively_parallel(dir.path(), &filter);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with("c.bin"));
+    }
+
+    #[test]
+    fn test_scanner_applies_keep_policy() {
+        let dir = tempdir().unwrap();
+        let master = dir.path().join("master");
+        fs::create_dir(&master).unwrap();
+        fs::write(dir.path().join("a.txt"), "same").unwrap();
+        fs::write(master.join("z.txt"), "same").unwrap();
+
+        let report = Scanner::new(ScanOptions::new([dir.path()])).run();
+        assert!(report.duplicate_groups[0].files[0].ends_with("a.txt"));
+        assert_eq!(report.duplicate_groups[0].kept_by, "path-order");
+
+        let policy = KeepPolicy::new(vec![KeepRule::InDir(master.clone())]);
+        let report = Scanner::new(ScanOptions::new([dir.path()]).keep_policy(policy)).run();
+        let group = &report.duplicate_groups[0];
+        assert!(group.files[0].ends_with("master/z.txt"));
+        assert!(group.kept_by.starts_with("dir:"));
+
+        // Targets given later rank lower
+        let policy = KeepPolicy::new(vec![KeepRule::RootOrder]);
+        let report = Scanner::new(ScanOptions::new([master.as_path(), dir.path()]).keep_policy(policy)).run();
+        assert!(report.duplicate_groups[0].files[0].ends_with("master/z.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scanner_savings_follow_the_keeper() {
+        let dir = tempdir().unwrap();
+        let len = 1 << 20;
+        // Same zeros, but a.bin is sparse and takes next to no blocks
+        fs::File::create(dir.path().join("a.bin")).unwrap().set_len(len).unwrap();
+        fs::write(dir.path().join("b.bin"), vec![0u8; len as usize]).unwrap();
+        let allocated = |name: &str| allocated_bytes(&fs::metadata(dir.path().join(name)).unwrap());
+        if allocated("a.bin") == allocated("b.bin") {
+            // The filesystem has no sparse files
+            return;
+        }
+
+        let report = Scanner::new(ScanOptions::new([dir.path()])).run();
+        let group = &report.duplicate_groups[0];
+        assert!(group.files[0].ends_with("a.bin"));
+        assert_eq!(group.reclaimable, allocated("b.bin"));
+
+        let policy = KeepPolicy::new(vec!["regex:b\\.bin$".parse().unwrap()]);
+        let report = Scanner::new(ScanOptions::new([dir.path()]).keep_policy(policy)).run();
+        let group = &report.duplicate_groups[0];
+        assert!(group.files[0].ends_with("b.bin"));
+        assert_eq!(group.reclaimable, allocated("a.bin"));
+    }
+
+    #[test]
+    fn test_scanner_reference_files_are_kept() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("archive");
+        let downloads = dir.path().join("downloads");
+        fs::create_dir(&archive).unwrap();
+        fs::create_dir(&downloads).unwrap();
+        fs::write(archive.join("z.jpg"), "photo").unwrap();
+        fs::write(downloads.join("a.jpg"), "photo").unwrap();
+        fs::write(downloads.join("b.jpg"), "photo").unwrap();
+        // Duplicated only inside the archive, so nothing to do about it
+        fs::write(archive.join("x.txt"), "archived twice").unwrap();
+        fs::write(archive.join("y.txt"), "archived twice").unwrap();
+
+        let policy = KeepPolicy::new(vec![KeepRule::ShortestPath]);
+        let options = ScanOptions::new([&downloads]).reference([&archive]).keep_policy(policy);
+        let report = Scanner::new(options).run();
+        assert_eq!(report.duplicate_groups.len(), 1);
+        let group = &report.duplicate_groups[0];
+        assert!(group.files[0].ends_with("archive/z.jpg"));
+        assert_eq!(group.kept_by, "reference");
+        assert_eq!(group.reference, vec![group.files[0].clone()]);
+        assert_eq!(group.removable().count(), 2);
+        assert!(group.removable().all(|f| f.starts_with(&downloads)));
+        let allocated = allocated_bytes(&fs::metadata(downloads.join("a.jpg")).unwrap());
+        assert_eq!(group.reclaimable, allocated * 2);
+
+        // A target that is also given as a reference stays read-only
+        let options = ScanOptions::new([dir.path()]).reference([&archive]);
+        let report = Scanner::new(options).run();
+        assert_eq!(report.duplicate_groups.len(), 1);
+        assert!(report.duplicate_groups[0].removable().all(|f| f.starts_with(&downloads)));
+    }
+
+    #[test]
+    fn test_scanner_collapses_duplicate_directories() {
+        let dir = tempdir().unwrap();
+        for copy in ["album", "album-copy"] {
+            fs::create_dir_all(dir.path().join(copy).join("raw")).unwrap();
+            fs::write(dir.path().join(copy).join("cover.jpg"), "cover").unwrap();
+            fs::write(dir.path().join(copy).join("raw/1.png"), "one").unwrap();
+        }
+        // A loose copy outside the directories keeps its group in the listing
+        fs::write(dir.path().join("cover-loose.jpg"), "cover").unwrap();
+
+        let report = Scanner::new(ScanOptions::new([dir.path()])).run();
+        assert!(report.directory_groups.is_empty());
+        assert!(report.duplicate_groups.iter().all(|g| !g.in_duplicate_dir));
+
+        let policy = KeepPolicy::new(vec![KeepRule::LongestPath]);
+        let options = ScanOptions::new([dir.path()]).directories(Some(DirMatch::Names)).keep_policy(policy);
+        let report = Scanner::new(options).run();
+        assert_eq!(report.directory_groups.len(), 1);
+        let group = &report.directory_groups[0];
+        assert_eq!(group.files, 2);
+        assert!(group.dirs[0].ends_with("album-copy"));
+        assert_eq!(group.removable().count(), 1);
+        let raw = report.duplicate_groups.iter().find(|g| g.size == 3).unwrap();
+        assert!(raw.in_duplicate_dir);
+        let cover = report.duplicate_groups.iter().find(|g| g.size == 5).unwrap();
+        assert!(!cover.in_duplicate_dir);
+        // Never keep the file that goes away with its directory
+        assert!(!group.removes(&cover.files[0]));
+        assert!(!group.removes(&raw.files[0]));
+    }
+
+    #[test]
+    fn test_scanner_stages_skip_work() {
+        let dir = tempdir().unwrap();
+        let big = |fill: u8, tail: u8| {
+            let mut data = vec![fill; (PARTIAL_BLOCK * 3) as usize];
+            *data.last_mut().unwrap() = tail;
+            data
+        };
+        fs::write(dir.path().join("unique.bin"), "only one of this size").unwrap();
+        fs::write(dir.path().join("a.bin"), big(0, 1)).unwrap();
+        fs::write(dir.path().join("b.bin"), big(0, 1)).unwrap();
+        // Same size as a and b but a different tail
+        fs::write(dir.path().join("c.bin"), big(0, 2)).unwrap();
+
+        let report = Scanner::new(ScanOptions::new([dir.path()])).run();
+        assert_eq!(report.stats.files_scanned, 4);
+        assert_eq!(report.stats.unique_size, 1);
+        assert_eq!(report.stats.partial_hashed, 3);
+        assert_eq!(report.stats.unique_partial, 1);
+        assert_eq!(report.stats.files_hashed, 2);
+        assert_eq!(report.hashes.len(), 2);
+        assert_eq!(report.files.len(), 4);
+        assert_eq!(report.duplicate_groups.len(), 1);
+        assert_eq!(report.duplicate_groups[0].size, PARTIAL_BLOCK * 3);
+    }
+
+    #[test]
+    fn test_scan_with_spilled_index() {
+        let dir = tempdir().unwrap();
+        for i in 0..40 {
+            let sub = dir.path().join(format!("d{}", i % 4));
+            fs::create_dir_all(&sub).unwrap();
+            fs::write(sub.join(format!("f{}.dat", i)), format!("content {}", i % 10)).unwrap();
+        }
+        fs::hard_link(dir.path().join("d0/f0.dat"), dir.path().join("d1/link.dat")).unwrap();
+        let options = ScanOptions::new([dir.path()]).text_similarity(None).image_similarity(None);
+
+        let in_memory = Scanner::new(options.clone()).run();
+        let spilled = Scanner::new(options.clone().index_memory(64)).run();
+        assert_eq!(in_memory.stats.spilled_bytes, 0);
+        assert!(spilled.stats.spilled_bytes > 0);
+        assert_eq!(spilled.files, in_memory.files);
+        assert_eq!(spilled.files.len(), 41);
+        assert_eq!(spilled.stats.hardlinked_files, 1);
+        assert_eq!(spilled.duplicate_groups.len(), 10);
+        let groups = |r: &ScanReport| r.duplicate_groups.iter().map(|g| g.files.clone()).collect::<Vec<_>>();
+        assert_eq!(groups(&spilled), groups(&in_memory));
+
+        let unlisted = Scanner::new(options.list_files(false)).run();
+        assert!(unlisted.files.is_empty() && unlisted.hashes.is_empty());
+        assert_eq!(groups(&unlisted), groups(&in_memory));
+        assert_eq!(unlisted.stats.files_scanned, 41);
+    }
+}
@@ -6,7 +6,7 @@
 //! - Perceptual hashing for image similarity
 //! - Grouping of similar files based on configurable thresholds
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use anyhow::{Result, Context};
 use image::{GenericImageView, DynamicImage, imageops::FilterType, Pixel};
 use std::collections::HashMap;
@@ -26,10 +26,7 @@ use crate::cli::ImageHashAlgorithm;
 /// # Note
 /// This implementation is not optimized for very large strings.
 pub fn levenshtein(a: &str, b: &str) -> usize {
-    let mut costs = vec![0; b.len() + 1];
-    for j in 0..=b.len() {
-        costs[j] = j;
-    }
+    let mut costs: Vec<usize> = (0..=b.len()).collect();
     for (i, ca) in a.chars().enumerate() {
         let mut last = i;
         costs[0] = i + 1;
@@ -95,27 +92,27 @@ pub fn text_similarity(path1: &Path, path2: &Path) -> Result<f32> {
 /// * `similarity_threshold` - The minimum similarity score (0.0 to 1.0) for files to be considered similar
 ///
 /// # Returns
-/// * `Result<Vec<Vec<String>>>` - A vector of groups, where each group contains paths of similar files
+/// * `Result<Vec<Vec<PathBuf>>>` - A vector of groups, where each group contains paths of similar files
 ///
 /// # Note
 /// This function uses a simple grouping algorithm that compares each file to others in its size bucket.
 pub fn group_similar_text_files(
-    files: &[String],
+    files: &[PathBuf],
     similarity_threshold: f32,
-) -> Result<Vec<Vec<String>>> {
+) -> Result<Vec<Vec<PathBuf>>> {
     if files.is_empty() {
         return Ok(Vec::new());
     }
 
     // First pass: group identical files by size and hash
-    let mut size_groups: HashMap<u64, Vec<String>> = HashMap::new();
+    let mut size_groups: HashMap<u64, Vec<PathBuf>> = HashMap::new();
     for file in files {
         if let Ok(metadata) = std::fs::metadata(file) {
             size_groups.entry(metadata.len()).or_default().push(file.clone());
         }
     }
 
-    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut groups: Vec<Vec<PathBuf>> = Vec::new();
     let mut processed = std::collections::HashSet::new();
 
     for file in files {
@@ -134,10 +131,7 @@ pub fn group_similar_text_files(
                         continue;
                     }
 
-                    if let Ok(similarity) = text_similarity(
-                        Path::new(file),
-                        Path::new(other_file),
-                    ) {
+                    if let Ok(similarity) = text_similarity(file, other_file) {
                         if similarity >= similarity_threshold {
                             current_group.push(other_file.clone());
                             processed.insert(other_file.clone());
@@ -191,14 +185,14 @@ pub fn generate_image_signature(path: &Path) -> Result<ImageSignature> {
     let ((avg_hash, phash), (dhash, color_hash)) = rayon::join(
         || {
             rayon::join(
-                || average_hash(&gray_img).unwrap_or_else(|_| 0),
-                || perceptual_hash(&gray_img).unwrap_or_else(|_| 0),
+                || average_hash(&gray_img).unwrap_or(0),
+                || perceptual_hash(&gray_img).unwrap_or(0),
             )
         },
         || {
             rayon::join(
-                || difference_hash(&gray_img).unwrap_or_else(|_| 0),
-                || color_hash(&img).unwrap_or_else(|_| 0),
+                || difference_hash(&gray_img).unwrap_or(0),
+                || color_hash(&img).unwrap_or(0),
             )
         },
     );
@@ -382,7 +376,7 @@ fn color_hash(img: &DynamicImage) -> Result<u64> {
     let img = img.resize_exact(8, 8, FilterType::Lanczos3);
     let mut hash = 0u64;
     
-    for (_i, (_x, _y, pixel)) in img.pixels().enumerate() {
+    for (_x, _y, pixel) in img.pixels() {
         let r = pixel[0] as u32;
         let g = pixel[1] as u32;
         let b = pixel[2] as u32;
@@ -497,8 +491,8 @@ pub fn hamming_similarity(a: u64, b: u64) -> f32 {
 /// # Note
 /// Different algorithms may be better suited for different types of images.
 pub fn compare_images_with_algorithm(
-    path1: &str,
-    path2: &str,
+    path1: &Path,
+    path2: &Path,
     algorithm: ImageHashAlgorithm,
 ) -> Option<f32> {
     let img1 = match image::open(path1) {
@@ -559,7 +553,7 @@ mod tests {
         let file1 = create_test_file("This is a test file with some content.");
         let file2 = create_test_file("This is a test file with some content.");
         
-        let similarity = text_similarity(&file1.path(), &file2.path()).unwrap();
+        let similarity = text_similarity(file1.path(), file2.path()).unwrap();
         assert!((similarity - 1.0).abs() < f32::EPSILON);
     }
     
@@ -568,7 +562,7 @@ mod tests {
         let file1 = create_test_file("This is a test file with some content.");
         let file2 = create_test_file("This is a completely different file with different content.");
         
-        let similarity = text_similarity(&file1.path(), &file2.path()).unwrap();
+        let similarity = text_similarity(file1.path(), file2.path()).unwrap();
         assert!(similarity < 0.5);
     }
     
@@ -577,7 +571,7 @@ mod tests {
         let file1 = create_test_file("");
         let file2 = create_test_file("");
         
-        let similarity = text_similarity(&file1.path(), &file2.path()).unwrap();
+        let similarity = text_similarity(file1.path(), file2.path()).unwrap();
         assert!((similarity - 1.0).abs() < f32::EPSILON);
     }
     
@@ -590,10 +584,10 @@ mod tests {
         let file4 = create_test_file("This is completely different content."); // Different
         
         let files = vec![
-            file1.path().to_str().unwrap().to_string(),
-            file2.path().to_str().unwrap().to_string(),
-            file3.path().to_str().unwrap().to_string(),
-            file4.path().to_str().unwrap().to_string(),
+            file1.path().to_path_buf(),
+            file2.path().to_path_buf(),
+            file3.path().to_path_buf(),
+            file4.path().to_path_buf(),
         ];
         
         // High threshold - only identical files should be grouped
@@ -652,7 +646,7 @@ mod tests {
         }
         
         // Save to temp files
-        let mut file1 = NamedTempFile::new()?;
+        let file1 = NamedTempFile::new()?;
         let path1 = file1.path().to_owned();
         img.save(&path1)?;
         
@@ -678,8 +672,8 @@ mod tests {
         }
         
         // Save to temp files
-        let mut file1 = NamedTempFile::new()?;
-        let mut file2 = NamedTempFile::new()?;
+        let file1 = NamedTempFile::new()?;
+        let file2 = NamedTempFile::new()?;
         let path1 = file1.path().to_owned();
         let path2 = file2.path().to_owned();
         
@@ -775,7 +769,7 @@ mod tests {
         }
         
         // Save to temp file
-        let mut file = NamedTempFile::new()?;
+        let file = NamedTempFile::new()?;
         let path = file.path().to_owned();
         DynamicImage::ImageRgb8(img).save(&path)?;
         
@@ -798,7 +792,7 @@ mod tests {
         for (x, y, pixel) in img2.enumerate_pixels_mut() {
             *pixel = image::Rgb([(x * 2 + y) as u8, (y * 2) as u8, x as u8]);
         }
-        let mut file2 = NamedTempFile::new()?;
+        let file2 = NamedTempFile::new()?;
         let path2 = file2.path().to_owned();
         DynamicImage::ImageRgb8(img2).save(&path2)?;
         
@@ -1,6 +1,11 @@
 use crate::safety::QuarantineManager;
 use crate::cli;
+use crate::filter::parse_size;
+use crate::hashing::{Security, Speed};
+use clap::ValueEnum;
 use inquire::{Confirm, Select, Text};
+use regex::Regex;
+use std::path::Path;
 use std::{thread, time::Duration};
 
 pub fn show_loading_screen() {
@@ -50,7 +55,7 @@ pub fn show_quarantine_menu() {
         ];
         let choice = Select::new("Quarantine Operations:", options.clone())
             .prompt()
-            .unwrap_or_else(|_| "Back");
+            .unwrap_or("Back");
         if choice == "Quarantine a File" {
             let file = Text::new("Enter the path to the file you want to quarantine:")
                 .prompt()
@@ -60,7 +65,7 @@ pub fn show_quarantine_menu() {
                 continue;
             }
             let mut qm = QuarantineManager::new().expect("Failed to create QuarantineManager");
-            match qm.quarantine_file(&file) {
+            match qm.quarantine_file(Path::new(&file)) {
                 Ok(_) => println!("File quarantined: {}", file),
                 Err(e) => println!("Failed to quarantine file: {}: {}", file, e),
             }
@@ -76,11 +81,11 @@ pub fn show_quarantine_menu() {
             let file_options: Vec<String> = files
                 .iter()
                 .map(|rec| {
-                    let quarantine_exists = std::path::Path::new(&rec.quarantine_path).exists();
+                    let quarantine_exists = rec.quarantine_path.exists();
                     if quarantine_exists {
-                        format!("{} ({} bytes)", rec.original_path, rec.file_size)
+                        format!("{} ({} bytes)", rec.original_path.display(), rec.file_size)
                     } else {
-                        format!("{} (MISSING)", rec.original_path)
+                        format!("{} (MISSING)", rec.original_path.display())
                     }
                 })
                 .collect();
@@ -96,32 +101,26 @@ pub fn show_quarantine_menu() {
             let idx = file_options.iter().position(|s| s == &file_choice);
             if let Some(i) = idx {
                 let rec = &files[i];
-                let quarantine_exists = std::path::Path::new(&rec.quarantine_path).exists();
+                let quarantine_exists = rec.quarantine_path.exists();
                 let action = Select::new(
-                    &format!("What would you like to do with {}?", rec.original_path),
+                    &format!("What would you like to do with {}?", rec.original_path.display()),
                     vec!["Restore (Rollback)", "Delete Permanently (Commit)", "Back"],
                 )
                 .prompt()
-                .unwrap_or_else(|_| "Back");
+                .unwrap_or("Back");
                 if action == "Restore (Rollback)" {
                     if quarantine_exists {
-                        let quarantine_path = &rec.quarantine_path;
                         let original_path = &rec.original_path;
-                        if let Some(parent) = std::path::Path::new(original_path).parent() {
-                            let _ = std::fs::create_dir_all(parent);
-                        }
-                        match std::fs::rename(quarantine_path, original_path) {
-                            Ok(_) => println!("Restored {}", original_path),
-                            Err(e) => println!("Failed to restore {}: {}", original_path, e),
-                        }
-                        // Remove from quarantine state
                         let mut qm2 =
                             QuarantineManager::new().expect("Failed to create QuarantineManager");
-                        let _ = qm2.remove_quarantined_file(original_path);
+                        match qm2.restore(original_path) {
+                            Ok(_) => println!("Restored {}", original_path.display()),
+                            Err(e) => println!("Failed to restore {}: {}", original_path.display(), e),
+                        }
                     } else {
                         println!(
                             "Quarantined file not found: {} (already missing)",
-                            rec.quarantine_path
+                            rec.quarantine_path.display()
                         );
                         let mut qm2 =
                             QuarantineManager::new().expect("Failed to create QuarantineManager");
@@ -130,9 +129,14 @@ pub fn show_quarantine_menu() {
                 } else if action == "Delete Permanently (Commit)" {
                     if quarantine_exists {
                         let quarantine_path = &rec.quarantine_path;
-                        match std::fs::remove_file(quarantine_path) {
-                            Ok(_) => println!("Deleted {}", quarantine_path),
-                            Err(e) => println!("Failed to delete {}: {}", quarantine_path, e),
+                        let removed = if rec.is_dir {
+                            std::fs::remove_dir_all(quarantine_path)
+                        } else {
+                            std::fs::remove_file(quarantine_path)
+                        };
+                        match removed {
+                            Ok(_) => println!("Deleted {}", quarantine_path.display()),
+                            Err(e) => println!("Failed to delete {}: {}", quarantine_path.display(), e),
                         }
                         // Remove from quarantine state
                         let mut qm2 =
@@ -141,7 +145,7 @@ pub fn show_quarantine_menu() {
                     } else {
                         println!(
                             "Quarantined file not found: {} (already missing)",
-                            rec.quarantine_path
+                            rec.quarantine_path.display()
                         );
                         let mut qm2 =
                             QuarantineManager::new().expect("Failed to create QuarantineManager");
@@ -167,19 +171,11 @@ pub fn show_quarantine_menu() {
                 .cloned()
                 .collect::<Vec<_>>()
             {
-                let quarantine_exists = std::path::Path::new(&rec.quarantine_path).exists();
+                let quarantine_exists = rec.quarantine_path.exists();
                 if quarantine_exists {
-                    let quarantine_path = &rec.quarantine_path;
-                    let original_path = &rec.original_path;
-                    if let Some(parent) = std::path::Path::new(original_path).parent() {
-                        let _ = std::fs::create_dir_all(parent);
-                    }
-                    match std::fs::rename(quarantine_path, original_path) {
-                        Ok(_) => {
-                            let _ = qm.remove_quarantined_file(original_path);
-                            restored += 1;
-                        }
-                        Err(e) => println!("Failed to restore {}: {}", original_path, e),
+                    match qm.restore(&rec.original_path) {
+                        Ok(_) => restored += 1,
+                        Err(e) => println!("Failed to restore {}: {}", rec.original_path.display(), e),
                     }
                 } else {
                     missing.push(rec.original_path.clone());
@@ -196,7 +192,7 @@ pub fn show_quarantine_menu() {
                     missing.len()
                 );
                 for m in missing {
-                    println!("  {}", m);
+                    println!("  {}", m.display());
                 }
             }
         } else if choice == "Back" {
@@ -235,13 +231,13 @@ pub fn scan_menu() {
             .prompt()
             .unwrap_or_default();
     // Min size
-    let min_size = Text::new("Minimum file size in bytes (leave blank for none):")
+    let min_size = Text::new("Minimum file size, e.g. 500K or 10M (leave blank for none):")
         .with_placeholder("0")
         .prompt()
         .unwrap_or_default();
     // Max size
-    let max_size = Text::new("Maximum file size in bytes (leave blank for none):")
-        .with_placeholder("1000000")
+    let max_size = Text::new("Maximum file size, e.g. 500K or 10M (leave blank for none):")
+        .with_placeholder("1G")
         .prompt()
         .unwrap_or_default();
     // Min age
@@ -296,10 +292,10 @@ pub fn scan_menu() {
         println!("File types: {}", filetypes);
     }
     if !min_size.is_empty() {
-        println!("Min size: {} bytes", min_size);
+        println!("Min size: {}", min_size);
     }
     if !max_size.is_empty() {
-        println!("Max size: {} bytes", max_size);
+        println!("Max size: {}", max_size);
     }
     if !min_age.is_empty() {
         println!("Min age: {} days", min_age);
@@ -331,63 +327,85 @@ pub fn scan_menu() {
         println!("Scan cancelled.");
         return;
     }
-    // Build CLI args
-    let mut args = vec!["dedcore".to_string()];
-    args.push(path.clone());
-    args.push(format!("--security={}", security));
-    args.push(format!("--speed={}", speed));
-    if !filetypes.is_empty() {
-        args.push(format!("--filetypes={}", filetypes));
-    }
-    if !min_size.is_empty() {
-        args.push(format!("--min-size={}", min_size));
-    }
-    if !max_size.is_empty() {
-        args.push(format!("--max-size={}", max_size));
+    // Build typed scan options
+    let mut args = cli::ScanArgs::new(vec![path.clone().into()]);
+    args.hash.security = Security::from_str(&security, true).unwrap_or(Security::High);
+    args.hash.speed = Speed::from_str(&speed, true).unwrap_or(Speed::Balanced);
+    if !filetypes.trim().is_empty() {
+        args.filters.filetypes = Some(
+            filetypes
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+        );
     }
-    if !min_age.is_empty() {
-        args.push(format!("--min-age={}", min_age));
-    }
-    if !max_age.is_empty() {
-        args.push(format!("--max-age={}", max_age));
+    args.filters.min_size = parse_optional_size(&min_size, "minimum size");
+    args.filters.max_size = parse_optional_size(&max_size, "maximum size");
+    args.filters.min_age = parse_optional(&min_age, "minimum age");
+    args.filters.max_age = parse_optional(&max_age, "maximum age");
+    if !regex.trim().is_empty() {
+        match Regex::new(regex.trim()) {
+            Ok(re) => args.filters.regex = Some(re),
+            Err(e) => println!("Invalid regex pattern ({}). Ignoring regex filter.", e),
+        }
     }
-    if !regex.is_empty() {
-        args.push(format!("--regex={}", regex));
+    args.dry = dry_run;
+    args.quarantine_all_dupes = quarantine_all;
+    if let Some(val) = parse_optional::<f32>(&similarity_threshold, "similarity threshold") {
+        if (0.0..=1.0).contains(&val) {
+            args.similarity_threshold = val;
+        } else {
+            println!(
+                "Invalid similarity threshold, must be between 0.0 and 1.0. Using default (0.8)."
+            );
+        }
     }
-    if dry_run {
-        args.push("--dry".to_string());
+    if let Some(val) = parse_optional::<f32>(&image_similarity_threshold, "image similarity threshold") {
+        if (0.0..=1.0).contains(&val) {
+            args.image_similarity_threshold = val;
+        } else {
+            println!(
+                "Invalid image similarity threshold, must be between 0.0 and 1.0. Using default (0.9)."
+            );
+        }
     }
-    if quarantine_all {
-        args.push("--quarantine-all-dupes".to_string());
+    args.append_report = Confirm::new(
+        "Would you like to append this scan to dedcore_report.json and dedcore_report.html?",
+    )
+    .with_default(true)
+    .prompt()
+    .unwrap_or(false);
+    cli::run_scan(&args);
+}
+
+/// Parse an optional numeric answer, warning (and ignoring it) when it is not a number.
+fn parse_optional_size(input: &str, what: &str) -> Option<u64> {
+    let input = input.trim();
+    if input.is_empty() {
+        return None;
     }
-    if !similarity_threshold.trim().is_empty() {
-        if let Ok(val) = similarity_threshold.trim().parse::<f64>() {
-            if val >= 0.0 && val <= 1.0 {
-                args.push(format!("--similarity-threshold={}", val));
-            } else {
-                println!(
-                    "Invalid similarity threshold, must be between 0.0 and 1.0. Using default (0.8)."
-                );
-            }
-        } else {
-            println!("Invalid similarity threshold input. Using default (0.8).");
+    match parse_size(input) {
+        Ok(size) => Some(size),
+        Err(e) => {
+            println!("Invalid {}: {}. Ignoring it.", what, e);
+            None
         }
     }
-    if !image_similarity_threshold.trim().is_empty() {
-        if let Ok(val) = image_similarity_threshold.trim().parse::<f64>() {
-            if val >= 0.0 && val <= 1.0 {
-                args.push(format!("--image-similarity-threshold={}", val));
-            } else {
-                println!(
-                    "Invalid image similarity threshold, must be between 0.0 and 1.0. Using default (0.9)."
-                );
-            }
-        } else {
-            println!("Invalid image similarity threshold input. Using default (0.9).");
+}
+
+fn parse_optional<T: std::str::FromStr>(input: &str, what: &str) -> Option<T> {
+    let input = input.trim();
+    if input.is_empty() {
+        return None;
+    }
+    match input.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            println!("Invalid {} '{}'. Ignoring it.", what, input);
+            None
         }
     }
-    // Call CLI logic with constructed args
-    cli::run_with_args(args);
 }
 
 pub fn main_menu() -> String {
@@ -0,0 +1,1001 @@
+//! Directory walking and file filtering.
+//!
+//! Both collectors share [`FileFilter`], so the sequential and the parallel
+//! walk always agree on which files make it into a scan. Directories excluded
+//! by ignore files or `--exclude` are pruned before anything inside them is
+//! looked at.
+//!
+//! Symbolic links are followed according to [`FollowSymlinks`]. A file reached
+//! through a link is listed under its canonical path, so it appears once
+//! however many links lead to it and is never paired with itself as a
+//! duplicate. A linked directory is only walked if no directory holding it
+//! has been, which also ends every link loop.
+//!
+//! Walks never descend into pseudo filesystems such as `/proc`, and with
+//! [`FileFilter::one_file_system`] not into any other filesystem either.
+
+use crate::filter::{parse_size, FilterExpr, PathGlob};
+use crate::ignore::{IgnoreOptions, IgnoreStack};
+use crate::mounts::{in_pseudo_filesystem, is_pseudo, mounted_at, SkippedMount};
+use crate::owner::{owner_ids, parse_group, parse_user, Identity};
+use clap::{Args, ValueEnum};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+/// Filters applied while walking directory targets.
+#[derive(Args, Debug, Clone, Default)]
+pub struct FileFilter {
+    /// Only include files with these extensions (comma-separated, e.g. txt,jpg)
+    #[arg(short, long, value_name = "EXTS", value_delimiter = ',')]
+    pub filetypes: Option<Vec<String>>,
+
+    /// Minimum file size, in bytes or with a unit (e.g. 10M, 1.5G, 500KB)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Maximum file size, in bytes or with a unit
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Minimum file age in days
+    #[arg(long, value_name = "DAYS")]
+    pub min_age: Option<u64>,
+
+    /// Maximum file age in days
+    #[arg(long, value_name = "DAYS")]
+    pub max_age: Option<u64>,
+
+    /// Only include files whose path matches this regular expression
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    pub regex: Option<Regex>,
+
+    /// Only include files owned by this user (name or uid); repeat to allow several
+    #[arg(long, value_name = "USER", value_parser = parse_user)]
+    pub owner: Vec<u32>,
+
+    /// Only include files whose group is this one (name or gid); repeat to allow several
+    #[arg(long, value_name = "GROUP", value_parser = parse_group)]
+    pub group: Vec<u32>,
+
+    /// Skip files the current user is not allowed to delete
+    #[arg(long)]
+    pub deletable: bool,
+
+    /// Only include files matching this glob; repeat to allow several
+    /// (a glob without '/' matches the file name, e.g. '*.jpg')
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<PathGlob>,
+
+    /// Skip files and directories matching this glob; repeat to skip several
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<PathGlob>,
+
+    /// Only include files matching this expression,
+    /// e.g. 'size > 10M and ext in (jpg,png) and not path ~ "/thumbs/" and mtime < 2w'
+    #[arg(long = "filter", value_name = "EXPR")]
+    pub expr: Option<FilterExpr>,
+
+    /// Don't read .dedcoreignore files or the global ignore file
+    #[arg(long)]
+    pub no_ignore: bool,
+
+    /// Also honor .gitignore files, and skip .git directories
+    #[arg(long)]
+    pub gitignore: bool,
+
+    /// Global ignore file applied to every target [default: ~/.dedcore/ignore]
+    #[arg(long, value_name = "PATH")]
+    pub ignore_file: Option<PathBuf>,
+
+    /// Which symbolic links to follow
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t)]
+    pub follow_symlinks: FollowSymlinks,
+
+    /// Don't descend into directories on other filesystems than their target
+    #[arg(long)]
+    pub one_file_system: bool,
+}
+
+/// When the walk follows symbolic links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum FollowSymlinks {
+    /// Never, not even links given as targets
+    Never,
+    /// Only links given as targets
+    #[default]
+    Cmdline,
+    /// Every link, scanning each file and directory once
+    Always,
+}
+
+/// A symbolic link met during the walk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Symlink {
+    #[serde(with = "crate::paths")]
+    pub path: PathBuf,
+    /// Where the link points, as written in it.
+    #[serde(with = "crate::paths")]
+    pub target: PathBuf,
+    pub status: LinkStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkStatus {
+    /// Left alone under the symlink policy.
+    NotFollowed,
+    /// Followed; what it points to is scanned under its own path.
+    Followed,
+    /// Points to a directory holding the link, so following it would never end.
+    Loop,
+    /// Points to nothing.
+    Broken,
+}
+
+impl fmt::Display for LinkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkStatus::NotFollowed => write!(f, "not followed"),
+            LinkStatus::Followed => write!(f, "followed"),
+            LinkStatus::Loop => write!(f, "loop"),
+            LinkStatus::Broken => write!(f, "broken"),
+        }
+    }
+}
+
+/// Everything a walk of the scan roots found.
+#[derive(Debug, Clone, Default)]
+pub struct Walk {
+    /// Regular files that passed the filters, sorted, each once.
+    pub files: Vec<PathBuf>,
+    /// Ignore files whose rules applied.
+    pub ignore_sources: Vec<PathBuf>,
+    /// Symbolic links met on the way, followed or not.
+    pub symlinks: Vec<Symlink>,
+    /// Mount points the walk stayed out of.
+    pub skipped_mounts: Vec<SkippedMount>,
+}
+
+fn parse_regex(s: &str) -> Result<Regex, String> {
+    Regex::new(s).map_err(|e| format!("invalid regex pattern: {}", e))
+}
+
+/// The current time in seconds since the epoch, as [`FileFilter::matches`] takes it.
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Device and inode number identifying the physical file behind a path.
+///
+/// `None` on platforms without inodes, where every path counts as its own file.
+#[cfg(unix)]
+pub fn file_id(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn file_id(_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Number of hardlinks to the file, 1 where the platform can't tell.
+#[cfg(unix)]
+pub fn link_count(meta: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink()
+}
+
+#[cfg(not(unix))]
+pub fn link_count(_meta: &fs::Metadata) -> u64 {
+    1
+}
+
+/// Bytes the file actually occupies on disk, which is less than its length for sparse files.
+#[cfg(unix)]
+pub fn allocated_bytes(meta: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.blocks() * 512
+}
+
+#[cfg(not(unix))]
+pub fn allocated_bytes(meta: &fs::Metadata) -> u64 {
+    meta.len()
+}
+
+impl FileFilter {
+    /// Where ignore rules come from under these settings.
+    pub fn ignore_options(&self) -> IgnoreOptions {
+        let global = if self.no_ignore {
+            None
+        } else {
+            self.ignore_file.clone().or_else(|| {
+                dirs::home_dir().map(|home| home.join(".dedcore").join("ignore")).filter(|p| p.is_file())
+            })
+        };
+        IgnoreOptions { dedcoreignore: !self.no_ignore, gitignore: self.gitignore, global }
+    }
+
+    /// Whether an `--exclude` glob matches `path`.
+    pub fn excludes(&self, path: &Path) -> bool {
+        self.exclude.iter().any(|g| g.matches(path))
+    }
+
+    /// Whether a regular file with the given metadata passes every filter.
+    ///
+    /// `now` is the scan start time in seconds since the epoch, used for the age filters.
+    pub fn matches(&self, path: &Path, meta: &fs::Metadata, now: u64) -> bool {
+        // Check file size
+        let len = meta.len();
+        if self.min_size.is_some_and(|min| len < min) || self.max_size.is_some_and(|max| len > max) {
+            return false;
+        }
+
+        // Check file age; files without a usable mtime are never filtered out by age
+        if self.min_age.is_some() || self.max_age.is_some() {
+            if let Some(modified) = meta.modified().ok().and_then(|m| m.duration_since(UNIX_EPOCH).ok()) {
+                let age_days = now.saturating_sub(modified.as_secs()) / 86400;
+                if self.min_age.is_some_and(|min| age_days < min)
+                    || self.max_age.is_some_and(|max| age_days > max)
+                {
+                    return false;
+                }
+            }
+        }
+
+        // Check regex filter
+        if let Some(ref re) = self.regex {
+            if !re.is_match(&path.to_string_lossy()) {
+                return false;
+            }
+        }
+
+        // Check globs and the filter expression
+        if !self.include.is_empty() && !self.include.iter().any(|g| g.matches(path)) {
+            return false;
+        }
+        if self.excludes(path) {
+            return false;
+        }
+        if self.expr.as_ref().is_some_and(|expr| !expr.matches(path, meta, now)) {
+            return false;
+        }
+
+        // Check ownership; without it, owner filters match nothing
+        if !self.owner.is_empty() && !owner_ids(meta).is_some_and(|(uid, _)| self.owner.contains(&uid)) {
+            return false;
+        }
+        if !self.group.is_empty() && !owner_ids(meta).is_some_and(|(_, gid)| self.group.contains(&gid)) {
+            return false;
+        }
+        if self.deletable && Identity::current().is_some_and(|me| !me.can_delete(path, meta)) {
+            return false;
+        }
+
+        // Check file extension
+        if let Some(ref exts) = self.filetypes {
+            if exts.is_empty() {
+                return true;
+            }
+            return match path.extension().and_then(|s| s.to_str()) {
+                Some(ext) => exts.iter().any(|x| x.eq_ignore_ascii_case(ext)),
+                None => false,
+            };
+        }
+        true
+    }
+}
+
+/// What a walk has covered so far, so that nothing is emitted twice.
+#[derive(Debug, Default)]
+struct Visited {
+    /// Directories walked, or about to be.
+    dirs: Vec<PathBuf>,
+    /// Files emitted other than by walking a directory in `dirs`: reached
+    /// through a link, or given as a target.
+    files: HashSet<PathBuf>,
+    /// Directory targets inside another target that no walk has reached yet.
+    pending: Vec<PathBuf>,
+}
+
+impl Visited {
+    fn from_dir(dir: &Path) -> Self {
+        Self { dirs: vec![dir.to_path_buf()], ..Default::default() }
+    }
+}
+
+/// Hand the regular files under `root` to `emit`, skipping whatever ignore files or `--exclude` exclude.
+///
+/// The ignore files that were read and the links met are added to `found`.
+/// Directories reached through links are walked in turn unless they lie in a
+/// directory already visited, and no visited directory is walked twice. Files
+/// reached through links are only emitted when they lie outside every visited
+/// directory, so each file is emitted at most once.
+fn walk_files(root: &Path, filter: &FileFilter, found: &mut Walk, visited: &mut Visited, emit: &mut dyn FnMut(PathBuf)) {
+    let options = filter.ignore_options();
+    let root_dev = device(root);
+    // Whatever a link leads to must be somewhere the walk itself could go
+    let reachable = |target: &Path| {
+        !(in_pseudo_filesystem(target) || filter.one_file_system && device(target) != root_dev)
+    };
+    let mut queue = vec![root.to_path_buf()];
+    while let Some(dir) = queue.pop() {
+        let mut ignores = IgnoreStack::new(&options, &dir);
+        let mut walk = WalkDir::new(&dir).into_iter();
+        while let Some(entry) = walk.next() {
+            let Ok(entry) = entry else { continue };
+            if entry.depth() > 0 && entry.file_type().is_dir() {
+                let fstype = mounted_at(entry.path());
+                let foreign = filter.one_file_system && device(entry.path()) != root_dev;
+                if foreign || fstype.is_some_and(is_pseudo) {
+                    found.skipped_mounts.push(SkippedMount {
+                        path: entry.path().to_path_buf(),
+                        fstype: fstype.map(str::to_string),
+                    });
+                    walk.skip_current_dir();
+                    continue;
+                }
+            }
+            // Already walked, or about to be, from a link
+            let repeated = entry.depth() > 0 && entry.file_type().is_dir() && visited.dirs.iter().any(|d| d == entry.path());
+            let excluded = entry.depth() > 0 && entry.file_type().is_dir() && filter.excludes(entry.path());
+            if repeated || excluded || !ignores.admit(&entry) {
+                if entry.file_type().is_dir() {
+                    walk.skip_current_dir();
+                }
+                continue;
+            }
+            if entry.file_type().is_dir() && !visited.pending.is_empty() {
+                // Covered by this walk, the target needs no walk of its own
+                visited.pending.retain(|p| p != entry.path());
+            }
+            if entry.file_type().is_file() {
+                if visited.files.is_empty() || !visited.files.contains(entry.path()) {
+                    emit(entry.into_path());
+                }
+            } else if entry.file_type().is_symlink() {
+                let Some(target) = follow_link(entry.path(), filter.follow_symlinks, found) else { continue };
+                if !reachable(&target) {
+                    continue;
+                }
+                if target.is_file() {
+                    if !visited.dirs.iter().any(|d| target.starts_with(d)) && visited.files.insert(target.clone()) {
+                        emit(target);
+                    }
+                } else if target.is_dir() && !visited.dirs.iter().any(|d| target.starts_with(d)) {
+                    visited.dirs.push(target.clone());
+                    queue.push(target);
+                }
+            }
+        }
+        found.ignore_sources.extend(ignores.sources);
+    }
+}
+
+/// Device holding `path`, without following a final symlink.
+fn device(path: &Path) -> Option<u64> {
+    fs::symlink_metadata(path).ok().and_then(|meta| file_id(&meta)).map(|(dev, _)| dev)
+}
+
+/// Record the link at `path` in `found`, and return the canonical path of what
+/// it points to if the walk should go there.
+fn follow_link(path: &Path, policy: FollowSymlinks, found: &mut Walk) -> Option<PathBuf> {
+    let target = fs::read_link(path).unwrap_or_default();
+    let (status, resolved) = if policy != FollowSymlinks::Always {
+        (LinkStatus::NotFollowed, None)
+    } else {
+        match fs::canonicalize(path) {
+            Err(_) => (LinkStatus::Broken, None),
+            Ok(resolved) if resolved.is_dir() && path.starts_with(&resolved) => (LinkStatus::Loop, None),
+            Ok(resolved) => (LinkStatus::Followed, Some(resolved)),
+        }
+    };
+    found.symlinks.push(Symlink { path: path.to_path_buf(), target, status });
+    resolved
+}
+
+/// Collects files recursively on the current thread.
+pub fn collect_files_recursively_with_filter<P: AsRef<Path>>(
+    root: P,
+    filter: &FileFilter,
+) -> Vec<PathBuf> {
+    let now = now_secs();
+    let root = root.as_ref();
+    let mut files = Vec::new();
+    walk_files(root, filter, &mut Walk::default(), &mut Visited::from_dir(root), &mut |f| files.push(f));
+    files.retain(|path| {
+        fs::metadata(path)
+            .map(|meta| filter.matches(path, &meta, now))
+            .unwrap_or(false)
+    });
+    files.sort();
+    files
+}
+
+/// Collects files recursively in parallel using Rayon for improved performance.
+/// This function provides the same filtering capabilities as `collect_files_recursively_with_filter`
+/// but checks metadata for several entries at once.
+pub fn collect_files_recursively_parallel<P: AsRef<Path> + Send + Sync>(
+    root: P,
+    filter: &FileFilter,
+) -> Vec<PathBuf> {
+    let root = root.as_ref();
+    let now = now_secs();
+
+    // Create a channel for collecting results
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    let mut files = Vec::new();
+    walk_files(root, filter, &mut Walk::default(), &mut Visited::from_dir(root), &mut |f| files.push(f));
+
+    // Check metadata in parallel
+    files
+        .into_par_iter()
+        .for_each_with(tx, |sender, path| {
+            let meta = match fs::metadata(&path) {
+                Ok(m) => m,
+                Err(_) => return,
+            };
+            if filter.matches(&path, &meta, now) {
+                let _ = sender.send(path);
+            }
+        });
+
+    // Collect all results
+    rx.into_iter().collect()
+}
+
+/// A scan target with symlinks and relative components resolved.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanRoot {
+    /// The target as it was given.
+    #[serde(with = "crate::paths")]
+    pub given: PathBuf,
+    /// Canonical absolute path.
+    #[serde(with = "crate::paths")]
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Files under a reference root are compared against but never modified.
+    pub reference: bool,
+}
+
+/// Canonicalize targets, dropping any that resolve to a root seen before.
+///
+/// Order is preserved, so earlier targets stay first. Targets that don't exist
+/// are returned as the second element.
+pub fn resolve_targets(targets: &[PathBuf]) -> (Vec<ScanRoot>, Vec<PathBuf>) {
+    let mut roots: Vec<ScanRoot> = Vec::new();
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    let mut missing = Vec::new();
+    for target in targets {
+        match fs::canonicalize(target) {
+            Ok(path) if path.is_file() || path.is_dir() => {
+                if seen.insert(path.clone()) {
+                    roots.push(ScanRoot {
+                        given: target.clone(),
+                        is_dir: path.is_dir(),
+                        path,
+                        reference: false,
+                    });
+                }
+            }
+            _ => missing.push(target.clone()),
+        }
+    }
+    (roots, missing)
+}
+
+/// Resolve scan targets followed by reference targets.
+///
+/// A reference that resolves to an already listed target marks that target as
+/// a reference instead of being added twice.
+pub fn resolve_with_reference(targets: &[PathBuf], reference: &[PathBuf]) -> (Vec<ScanRoot>, Vec<PathBuf>) {
+    let (mut roots, mut missing) = resolve_targets(targets);
+    let (extra, extra_missing) = resolve_targets(reference);
+    for mut root in extra {
+        match roots.iter_mut().find(|r| r.path == root.path) {
+            Some(existing) => existing.reference = true,
+            None => {
+                root.reference = true;
+                roots.push(root);
+            }
+        }
+    }
+    missing.extend(extra_missing);
+    (roots, missing)
+}
+
+/// Whether `file` lies under any reference root, however the roots nest.
+pub fn is_reference(roots: &[ScanRoot], file: &Path) -> bool {
+    roots.iter().any(|r| r.reference && file.starts_with(&r.path))
+}
+
+/// The most specific root containing `file`, if any.
+pub fn root_of<'a>(roots: &'a [ScanRoot], file: &Path) -> Option<&'a ScanRoot> {
+    roots
+        .iter()
+        .filter(|r| file.starts_with(&r.path))
+        .max_by_key(|r| r.path.components().count())
+}
+
+/// Walk resolved roots, visiting each file once even when roots overlap.
+///
+/// A root inside another directory root is covered by walking the outer one,
+/// or walked on its own when that walk prunes it.
+pub fn collect_roots(roots: &[ScanRoot], filter: &FileFilter) -> Vec<PathBuf> {
+    walk_roots(roots, filter).files
+}
+
+/// Like [`collect_roots`], also returning the ignore files that applied and the links met.
+pub fn walk_roots(roots: &[ScanRoot], filter: &FileFilter) -> Walk {
+    let mut found_files = Vec::new();
+    let mut found = stream_roots(roots, filter, |f| found_files.push(f));
+    let now = now_secs();
+    // Check metadata in parallel
+    let mut files: Vec<PathBuf> = found_files
+        .into_par_iter()
+        .filter(|f| f.admit(filter, now).is_some())
+        .map(|f| f.path)
+        .collect();
+    files.sort();
+    found.files = files;
+    found
+}
+
+/// A file reached by [`stream_roots`], before any filter was applied.
+#[derive(Debug, Clone)]
+pub struct FoundFile {
+    pub path: PathBuf,
+    /// Given as a target itself, so taken as it is rather than filtered.
+    pub named: bool,
+}
+
+impl FoundFile {
+    /// The file's metadata, if it belongs in the scan: it was named, or it passes `filter`.
+    pub fn admit(&self, filter: &FileFilter, now: u64) -> Option<fs::Metadata> {
+        let meta = fs::metadata(&self.path).ok()?;
+        (self.named || filter.matches(&self.path, &meta, now)).then_some(meta)
+    }
+}
+
+/// Walk resolved roots like [`walk_roots`], but hand each file to `emit` as
+/// soon as it is found instead of collecting them, so that they can be
+/// processed while the walk goes on.
+///
+/// Files come in walk order, each once, and unfiltered so that the caller can
+/// check them with [`FoundFile::admit`] on other threads. The returned walk
+/// has no files.
+pub fn stream_roots<F: FnMut(FoundFile)>(roots: &[ScanRoot], filter: &FileFilter, mut emit: F) -> Walk {
+    let mut found = Walk::default();
+    let unfollowed = |root: &ScanRoot| filter.follow_symlinks == FollowSymlinks::Never && root.given.is_symlink();
+    let dirs: Vec<&ScanRoot> = roots.iter().filter(|r| r.is_dir && !unfollowed(r)).collect();
+    let nested =
+        |root: &ScanRoot| dirs.iter().any(|other| other.path != root.path && root.path.starts_with(&other.path));
+    // Links into any root lead nowhere new
+    let mut visited = Visited {
+        dirs: dirs.iter().filter(|r| !nested(r)).map(|r| r.path.clone()).collect(),
+        files: roots.iter().filter(|r| !r.is_dir && !unfollowed(r)).map(|r| r.path.clone()).collect(),
+        pending: dirs.iter().filter(|r| nested(r)).map(|r| r.path.clone()).collect(),
+    };
+    for root in roots {
+        if unfollowed(root) {
+            follow_link(&root.given, FollowSymlinks::Never, &mut found);
+        } else if !root.is_dir {
+            emit(FoundFile { path: root.path.clone(), named: true });
+        } else if !nested(root) {
+            walk_files(&root.path, filter, &mut found, &mut visited, &mut |path| {
+                emit(FoundFile { path, named: false })
+            });
+        }
+    }
+    // A target inside another one is normally walked with it, unless that walk
+    // pruned it: an ignore rule, --exclude or a mount boundary. Walk those on
+    // their own, outermost first, since they may hold each other.
+    let mut pruned = visited.pending.clone();
+    pruned.sort_by_key(|dir| dir.components().count());
+    for dir in pruned {
+        if !visited.pending.contains(&dir) {
+            continue;
+        }
+        visited.pending.retain(|p| *p != dir);
+        visited.dirs.push(dir.clone());
+        walk_files(&dir, filter, &mut found, &mut visited, &mut |path| {
+            emit(FoundFile { path, named: false })
+        });
+        found.skipped_mounts.retain(|m| m.path != dir);
+    }
+    found.ignore_sources.sort();
+    found.ignore_sources.dedup();
+    found.symlinks.sort_by(|a, b| a.path.cmp(&b.path));
+    found.symlinks.dedup();
+    found.skipped_mounts.sort_by(|a, b| a.path.cmp(&b.path));
+    found.skipped_mounts.dedup();
+    found
+}
+
+/// Read a list of paths, one per line or separated by NUL bytes, as printed by
+/// `find`, `fd` or `git ls-files` (with `-print0`, `-0` or `-z` respectively).
+///
+/// Empty entries are skipped. On Unix paths are taken byte for byte, so names
+/// that aren't valid UTF-8 survive.
+pub fn read_path_list<R: Read>(mut reader: R, nul: bool) -> io::Result<Vec<PathBuf>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let separator = if nul { b'\0' } else { b'\n' };
+    Ok(bytes
+        .split(|&b| b == separator)
+        .map(|entry| if nul { entry } else { entry.strip_suffix(b"\r").unwrap_or(entry) })
+        .filter(|entry| !entry.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Sort listed paths into directories to walk and files to take as they are.
+///
+/// Unlike files given as targets, listed files go through the filters, since a
+/// list usually stands in for a walk. They come back canonical, each once.
+/// Links are handled as for targets. Paths that don't exist are returned last.
+pub fn resolve_listed(paths: Vec<PathBuf>, filter: &FileFilter) -> (Vec<PathBuf>, Vec<PathBuf>, Vec<PathBuf>) {
+    let now = now_secs();
+    let resolved: Vec<(PathBuf, Option<PathBuf>)> = paths
+        .into_par_iter()
+        .map(|path| {
+            let resolved = if filter.follow_symlinks == FollowSymlinks::Never && path.is_symlink() {
+                None
+            } else {
+                fs::canonicalize(&path).ok()
+            };
+            (path, resolved)
+        })
+        .collect();
+    let (mut dirs, mut files, mut missing) = (Vec::new(), Vec::new(), Vec::new());
+    for (path, resolved) in resolved {
+        match resolved.map(|r| (fs::metadata(&r), r)) {
+            Some((Ok(meta), dir)) if meta.is_dir() => dirs.push(dir),
+            Some((Ok(meta), file)) if meta.is_file() => {
+                if filter.matches(&file, &meta, now) {
+                    files.push(file);
+                }
+            }
+            // Links left alone are not missing
+            None if path.is_symlink() && filter.follow_symlinks == FollowSymlinks::Never => {}
+            _ => missing.push(path),
+        }
+    }
+    files.sort();
+    files.dedup();
+    (dirs, files, missing)
+}
+
+/// Expand file and directory targets into the list of files to process.
+///
+/// Files named directly are taken as-is; directories are walked and filtered.
+/// Paths are canonical and every file appears once, however the targets overlap.
+/// Targets that are neither are returned as the second element.
+pub fn collect_targets(targets: &[PathBuf], filter: &FileFilter) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let (roots, missing) = resolve_targets(targets);
+    (collect_roots(&roots, filter), missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ignore::IGNORE_FILE;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_collectors_agree() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("a.txt"), "hello").unwrap();
+        fs::write(dir.path().join("sub/b.TXT"), "hello world").unwrap();
+        fs::write(dir.path().join("sub/c.jpg"), "x").unwrap();
+        fs::write(dir.path().join("noext"), "hello").unwrap();
+
+        let filter = FileFilter {
+            filetypes: Some(vec!["txt".to_string()]),
+            min_size: Some(2),
+            ..Default::default()
+        };
+        let mut seq = collect_files_recursively_with_filter(dir.path(), &filter);
+        let mut par = collect_files_recursively_parallel(dir.path(), &filter);
+        seq.sort();
+        par.sort();
+        assert_eq!(seq, par);
+        assert_eq!(seq.len(), 2);
+        assert!(seq.iter().all(|f| f.extension().is_some_and(|e| e.eq_ignore_ascii_case("txt"))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_overlapping_targets_collect_once() {
+        let dir = tempdir().unwrap();
+        let photos = dir.path().join("photos");
+        fs::create_dir(&photos).unwrap();
+        fs::write(photos.join("cat.jpg"), "meow").unwrap();
+        fs::write(dir.path().join("notes.txt"), "hi").unwrap();
+        std::os::unix::fs::symlink(&photos, dir.path().join("pics")).unwrap();
+
+        let targets = vec![
+            photos.clone(),
+            dir.path().to_path_buf(),
+            dir.path().join("pics"),
+            dir.path().join("photos/../notes.txt"),
+        ];
+        let (roots, missing) = resolve_targets(&targets);
+        assert!(missing.is_empty());
+        assert_eq!(roots.len(), 3);
+
+        let files = collect_roots(&roots, &FileFilter::default());
+        assert_eq!(files.len(), 2);
+        let cat = files.iter().find(|f| f.ends_with("cat.jpg")).unwrap();
+        assert_eq!(root_of(&roots, cat).unwrap().given, photos);
+        let notes = files.iter().find(|f| f.ends_with("notes.txt")).unwrap();
+        assert!(root_of(&roots, notes).unwrap().given.ends_with("notes.txt"));
+    }
+
+    #[test]
+    fn test_ignore_files_prune_the_walk() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        for d in ["node_modules/pkg", "src", ".git/objects", "logs"] {
+            fs::create_dir_all(root.join(d)).unwrap();
+        }
+        for f in ["node_modules/pkg/index.js", "src/main.rs", ".git/objects/ab", "logs/a.log", "logs/keep.log"] {
+            fs::write(root.join(f), "x").unwrap();
+        }
+        fs::write(root.join(IGNORE_FILE), "node_modules/\n").unwrap();
+        fs::write(root.join("logs/.gitignore"), "*.log\n!keep.log\n").unwrap();
+        let roots = resolve_targets(&[root.to_path_buf()]).0;
+        let names = |files: &[PathBuf]| -> Vec<String> {
+            let mut names: Vec<String> =
+                files.iter().map(|f| f.strip_prefix(&roots[0].path).unwrap().to_string_lossy().to_string()).collect();
+            names.retain(|n| !n.ends_with("ignore"));
+            names
+        };
+
+        let filter = FileFilter { ignore_file: Some(root.join("absent")), ..Default::default() };
+        let Walk { files, ignore_sources: sources, .. } = walk_roots(&roots, &filter);
+        assert_eq!(names(&files), [".git/objects/ab", "logs/a.log", "logs/keep.log", "src/main.rs"]);
+        assert_eq!(sources, [roots[0].path.join(IGNORE_FILE)]);
+
+        let filter = FileFilter { gitignore: true, ignore_file: Some(root.join("absent")), ..Default::default() };
+        let Walk { files, ignore_sources: sources, .. } = walk_roots(&roots, &filter);
+        assert_eq!(names(&files), ["logs/keep.log", "src/main.rs"]);
+        assert_eq!(sources.len(), 2);
+        let mut seq = collect_files_recursively_with_filter(&roots[0].path, &filter);
+        seq.sort();
+        assert_eq!(seq, files);
+
+        let filter = FileFilter { no_ignore: true, ..Default::default() };
+        assert_eq!(collect_roots(&roots, &filter).len(), 7);
+    }
+
+    #[test]
+    fn test_filter_size_and_regex() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("small.dat"), "ab").unwrap();
+        fs::write(dir.path().join("big_backup.dat"), "abcdefgh").unwrap();
+
+        let filter = FileFilter {
+            max_size: Some(4),
+            ..Default::default()
+        };
+        let files = collect_files_recursively_parallel(dir.path(), &filter);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with("small.dat"));
+
+        let filter = FileFilter {
+            regex: Some(Regex::new("backup").unwrap()),
+            ..Default::default()
+        };
+        let files = collect_files_recursively_parallel(dir.path(), &filter);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with("big_backup.dat"));
+    }
+
+    #[test]
+    fn test_globs_and_expression() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("photos/thumbs")).unwrap();
+        fs::write(dir.path().join("photos/a.jpg"), "large photo").unwrap();
+        fs::write(dir.path().join("photos/b.png"), "x").unwrap();
+        fs::write(dir.path().join("photos/notes.txt"), "large notes").unwrap();
+        fs::write(dir.path().join("photos/thumbs/a.jpg"), "large thumb").unwrap();
+
+        let filter = FileFilter {
+            include: vec!["*.jpg".parse().unwrap(), "*.png".parse().unwrap()],
+            exclude: vec!["thumbs".parse().unwrap()],
+            ..Default::default()
+        };
+        let mut files = collect_files_recursively_with_filter(dir.path(), &filter);
+        files.sort();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].ends_with("photos/a.jpg") && files[1].ends_with("photos/b.png"));
+        let mut par = collect_files_recursively_parallel(dir.path(), &filter);
+        par.sort();
+        assert_eq!(par, files);
+
+        let filter = FileFilter {
+            expr: Some(r#"size > 4 and ext in (jpg,png) and not path ~ "/thumbs/" and mtime < 2w"#.parse().unwrap()),
+            ..Default::default()
+        };
+        let files = collect_files_recursively_parallel(dir.path(), &filter);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with("photos/a.jpg"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks_policies() {
+        use crate::scanner::{ScanOptions, Scanner};
+        use std::os::unix::fs::symlink;
+        let dir = tempdir().unwrap();
+        let (root, outside) = (dir.path().join("root"), dir.path().join("outside"));
+        fs::create_dir_all(root.join("real")).unwrap();
+        fs::create_dir(&outside).unwrap();
+        fs::write(root.join("real/a.txt"), "same").unwrap();
+        fs::write(root.join("real/b.txt"), "other").unwrap();
+        fs::write(outside.join("c.txt"), "outside").unwrap();
+        symlink(root.join("real/a.txt"), root.join("a-link.txt")).unwrap();
+        symlink("real", root.join("real-link")).unwrap();
+        symlink(".", root.join("real/up")).unwrap();
+        symlink(&outside, root.join("outside-link")).unwrap();
+        symlink("nowhere", root.join("broken")).unwrap();
+        let (roots, _) = resolve_targets(std::slice::from_ref(&root));
+        let statuses = |walk: &Walk| -> Vec<(String, LinkStatus)> {
+            walk.symlinks
+                .iter()
+                .map(|l| (l.path.strip_prefix(&roots[0].path).unwrap().display().to_string(), l.status))
+                .collect()
+        };
+
+        let walk = walk_roots(&roots, &FileFilter::default());
+        assert_eq!(walk.files.len(), 2);
+        assert_eq!(walk.symlinks.len(), 5);
+        assert!(walk.symlinks.iter().all(|l| l.status == LinkStatus::NotFollowed));
+
+        let always = FileFilter { follow_symlinks: FollowSymlinks::Always, ..Default::default() };
+        let walk = walk_roots(&roots, &always);
+        // Everything once, under its real path
+        assert_eq!(walk.files.len(), 3);
+        assert!(walk.files.iter().any(|f| f.ends_with("outside/c.txt")));
+        assert_eq!(
+            statuses(&walk),
+            [
+                ("a-link.txt".to_string(), LinkStatus::Followed),
+                ("broken".to_string(), LinkStatus::Broken),
+                ("outside-link".to_string(), LinkStatus::Followed),
+                ("real/up".to_string(), LinkStatus::Loop),
+                ("real-link".to_string(), LinkStatus::Followed),
+            ]
+        );
+        let mut seq = collect_files_recursively_with_filter(&roots[0].path, &always);
+        seq.sort();
+        assert_eq!(seq, walk.files);
+
+        // A link and its target are one file, not a duplicate pair
+        let report = Scanner::new(ScanOptions::new([&root]).filter(always)).run();
+        assert!(report.duplicate_groups.is_empty());
+        assert_eq!(report.symlinks.len(), 5);
+
+        // Links given as targets
+        let (link_root, _) = resolve_targets(&[root.join("outside-link")]);
+        assert_eq!(walk_roots(&link_root, &FileFilter::default()).files.len(), 1);
+        let never = FileFilter { follow_symlinks: FollowSymlinks::Never, ..Default::default() };
+        let walk = walk_roots(&link_root, &never);
+        assert!(walk.files.is_empty());
+        assert_eq!(walk.symlinks[0].status, LinkStatus::NotFollowed);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_one_file_system() {
+        use std::os::unix::fs::symlink;
+        let dir = tempdir().unwrap();
+        // Needs a second filesystem to reach; /dev/shm usually is one
+        let Ok(other) = tempfile::tempdir_in("/dev/shm") else { return };
+        if device(other.path()) == device(dir.path()) {
+            return;
+        }
+        fs::write(dir.path().join("here.txt"), "here").unwrap();
+        fs::write(other.path().join("there.txt"), "there").unwrap();
+        symlink(other.path(), dir.path().join("elsewhere")).unwrap();
+        let (roots, _) = resolve_targets(&[dir.path().to_path_buf()]);
+
+        let filter = FileFilter { follow_symlinks: FollowSymlinks::Always, ..Default::default() };
+        assert_eq!(walk_roots(&roots, &filter).files.len(), 2);
+        let filter = FileFilter { one_file_system: true, ..filter };
+        let walk = walk_roots(&roots, &filter);
+        assert_eq!(walk.files.len(), 1);
+        assert!(walk.files[0].ends_with("here.txt"));
+        // Given as a target, the other filesystem is scanned all the same
+        let (roots, _) = resolve_targets(&[other.path().to_path_buf()]);
+        assert_eq!(walk_roots(&roots, &filter).files.len(), 1);
+    }
+
+    #[test]
+    fn test_nested_targets_pruned_by_the_outer_walk() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        for d in ["build/keep", "cache/keep", "src"] {
+            fs::create_dir_all(root.join(d)).unwrap();
+        }
+        for f in ["top.txt", "build/keep/a.txt", "build/skip.txt", "cache/keep/b.txt", "src/c.txt"] {
+            fs::write(root.join(f), "x").unwrap();
+        }
+        fs::write(root.join(IGNORE_FILE), "cache/\n").unwrap();
+        let filter = FileFilter {
+            exclude: vec!["build".parse().unwrap()],
+            ignore_file: Some(root.join("absent")),
+            ..Default::default()
+        };
+        let names = |targets: &[PathBuf]| -> Vec<String> {
+            let (roots, _) = resolve_targets(targets);
+            let base = roots[0].path.clone();
+            let mut names: Vec<String> = walk_roots(&roots, &filter)
+                .files
+                .iter()
+                .map(|f| f.strip_prefix(&base).unwrap().to_string_lossy().to_string())
+                .filter(|n| !n.ends_with("ignore"))
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(names(&[root.to_path_buf()]), ["src/c.txt", "top.txt"]);
+        // Named targets are scanned even where the outer walk doesn't go, and only once where it does
+        let targets = [root.to_path_buf(), root.join("build/keep"), root.join("cache/keep"), root.join("src")];
+        assert_eq!(names(&targets), ["build/keep/a.txt", "cache/keep/b.txt", "src/c.txt", "top.txt"]);
+        let targets = [root.to_path_buf(), root.join("cache"), root.join("cache/keep")];
+        assert_eq!(names(&targets), ["cache/keep/b.txt", "src/c.txt", "top.txt"]);
+    }
+
+    #[test]
+    fn test_path_lists() {
+        let list = read_path_list(&b"a.txt\r\nsub dir/b.txt\n\n"[..], false).unwrap();
+        assert_eq!(list, [PathBuf::from("a.txt"), PathBuf::from("sub dir/b.txt")]);
+        let list = read_path_list(&b"line\nbreak\0two\0"[..], true).unwrap();
+        assert_eq!(list, [PathBuf::from("line\nbreak"), PathBuf::from("two")]);
+
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("a.txt"), "a").unwrap();
+        fs::write(dir.path().join("big.txt"), "too big").unwrap();
+        let listed = vec![
+            dir.path().join("a.txt"),
+            dir.path().join("sub/../a.txt"),
+            dir.path().join("big.txt"),
+            dir.path().join("sub"),
+            dir.path().join("gone.txt"),
+        ];
+        let filter = FileFilter { max_size: Some(3), ..Default::default() };
+        let (dirs, files, missing) = resolve_listed(listed, &filter);
+        assert_eq!(dirs, [fs::canonicalize(dir.path().join("sub")).unwrap()]);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with("a.txt"));
+        assert_eq!(missing, [dir.path().join("gone.txt")]);
+    }
+}